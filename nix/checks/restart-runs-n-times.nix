@@ -29,7 +29,15 @@ in
 runCommandLocal "restart-runs-n-times" { } ''
   set -euo pipefail
 
-  ${lib.getExe nimiWrapper} &> nimi_logs.txt
+  status=0
+  ${lib.getExe nimiWrapper} &> nimi_logs.txt || status=$?
+
+  if [ "$status" != "1" ]; then
+    echo "Expected nimi to exit with the service's exit code 1, got $status"
+    echo "nimi logs: $(cat nimi_logs.txt)"
+    exit 1
+  fi
+
   occurred="$(grep -c "goodbye world" nimi_logs.txt)"
 
   if [ "$occurred" != "${toString (N + 1)}" ]; then
@@ -11,6 +11,7 @@ let
   inherit
     (portable-lib.configure {
       serviceManagerPkgs = pkgs;
+      extraRootModules = lib.filesystem.listFilesRecursive ./service;
     })
     serviceSubmodule
     ;
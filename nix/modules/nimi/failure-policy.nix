@@ -0,0 +1,35 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "nimi";
+
+  options.settings.failurePolicy = mkOption {
+    description = ''
+      What happens to the remaining services when one of them fails.
+
+      - `stop-all`: stop every service and exit as soon as one fails.
+      - `ignore`: log the failure and keep the other services running.
+      - `restart-only`: also feed errors like a failure to spawn into the
+        failing service's restart policy, without tearing down the other
+        services.
+
+      A service exiting with a failing status is always handled by its
+      restart policy; the failure policy applies to errors which the restart
+      policy can't handle, such as a missing binary or broken config data,
+      and to services whose restart policy has given up.
+
+      Once every service has stopped, nimi exits with the exit code of the
+      first service that failed, or `128 + signal` if it was killed by a
+      signal.
+    '';
+    type = types.enum [
+      "stop-all"
+      "ignore"
+      "restart-only"
+    ];
+    default = "stop-all";
+    example = lib.literalExpression ''"ignore"'';
+  };
+}
@@ -0,0 +1,26 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "nimi";
+
+  options.settings.forwardSignals = mkOption {
+    description = ''
+      Signals which nimi relays to the process group of every running
+      service when it receives them, e.g. for services reopening their log
+      files on `SIGUSR1`.
+
+      `SIGTERM` and `SIGINT` shut nimi down and are forwarded as part of the
+      shutdown, so they can't be listed here. `SIGHUP` reloads the config
+      when nimi was started with a config file; when listed here it is also
+      forwarded to the services.
+    '';
+    type = types.listOf types.str;
+    default = [
+      "SIGUSR1"
+      "SIGUSR2"
+    ];
+    example = lib.literalExpression ''[ "SIGHUP" "SIGUSR1" "SIGWINCH" ]'';
+  };
+}
@@ -42,6 +42,69 @@ in
           type = types.str;
           default = "nimi_logs";
         };
+        combineOutput = mkOption {
+          description = ''
+            If the stderr of each service should be redirected into its stdout.
+
+            Both streams then share a single pipe, so lines show up in the
+            exact order the service wrote them. The distinction between the two
+            streams is lost, and every line is logged at the stdout level.
+          '';
+          type = types.bool;
+          default = false;
+          example = true;
+        };
+        maxLineLength = mkOption {
+          description = ''
+            Maximum length in bytes of a single line of service output.
+
+            Longer lines are cut off at this length and end in a marker
+            telling how many bytes were dropped, the rest of the line is
+            skipped. Keeps a service writing huge lines, or binary data
+            without any newlines, from making nimi buffer without bound.
+          '';
+          type = types.ints.positive;
+          default = 64 * 1024;
+          example = 1024 * 1024;
+        };
+        rotation = mkOption {
+          description = ''
+            Size based rotation of the log files, covering both the files
+            under `logsDir` and each service's `logFile`.
+
+            Once a file grows past `maxSize` bytes it is renamed to
+            `<file>.1`, older rotations shift to `<file>.2` and so on, and a
+            fresh file is started. Only the newest `maxFiles` rotations are
+            kept.
+          '';
+          example = lib.literalExpression ''
+            {
+              maxSize = 10 * 1024 * 1024;
+              maxFiles = 3;
+            }
+          '';
+          type = types.submodule {
+            options = {
+              maxSize = mkOption {
+                description = ''
+                  Size in bytes after which a log file gets rotated.
+
+                  Log files are never rotated when `null`.
+                '';
+                type = types.nullOr types.ints.positive;
+                default = null;
+              };
+              maxFiles = mkOption {
+                description = ''
+                  Number of rotated log files to keep next to the current one.
+                '';
+                type = types.ints.unsigned;
+                default = 5;
+              };
+            };
+          };
+          default = { };
+        };
       };
     };
     default = { };
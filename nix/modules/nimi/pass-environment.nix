@@ -0,0 +1,25 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "nimi";
+
+  options.settings.passEnvironment = mkOption {
+    description = ''
+      Variables from the environment of nimi to pass on to every service.
+
+      When set, the environment of every service is cleared and only these
+      variables, those in the service's own `process.passEnvironment` and
+      `PATH` are passed on. `process.environment` is applied on top and takes
+      precedence over passed on variables.
+
+      When `null`, services without a `process.passEnvironment` inherit the
+      whole environment of nimi, including any secrets in it. Listing only
+      the variables services need keeps those away from them.
+    '';
+    example = lib.literalExpression ''[ "TZ" "LANG" "TERM" ]'';
+    type = types.nullOr (types.listOf types.str);
+    default = null;
+  };
+}
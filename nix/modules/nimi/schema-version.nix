@@ -0,0 +1,20 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "nimi";
+
+  options.schemaVersion = mkOption {
+    description = ''
+      Version of the config format generated by these modules.
+
+      nimi migrates configs for older versions and refuses configs for
+      versions newer than it supports, instead of misreading them.
+    '';
+    type = types.ints.unsigned;
+    default = 1;
+    readOnly = true;
+    internal = true;
+  };
+}
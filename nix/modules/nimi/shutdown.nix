@@ -0,0 +1,52 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "nimi";
+
+  options.settings.shutdown = mkOption {
+    description = ''
+      Shutdown behavior for the nimi process manager.
+
+      When nimi receives `SIGINT` or `SIGTERM` it forwards the signal to every
+      running service, along with every process the service forked, and waits
+      for them to exit. Processes that do not exit within the configured
+      timeout are forcefully stopped with `SIGKILL`.
+    '';
+    example = lib.literalExpression ''
+      {
+        timeout = 30000;
+      }
+    '';
+    type = types.submodule {
+      options = {
+        timeout = mkOption {
+          description = ''
+            Time in milliseconds to wait for a service to exit gracefully
+            before escalating to `SIGKILL`.
+
+            The default matches the grace period used by `docker stop`.
+          '';
+          type = types.ints.positive;
+          default = 10000;
+          example = lib.literalExpression "30000";
+        };
+        killTimeout = mkOption {
+          description = ''
+            Time in milliseconds to wait for a service to exit after
+            `SIGKILL`.
+
+            A process stuck in uninterruptible sleep ignores even `SIGKILL`
+            until its syscall returns. Once this elapses nimi logs a warning
+            and carries on shutting down without it.
+          '';
+          type = types.ints.positive;
+          default = 5000;
+          example = lib.literalExpression "1000";
+        };
+      };
+    };
+    default = { };
+  };
+}
@@ -51,6 +51,70 @@ in
             )
           '';
         };
+
+        commands = mkOption {
+          description = ''
+            Commands to run to completion one after another at startup, after
+            `runOnStartup`.
+
+            Each command is given as an argv list. Later commands and the
+            services only start once the previous command exited
+            successfully, so an init sequence can be split into separate
+            steps.
+          '';
+          type = types.listOf (types.nonEmptyListOf types.str);
+          default = [ ];
+          example = lib.literalExpression ''
+            [
+              [ "mkdir" "-p" "/var/lib/my-service" ]
+              [ (lib.getExe pkgs.my-service) "migrate" ]
+            ]
+          '';
+        };
+
+        environment = mkOption {
+          description = ''
+            Environment variables to set for `runOnStartup` and `commands`,
+            on top of the environment nimi itself runs in.
+          '';
+          type = types.attrsOf types.str;
+          default = { };
+          example = lib.literalExpression ''
+            {
+              DATA_DIR = "/var/lib/my-service";
+            }
+          '';
+        };
+
+        ignoreFailure = mkOption {
+          description = ''
+            Whether to carry on when a startup command exits with a non-zero
+            status.
+
+            By default a failing startup command aborts nimi before the
+            remaining commands and any service are started. Enable this for best-effort tasks, such as warming a
+            cache, whose failure is logged but shouldn't keep the services
+            from running.
+          '';
+          type = types.bool;
+          default = false;
+          example = true;
+        };
+
+        timeout = mkOption {
+          description = ''
+            Time in milliseconds every service gets to be running and pass
+            its readiness check, counted from when the services are started.
+
+            When it passes, nimi shuts all services down and exits with an
+            error, so a container that doesn't come up fails instead of
+            hanging around. Services that stop before becoming ready don't
+            hold it up. Set to `null` to wait for as long as it takes.
+          '';
+          type = types.nullOr types.ints.positive;
+          default = null;
+          example = lib.literalExpression "60000";
+        };
       };
     };
     default = { };
@@ -0,0 +1,202 @@
+# Type of a restart policy, shared by `settings.restart` and the `restart` of
+# each service
+{ lib }:
+let
+  inherit (lib) mkOption types;
+in
+types.submodule {
+  options = {
+    mode = mkOption {
+      description = ''
+        Selects the restart behavior.
+
+        - `never`: do not restart failed services.
+        - `up-to-count`: restart up to `count` times on failure, then stop.
+        - `on-failure`: always restart on failure, but not after a
+          successful exit.
+        - `always`: always restart, even after a successful exit.
+
+        Choose `up-to-count` if you want a service to get a few retries
+        during a transient failure, but still fail fast when the issue is
+        persistent. Choose `on-failure` when continuous availability matters
+        more than surfacing the failure, and `always` for services that
+        should be kept running even when they finish on their own.
+
+        `always` used to only restart failed services, like `on-failure`
+        does now. The default changed from `always` to `on-failure` to keep
+        that behavior, configs which set `always` explicitly now also get
+        their services restarted after a successful exit.
+      '';
+      default = "on-failure";
+      example = lib.literalExpression ''"up-to-count"'';
+      type = types.enum [
+        "never"
+        "up-to-count"
+        "on-failure"
+        "always"
+      ];
+    };
+    time = mkOption {
+      description = ''
+        Delay between restarts in milliseconds.
+
+        Increase this value for crash loops to give the system time to
+        recover resources or for dependent services to come back.
+      '';
+      type = types.ints.positive;
+      default = 1000;
+      example = lib.literalExpression "250";
+    };
+    count = mkOption {
+      description = ''
+        Maximum number of restart attempts when `mode` is `up-to-count`.
+
+        Once this limit is reached, the service is left stopped until you
+        intervene or change the configuration.
+      '';
+      type = types.ints.positive;
+      default = 5;
+      example = lib.literalExpression "3";
+    };
+    backoff = mkOption {
+      description = ''
+        Exponential backoff for the delay between restarts.
+
+        The delay before restart attempt `n` (starting at zero) is
+        `min(initial * multiplier^n, max)` milliseconds, replacing `time`.
+        This keeps a service that crashes instantly from hammering the
+        system at a fixed cadence.
+
+        Set to `null` to always wait `time` milliseconds.
+      '';
+      example = lib.literalExpression ''
+        {
+          initial = 100;
+          max = 30000;
+          multiplier = 2.0;
+          jitter = 0.1;
+        }
+      '';
+      type = types.nullOr (
+        types.submodule {
+          options = {
+            initial = mkOption {
+              description = "Delay in milliseconds before the first restart.";
+              type = types.ints.positive;
+              default = 100;
+            };
+            max = mkOption {
+              description = "Upper bound in milliseconds for the delay.";
+              type = types.ints.positive;
+              default = 30000;
+            };
+            multiplier = mkOption {
+              description = "Factor the delay grows by after every restart.";
+              type = types.addCheck types.number (x: x >= 1);
+              default = 2.0;
+            };
+            jitter = mkOption {
+              description = ''
+                Fraction (between 0 and 1) of the delay to randomly
+                subtract, spreading out restarts of services that crashed
+                at the same time.
+              '';
+              type = types.addCheck types.number (x: x >= 0 && x <= 1);
+              default = 0.0;
+            };
+          };
+        }
+      );
+      default = null;
+    };
+    successThreshold = mkOption {
+      description = ''
+        Time in milliseconds a service has to stay up before its restart
+        count and backoff are reset.
+
+        Without this, a service restarting rarely over a long time will
+        eventually exhaust its `up-to-count` budget even though it is
+        basically healthy.
+
+        Set to `null` to never reset the restart count.
+      '';
+      type = types.nullOr types.ints.positive;
+      default = null;
+      example = lib.literalExpression "60000";
+    };
+    startLimit = mkOption {
+      description = ''
+        Gives up on a service which was started more than `burst` times
+        within `interval` milliseconds, like `StartLimitIntervalSec` and
+        `StartLimitBurst` of systemd.
+
+        Backoff and `on-failure` or `always` restarts make a crash loop
+        slow, but never end it. With a start limit a service which keeps
+        crashing is eventually marked failed and handled by
+        `settings.failurePolicy`. The limit applies on top of `count` in
+        `up-to-count` mode, whichever is hit first stops the restarts.
+
+        Set to `null` to not limit how often services are started.
+      '';
+      example = lib.literalExpression ''
+        {
+          interval = 10000;
+          burst = 5;
+        }
+      '';
+      type = types.nullOr (
+        types.submodule {
+          options = {
+            interval = mkOption {
+              description = "Window in milliseconds in which starts are counted.";
+              type = types.ints.positive;
+              default = 10000;
+            };
+            burst = mkOption {
+              description = "Maximum number of starts within `interval`.";
+              type = types.ints.positive;
+              default = 5;
+            };
+          };
+        }
+      );
+      default = null;
+    };
+    crashLoop = mkOption {
+      description = ''
+        Reports a service which was restarted `restarts` times within
+        `interval` milliseconds as crash-looping. nimi logs a single error
+        when a service starts crash-looping, and while it waits to restart
+        the service shows up in the `crash-looping` state instead of
+        `restarting` on `/status` and the control socket.
+
+        Unlike `startLimit` this never stops the restarts.
+
+        Set to `null` to never report crash loops.
+      '';
+      example = lib.literalExpression ''
+        {
+          interval = 60000;
+          restarts = 5;
+        }
+      '';
+      type = types.nullOr (
+        types.submodule {
+          options = {
+            interval = mkOption {
+              description = "Window in milliseconds in which restarts are counted.";
+              type = types.ints.positive;
+              default = 60000;
+            };
+            restarts = mkOption {
+              description = "Number of restarts within `interval` which count as a crash loop.";
+              type = types.ints.positive;
+              default = 5;
+            };
+          };
+        }
+      );
+      default = null;
+    };
+  };
+}
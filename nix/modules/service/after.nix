@@ -0,0 +1,22 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.after = mkOption {
+    description = ''
+      Names of other services which have to be started before this one.
+
+      The service is held back until each listed service is ready, meaning
+      its `readiness` check passed or, without one, its process has been
+      spawned. nimi
+      refuses to start if a listed service doesn't exist or the dependencies
+      form a cycle.
+    '';
+    example = lib.literalExpression ''[ "database-migration" ]'';
+    type = types.listOf types.str;
+    default = [ ];
+  };
+}
@@ -0,0 +1,63 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.cgroup = mkOption {
+    description = ''
+      cgroup v2 to place the service process and its hooks into.
+
+      nimi creates the cgroup when the service is first started and keeps it
+      across restarts. Once the service stopped, nimi removes the directories
+      it created, cgroups which already existed are left alone. To enable the
+      controllers needed for the limits in its own cgroup, nimi first moves
+      itself into a `nimi-supervisor` cgroup below it.
+
+      If cgroup v2 isn't mounted or nimi may
+      not create the cgroup or enable the controllers needed for its
+      limits, a warning is logged and the service runs in the cgroup of nimi.
+    '';
+    example = lib.literalExpression ''
+      {
+        memoryMax = 512 * 1024 * 1024;
+        cpuQuota = 50;
+      }
+    '';
+    type = types.nullOr (
+      types.submodule {
+        options = {
+          path = mkOption {
+            description = ''
+              Path of the cgroup relative to the root of the cgroup v2
+              hierarchy. Defaults to `nimi/<service name>`.
+            '';
+            example = "background/indexer";
+            type = types.nullOr types.str;
+            default = null;
+          };
+
+          memoryMax = mkOption {
+            description = ''
+              Memory limit of the cgroup in bytes, written to `memory.max`.
+            '';
+            type = types.nullOr types.ints.positive;
+            default = null;
+          };
+
+          cpuQuota = mkOption {
+            description = ''
+              CPU time the cgroup may use in percent of a single CPU, written
+              to `cpu.max`. `200` allows two full CPUs.
+            '';
+            example = 50;
+            type = types.nullOr types.ints.positive;
+            default = null;
+          };
+        };
+      }
+    );
+    default = null;
+  };
+}
@@ -0,0 +1,26 @@
+{ lib, config, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.command = mkOption {
+    description = ''
+      Shell command to run the service with, as an alternative to
+      `process.argv`.
+
+      The command is run through `sh -c`, which makes it possible to express
+      pipelines or other shell syntax in a single string. `sh` is looked up on
+      the `PATH` of nimi. Only one of `process.argv` and `process.command` may
+      be set.
+    '';
+    example = lib.literalExpression ''"my-server --port 8080 | tee /tmp/server.log"'';
+    type = types.nullOr types.str;
+    default = null;
+  };
+
+  config = lib.mkIf (config.process.command != null) {
+    process.argv = lib.mkDefault [ ];
+  };
+}
@@ -0,0 +1,26 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.configData = mkOption {
+    type = types.attrsOf (
+      types.submodule {
+        options.mode = mkOption {
+          description = ''
+            Permissions of the config file, as an octal string.
+
+            A file with a mode is always copied into the config directory
+            instead of being symlinked, since a symlink has no permissions of
+            its own. Files written from `text` default to `0644`.
+          '';
+          example = "0600";
+          type = types.nullOr (types.strMatching "[0-7]{1,4}");
+          default = null;
+        };
+      }
+    );
+  };
+}
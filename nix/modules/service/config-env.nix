@@ -0,0 +1,38 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.configEnvVar = mkOption {
+    description = ''
+      Environment variable the generated config directory is passed to the
+      service in, for programs which don't read their config from
+      `XDG_CONFIG_HOME`.
+    '';
+    example = "XDG_CONFIG_DIRS";
+    type = types.strMatching "[A-Za-z_][A-Za-z0-9_]*";
+    default = "XDG_CONFIG_HOME";
+  };
+
+  options.configEnvMode = mkOption {
+    description = ''
+      How the config directory is added to `configEnvVar`.
+
+      - `"set"`: the variable holds just the config directory. Setting the
+        variable in `process.environment` takes precedence.
+      - `"prepend"`: the config directory is put in front of the `:`
+        separated list the variable already holds, from
+        `process.environment` or the environment of nimi. Suits search
+        paths like `XDG_CONFIG_DIRS`, keeping the host's entries as a
+        fallback.
+    '';
+    example = "prepend";
+    type = types.enum [
+      "set"
+      "prepend"
+    ];
+    default = "set";
+  };
+}
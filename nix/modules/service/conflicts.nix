@@ -0,0 +1,23 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.conflicts = mkOption {
+    description = ''
+      Names of other services which must never run at the same time as
+      this one.
+
+      Before the service starts, any listed service which hasn't stopped
+      yet is stopped and not started again until requested through the
+      control socket. Listed services which are ordered `after` this one and
+      still wait for it are left alone. The two services may not both be
+      `longrunning`, otherwise nimi refuses to start.
+    '';
+    example = lib.literalExpression ''[ "server" ]'';
+    type = types.listOf types.str;
+    default = [ ];
+  };
+}
@@ -0,0 +1,48 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process = {
+    user = mkOption {
+      description = ''
+        User to run the service process as, given as a name or numeric uid.
+
+        Supplementary groups are initialized from the user's group
+        memberships. Changing user requires nimi to run as root.
+
+        Set to `null` to run as the same user as nimi.
+      '';
+      example = lib.literalExpression ''"nobody"'';
+      type = types.nullOr (types.either types.str types.ints.unsigned);
+      default = null;
+    };
+    group = mkOption {
+      description = ''
+        Group to run the service process as, given as a name or numeric gid.
+
+        Defaults to the primary group of `process.user` when set to `null`.
+      '';
+      example = lib.literalExpression ''"nogroup"'';
+      type = types.nullOr (types.either types.str types.ints.unsigned);
+      default = null;
+    };
+    supplementaryGroups = mkOption {
+      description = ''
+        Additional groups to make the service process a member of, given as
+        names or numeric gids.
+
+        They are added to the groups of `process.user`, or to those of nimi
+        when no user is set. Requires nimi to run as root.
+      '';
+      example = [
+        "docker"
+        "video"
+      ];
+      type = types.listOf (types.either types.str types.ints.unsigned);
+      default = [ ];
+    };
+  };
+}
@@ -0,0 +1,19 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.enable = mkOption {
+    description = ''
+      Whether nimi runs the service.
+
+      A disabled service is left out entirely. Services listing it in
+      `after` start without waiting for it.
+    '';
+    example = false;
+    type = types.bool;
+    default = true;
+  };
+}
@@ -0,0 +1,36 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.environmentFiles = mkOption {
+    description = ''
+      Files to read environment variables for the service process from.
+
+      Each file holds `KEY=VALUE` lines, values may be single or double
+      quoted and lines starting with `#` are comments. The files are read
+      in order every time the process is spawned, so they can hold secrets
+      which only exist at runtime. Variables in `process.environment` take
+      precedence over the files.
+    '';
+    example = [ "/run/secrets/my-service.env" ];
+    type = types.listOf types.str;
+    default = [ ];
+  };
+
+  options.process.environmentSecretFiles = mkOption {
+    description = ''
+      Environment files like `process.environmentFiles` whose values are
+      secrets, read after them.
+
+      Only the paths end up in the generated config. The values are never
+      printed by nimi, `--dry-run` only lists the files and parse errors
+      don't quote their contents.
+    '';
+    example = [ "/run/secrets/my-service-token.env" ];
+    type = types.listOf types.str;
+    default = [ ];
+  };
+}
@@ -0,0 +1,25 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.environment = mkOption {
+    description = ''
+      Environment variables to set for the service process.
+
+      These are applied after nimi's own variables, so setting
+      `XDG_CONFIG_HOME` here overrides the generated config directory,
+      unless `configEnvMode` prepends to it.
+    '';
+    example = lib.literalExpression ''
+      {
+        LOG_LEVEL = "info";
+        LISTEN_ADDR = "0.0.0.0:8080";
+      }
+    '';
+    type = types.attrsOf types.str;
+    default = { };
+  };
+}
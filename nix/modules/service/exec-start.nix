@@ -0,0 +1,38 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+
+  hooksType = types.listOf (types.nonEmptyListOf types.str);
+in
+{
+  _class = "service";
+
+  options.execStartPre = mkOption {
+    description = ''
+      Commands to run to completion, one after another, before the service
+      process is started.
+
+      Each command is given as an argv list and runs with the same
+      environment, config directory, working directory, credentials and
+      limits as the service itself, with its output logged under the service
+      name. If any of them fails the service isn't started and the failure is
+      handled by the restart policy.
+    '';
+    example = lib.literalExpression ''[ [ "my-server" "migrate" ] ]'';
+    type = hooksType;
+    default = [ ];
+  };
+
+  options.execStartPost = mkOption {
+    description = ''
+      Commands to run one after another once the service process has been
+      started.
+
+      They run in the same environment as `execStartPre`. If any of them
+      fails the service process is stopped again.
+    '';
+    example = lib.literalExpression ''[ [ "curl" "-X" "POST" "http://localhost:8080/warmup" ] ]'';
+    type = hooksType;
+    default = [ ];
+  };
+}
@@ -0,0 +1,22 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.execStopPre = mkOption {
+    description = ''
+      Commands to run one after another on shutdown, before the service
+      process is sent the stop signal.
+
+      Useful for draining connections or flushing state. They run in the same
+      environment as `execStartPre`. The hooks count towards
+      `settings.shutdown.timeout`: once it runs out the remaining hooks are
+      killed and so is the service process.
+    '';
+    example = lib.literalExpression ''[ [ "my-server" "drain" ] ]'';
+    type = types.listOf (types.nonEmptyListOf types.str);
+    default = [ ];
+  };
+}
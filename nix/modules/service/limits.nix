@@ -0,0 +1,27 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.limits = mkOption {
+    description = ''
+      Resource limits to apply to the service process.
+
+      Each attribute maps onto the `RLIMIT_*` constant of the same name and
+      its value is used as both the soft and the hard limit. Supported names
+      are `as`, `core`, `cpu`, `data`, `fsize`, `memlock`, `nofile`, `nproc`
+      and `stack`. Any other name is rejected when nimi loads its config.
+    '';
+    example = lib.literalExpression ''
+      {
+        nofile = 1024;
+        as = 536870912;
+        core = 0;
+      }
+    '';
+    type = types.attrsOf types.ints.unsigned;
+    default = { };
+  };
+}
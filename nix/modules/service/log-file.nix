@@ -0,0 +1,25 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.logFile = mkOption {
+    description = ''
+      File to append the stdout and stderr of this service to.
+
+      Unlike the run-specific files under `settings.logging.logsDir`, this
+      path stays the same across runs, which makes it suitable for durable
+      logs. Missing parent directories are created and every line is flushed
+      as soon as it is written. When set, it takes precedence over
+      `settings.logging`.
+
+      If the file can't be written, nimi logs an error once and keeps
+      printing the output to the console.
+    '';
+    example = lib.literalExpression ''"/var/log/my-service.log"'';
+    type = types.nullOr types.str;
+    default = null;
+  };
+}
@@ -0,0 +1,85 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.logLevel = mkOption {
+    description = ''
+      Minimum log level required for this service's output to be printed.
+
+      It applies to both streams, at the level each line is logged at:
+      `stdoutLevel` (`debug` by default) and `stderrLevel` (`error` by
+      default), or the level `logLevelRegex` finds in the line. So `"info"`
+      silences stdout but keeps stderr, while `"off"` silences both.
+      Per-service log files keep every line.
+
+      This applies on top of the global `RUST_LOG` filter: a line is only
+      printed when both let it through. When unset, only `RUST_LOG` applies.
+    '';
+    example = lib.literalExpression ''"warn"'';
+    type = types.nullOr (
+      types.enum [
+        "off"
+        "error"
+        "warn"
+        "info"
+        "debug"
+        "trace"
+      ]
+    );
+    default = null;
+  };
+
+  options.stdoutLevel = mkOption {
+    description = ''
+      Level the lines this service writes to stdout are logged at.
+
+      Raise it to `info` for services which write their regular logs to
+      stdout, so they show up at the default verbosity.
+    '';
+    example = lib.literalExpression ''"info"'';
+    type = types.enum [
+      "error"
+      "warn"
+      "info"
+      "debug"
+      "trace"
+    ];
+    default = "debug";
+  };
+
+  options.stderrLevel = mkOption {
+    description = ''
+      Level the lines this service writes to stderr are logged at.
+
+      Lower it for services which write harmless chatter to stderr, so it
+      doesn't show up as errors.
+    '';
+    example = lib.literalExpression ''"warn"'';
+    type = types.enum [
+      "error"
+      "warn"
+      "info"
+      "debug"
+      "trace"
+    ];
+    default = "error";
+  };
+
+  options.logLevelRegex = mkOption {
+    description = ''
+      Regex reading the level a line of this service's output was logged
+      at, for services which prefix their lines with it.
+
+      The level is taken from the capture group named `level`, or from the
+      first group when there is none. Besides the usual level names, syslog
+      names like `notice` or `crit` are understood, case doesn't matter.
+      Lines it doesn't match keep `stdoutLevel` or `stderrLevel`.
+    '';
+    example = lib.literalExpression ''"^\\[(\\w+)\\]|level=(?P<level>\\w+)"'';
+    type = types.nullOr types.str;
+    default = null;
+  };
+}
@@ -0,0 +1,25 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.materialize = mkOption {
+    description = ''
+      How the `configData` files of the service are placed into its config
+      directory.
+
+      - `symlink`: link to the `source` of each file in the nix store.
+      - `copy`: copy each `source`, keeping its file mode. Use this when the
+        nix store isn't available at runtime or the service refuses to read
+        symlinked config files.
+    '';
+    example = lib.literalExpression ''"copy"'';
+    type = types.enum [
+      "symlink"
+      "copy"
+    ];
+    default = "symlink";
+  };
+}
@@ -0,0 +1,20 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.nice = mkOption {
+    description = ''
+      Niceness of the service process, from `-20` for the highest scheduling
+      priority to `19` for the lowest.
+
+      Raising the priority above that of nimi requires nimi to run as root
+      or with `CAP_SYS_NICE`. Inherits the niceness of nimi when unset.
+    '';
+    example = 10;
+    type = types.nullOr (types.ints.between (-20) 19);
+    default = null;
+  };
+}
@@ -0,0 +1,25 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.outputWatchdog = mkOption {
+    description = ''
+      Time in milliseconds the service may go without writing a line to
+      stdout or stderr.
+
+      When it elapses the process is killed and the run counts as failed, so
+      the restart policy decides if it is started again. This is only
+      meaningful for chatty services which log continuously, e.g. a worker
+      printing a line per job or a heartbeat, where going quiet means the
+      process hangs. Lines filtered out by `logLevel` still count.
+
+      Set to `null` to never watch the output.
+    '';
+    example = lib.literalExpression "60000";
+    type = types.nullOr types.ints.positive;
+    default = null;
+  };
+}
@@ -0,0 +1,33 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.passEnvironment = mkOption {
+    description = ''
+      Variables from the environment of nimi to pass on to the service.
+
+      When `null` the service inherits the whole environment of nimi, unless
+      `settings.passEnvironment` is set. When set to a list the environment
+      is cleared first and only the listed variables, along with those in
+      `settings.passEnvironment`, are passed on, followed by
+      `process.environment`. `PATH` is
+      always passed on so binaries given by name and programs the service
+      shells out to can still be found; set `process.environment.PATH` to
+      override it.
+
+      Inheriting everything is convenient, but it also hands the service any
+      secrets or configuration present in the environment of nimi, such as
+      credentials injected by the container runtime. Prefer an explicit list
+      for services which don't need them. Passing on `PATH` in turn means
+      anyone able to write to one of its directories can replace the
+      binaries the service runs by name, use absolute paths or a
+      `process.environment.PATH` of trusted directories if that matters.
+    '';
+    example = lib.literalExpression ''[ "HOME" "TZ" ]'';
+    type = types.nullOr (types.listOf types.str);
+    default = null;
+  };
+}
@@ -0,0 +1,54 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.readiness = mkOption {
+    description = ''
+      Readiness check for the service.
+
+      After the service process is spawned, nimi runs `command` every
+      `interval` milliseconds until it exits successfully, at which point the
+      service is considered ready and services ordered `after` it are started.
+      If the check hasn't succeeded after `retries` attempts the service is
+      stopped and handled like a failed run by the restart policy.
+
+      Set to `null` to consider the service ready as soon as it is spawned.
+    '';
+    example = lib.literalExpression ''
+      {
+        command = [ (lib.getExe pkgs.curl) "--fail" "http://localhost:8080/healthz" ];
+        interval = 500;
+        retries = 20;
+      }
+    '';
+    type = types.nullOr (
+      types.submodule {
+        options = {
+          command = mkOption {
+            description = "Argv of the readiness check command.";
+            type = types.nonEmptyListOf (types.either types.str types.path);
+          };
+          interval = mkOption {
+            description = "Time in milliseconds to wait between checks.";
+            type = types.ints.positive;
+            default = 1000;
+          };
+          timeout = mkOption {
+            description = "Time in milliseconds a single check may take.";
+            type = types.ints.positive;
+            default = 5000;
+          };
+          retries = mkOption {
+            description = "Maximum number of checks before the service is considered failed.";
+            type = types.ints.positive;
+            default = 30;
+          };
+        };
+      }
+    );
+    default = null;
+  };
+}
@@ -0,0 +1,29 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.restart = mkOption {
+    description = ''
+      Restart policy of this service, replacing `settings.restart` as a
+      whole. Options left out of the block take their usual defaults, not
+      the values of `settings.restart`.
+
+      Useful when services need different policies, e.g. a web app that is
+      restarted `always` next to a worker that should `never` be.
+      `oneshot` services are never restarted either way.
+
+      Set to `null` to follow `settings.restart`.
+    '';
+    example = lib.literalExpression ''
+      {
+        mode = "always";
+        time = 250;
+      }
+    '';
+    type = types.nullOr (import ../restart-type.nix { inherit lib; });
+    default = null;
+  };
+}
@@ -0,0 +1,65 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.sockets = mkOption {
+    description = ''
+      Listening sockets to bind and pass on to the service process.
+
+      Nimi binds the sockets before starting the service and keeps them open
+      across restarts, so connections made while the service restarts wait
+      in the backlog instead of getting refused. The process finds them
+      starting at file descriptor 3, in the listed order, following the
+      systemd socket activation protocol (`LISTEN_FDS`, `LISTEN_FDNAMES`
+      and `LISTEN_PID`), so `sd_listen_fds` and its equivalents work
+      unchanged.
+    '';
+    example = lib.literalExpression ''
+      [
+        { listen = "0.0.0.0:8080"; }
+        { listen = "/run/my-server/control.sock"; name = "control"; }
+      ]
+    '';
+    type = types.listOf (
+      types.submodule {
+        options = {
+          listen = mkOption {
+            description = ''
+              Address to listen on, either `host:port` or the absolute path
+              of a unix socket. A socket file left behind by a previous run
+              is removed before binding.
+            '';
+            example = "[::]:8080";
+            type = types.str;
+          };
+
+          type = mkOption {
+            description = ''
+              Kind of socket, `stream` for TCP and unix stream sockets or
+              `datagram` for UDP and unix datagram sockets.
+            '';
+            type = types.enum [
+              "stream"
+              "datagram"
+            ];
+            default = "stream";
+          };
+
+          name = mkOption {
+            description = ''
+              Name of the socket in `LISTEN_FDNAMES`, defaults to the name of
+              the service.
+            '';
+            example = "control";
+            type = types.nullOr types.str;
+            default = null;
+          };
+        };
+      }
+    );
+    default = [ ];
+  };
+}
@@ -0,0 +1,22 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.startDelay = mkOption {
+    description = ''
+      Time in milliseconds to wait before the service is first started.
+
+      The delay begins once every service in `after` is ready and is not
+      applied again when the service restarts. Shutting down during the delay
+      skips starting the service altogether.
+
+      Set to `null` to start the service right away.
+    '';
+    example = lib.literalExpression "5000";
+    type = types.nullOr types.ints.positive;
+    default = null;
+  };
+}
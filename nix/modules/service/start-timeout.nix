@@ -0,0 +1,25 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.startTimeout = mkOption {
+    description = ''
+      Time in milliseconds the service may take to become ready.
+
+      Applies to services with a `readiness` check and to `oneshot`
+      services, which become ready once they completed. When the timeout
+      elapses first, the process is stopped and the start is handled like a
+      failed run by the restart policy, so a service hanging during its
+      initialization doesn't keep the services ordered `after` it waiting
+      forever.
+
+      Set to `null` to wait as long as the readiness check keeps retrying.
+    '';
+    example = lib.literalExpression "30000";
+    type = types.nullOr types.ints.positive;
+    default = null;
+  };
+}
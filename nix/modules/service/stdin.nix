@@ -0,0 +1,35 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.stdin = mkOption {
+    description = ''
+      Where the service process reads its standard input from.
+
+      - `"null"`: read from `/dev/null`, so the process sees an empty input.
+      - `"inherit"`: share the standard input of nimi, e.g. the terminal
+        nimi was started from.
+      - `{ file = <path>; }`: read from a file, which is opened anew every
+        time the process is started.
+
+      Hooks of the service always read from `/dev/null`.
+
+      Services used to share the standard input of nimi before this option
+      existed, set it to `"inherit"` to keep that behavior.
+    '';
+    example = lib.literalExpression ''{ file = "/etc/my-service/seed.sql"; }'';
+    type = types.either (types.enum [
+      "null"
+      "inherit"
+    ]) (types.submodule {
+      options.file = mkOption {
+        description = "Path of the file to read the standard input from.";
+        type = types.str;
+      };
+    });
+    default = "null";
+  };
+}
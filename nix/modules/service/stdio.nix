@@ -0,0 +1,26 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.stdio = mkOption {
+    description = ''
+      Where the standard output and error of the service and its hooks go.
+
+      - `"capture"`: read by nimi and logged line by line under the name of
+        the service, honouring `logLevel`, `logFile` and the logging
+        settings.
+      - `"inherit"`: written straight to the standard output and error of
+        nimi, e.g. the terminal it was started from. The output bypasses
+        the formatting, filtering and log files of nimi.
+    '';
+    example = "inherit";
+    type = types.enum [
+      "capture"
+      "inherit"
+    ];
+    default = "capture";
+  };
+}
@@ -0,0 +1,21 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.successExitCodes = mkOption {
+    description = ''
+      Exit codes which count as a successful exit of the service process.
+
+      When empty only an exit code of `0` is successful. When set, the list
+      replaces that default, so include `0` explicitly if it should still
+      count as a success. Exits caused by nimi shutting the service down are
+      always treated as successful.
+    '';
+    example = lib.literalExpression "[ 0 2 143 ]";
+    type = types.listOf types.int;
+    default = [ ];
+  };
+}
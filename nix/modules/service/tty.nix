@@ -0,0 +1,24 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.tty = mkOption {
+    description = ''
+      Whether to run the service process on a pseudo terminal of its own,
+      for programs which behave differently or refuse to run without one.
+
+      The terminal becomes the controlling terminal and the standard output
+      and error of the process, as well as its standard input unless
+      `process.stdin` reads from somewhere else. It can't be combined with
+      `process.stdio = "inherit"`. It is sized like the terminal nimi runs on, or 80x24.
+      Everything written to it is logged like captured output, stdout and
+      stderr can't be told apart. Hooks of the service keep using pipes.
+    '';
+    example = true;
+    type = types.bool;
+    default = false;
+  };
+}
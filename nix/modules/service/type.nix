@@ -0,0 +1,27 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.type = mkOption {
+    description = ''
+      How the lifetime of the service process is treated.
+
+      - `longrunning`: the process is expected to keep running and is
+        restarted according to `settings.restart`.
+      - `oneshot`: a task which runs to completion once, such as a database
+        migration. It is never restarted and is expected to exit
+        successfully; a failure is handled by `settings.failurePolicy`.
+        Services ordered `after` a oneshot service wait until it completed,
+        and its `readiness` check is ignored.
+    '';
+    example = lib.literalExpression ''"oneshot"'';
+    type = types.enum [
+      "longrunning"
+      "oneshot"
+    ];
+    default = "longrunning";
+  };
+}
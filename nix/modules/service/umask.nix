@@ -0,0 +1,18 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.umask = mkOption {
+    description = ''
+      File mode creation mask of the service process, as an octal string.
+
+      Inherits the umask of nimi when unset.
+    '';
+    example = "0027";
+    type = types.nullOr (types.strMatching "[0-7]{1,4}");
+    default = null;
+  };
+}
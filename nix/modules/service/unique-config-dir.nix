@@ -0,0 +1,21 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.uniqueConfigDir = mkOption {
+    description = ''
+      Whether the service gets a freshly created config directory of its own
+      instead of the `nimi-config-<sha256>` directory shared by every
+      service with identical `configData`.
+
+      The directory is named `nimi-config-<random>` and removed once the
+      service stops.
+    '';
+    example = true;
+    type = types.bool;
+    default = false;
+  };
+}
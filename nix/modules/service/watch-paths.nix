@@ -0,0 +1,23 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.watchPaths = mkOption {
+    description = ''
+      Paths whose changes make nimi restart this service gracefully, e.g.
+      config files read from outside of `configData`.
+
+      The paths are checked twice a second and a change only triggers a
+      restart once they stayed unchanged for half a second, so a file
+      written in several steps restarts the service once. A path appearing,
+      disappearing or a symlink switching targets counts as a change too.
+      A watched directory only notices entries being added or removed.
+    '';
+    example = [ "/etc/my-service/config.toml" ];
+    type = types.listOf types.str;
+    default = [ ];
+  };
+}
@@ -0,0 +1,29 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.watchdog = mkOption {
+    description = ''
+      Watchdog interval in milliseconds for services implementing the
+      `sd_notify` watchdog protocol, like `WatchdogSec` of systemd.
+
+      nimi binds a notification socket for the service and passes it on in
+      `NOTIFY_SOCKET`, along with the interval in `WATCHDOG_USEC`. The service
+      has to send `WATCHDOG=1` at least once per interval, and may send
+      `WATCHDOG=trigger` to ask to be restarted. Once a ping is missed the
+      process is killed and the run counts as failed, so the restart policy
+      decides if it is started again.
+
+      Only `process.user`, or the user of nimi when unset, may use the
+      socket, and only pings sent from the process group of the service count.
+
+      Set to `null` to not watch the service.
+    '';
+    example = lib.literalExpression "30000";
+    type = types.nullOr types.ints.positive;
+    default = null;
+  };
+}
@@ -0,0 +1,19 @@
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+in
+{
+  _class = "service";
+
+  options.process.workingDirectory = mkOption {
+    description = ''
+      Directory to run the service process in.
+
+      The directory must already exist when the service starts. Set to
+      `null` to inherit the working directory of nimi.
+    '';
+    example = lib.literalExpression ''"/var/lib/my-service"'';
+    type = types.nullOr types.str;
+    default = null;
+  };
+}
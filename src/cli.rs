@@ -1,14 +1,23 @@
 //! Module containing the schema for the command line interface and methods to run it
 
-use std::path::{Path, PathBuf};
+use std::{
+    ffi::OsString,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use clap::{Parser, Subcommand, command};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum, error::ErrorKind};
 use eyre::{Context, Result};
-use format_serde_error::SerdeError;
-use log::info;
-use tokio::fs;
+use futures::future::OptionFuture;
+use log::{LevelFilter, error, info};
 
-use crate::{config::Config, process_manager::ProcessManager};
+use crate::{
+    config::{Config, ConfigSources},
+    pid_file::PidFile,
+    process_manager::{ProcessManager, service_manager::LogSink},
+};
 
 /// NixOS modular services runner and container init
 ///
@@ -53,12 +62,107 @@ use crate::{config::Config, process_manager::ProcessManager};
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
-    /// Path to the json representation of nimi services to run
+    /// Path to the config of the nimi services to run
     ///
     /// To generate this use the `mkNimiBin` of the nix
-    /// package for nimi
+    /// package for nimi. Pass `-` to read it from stdin.
+    ///
+    /// Read as TOML or YAML for files ending in `.toml`, `.yaml` or `.yml`, and as
+    /// JSON otherwise
+    ///
+    /// Can be given multiple times to merge several files, see `--config-dir`
+    ///
+    /// Either this or `--config-dir` is required, except for `schema`
     #[arg(short, long)]
-    pub config: PathBuf,
+    pub config: Vec<PathBuf>,
+
+    /// Directory of config files to merge after every `--config`
+    ///
+    /// Only files ending in `.json`, `.toml`, `.yaml` or `.yml` are read, in the
+    /// format of their extension. Files are merged in the order of their names. A service may only be defined
+    /// in a single file, settings from later files override earlier ones
+    #[arg(long)]
+    pub config_dir: Option<PathBuf>,
+
+    /// Expand `${NAME}` and `${NAME:-default}` in the command, environment and working
+    /// directory of services from the environment of nimi
+    #[arg(long)]
+    pub expand_env: bool,
+
+    /// Path to write the PID of nimi to while running services
+    ///
+    /// The file is removed again on shutdown
+    #[arg(long)]
+    pub pidfile: Option<PathBuf>,
+
+    /// Prefix every line of service output with a timestamp and the service name
+    ///
+    /// Keeps the output self-describing regardless of how `RUST_LOG` formats it
+    #[arg(long)]
+    pub log_prefix: bool,
+
+    /// Print fewer of nimi's own logs, `-q` keeps warnings and `-qq` only errors
+    ///
+    /// Output of services isn't affected
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "verbose")]
+    pub quiet: u8,
+
+    /// Print more of nimi's own logs, `-v` adds debug and `-vv` trace logs
+    ///
+    /// Output of services isn't affected
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Where to print the output of services to
+    #[arg(long, value_enum, default_value_t)]
+    pub log_target: LogTarget,
+
+    /// Where to print the shutdown summary to, one line of JSON written before exiting
+    ///
+    /// Printed rather than logged, so `--quiet` and `RUST_LOG` don't hide it
+    #[arg(long, value_enum, default_value_t)]
+    pub summary: SummaryTarget,
+
+    /// Address to serve the status of nimi on over HTTP, e.g. `127.0.0.1:9000`
+    ///
+    /// `/healthz` answers while nimi is running, `/status` lists the state, restarts, last
+    /// exit and uptime of every service as JSON and `/metrics` exports them for Prometheus
+    #[arg(long)]
+    pub status_addr: Option<SocketAddr>,
+
+    /// Path of a unix socket accepting commands to inspect and control services
+    ///
+    /// Takes one command per line: `list`, `status <name>`, `restart <name>` or
+    /// `stop <name>`
+    #[arg(long)]
+    pub control_socket: Option<PathBuf>,
+
+    /// Directory to create the config directories of services in
+    ///
+    /// Defaults to `$XDG_RUNTIME_DIR`, or the system temp directory if that isn't set
+    #[arg(long)]
+    pub runtime_dir: Option<PathBuf>,
+
+    /// Seconds to let services drain for before shutting them down
+    ///
+    /// On the first `SIGINT` or `SIGTERM` services are no longer restarted but may keep
+    /// running until they exit on their own. Once the time passes, or on a second
+    /// signal, they are stopped as usual
+    #[arg(long, value_name = "SECONDS")]
+    pub drain: Option<u64>,
+
+    /// Print what `run` would spawn for every service instead of spawning anything
+    ///
+    /// Lists the resolved binary, arguments, environment, working directory, config
+    /// data and restart policy of each service
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Keep running once every service has exited, until `SIGINT` or `SIGTERM`
+    ///
+    /// A failing service still stops nimi right away with the `stop-all` failure policy
+    #[arg(long)]
+    pub keep_alive: bool,
 
     /// The subcommand to run
     #[command(subcommand)]
@@ -66,40 +170,137 @@ pub struct Cli {
 }
 
 impl Cli {
-    async fn read_config(path: &Path) -> Result<Config> {
-        let config = fs::read_to_string(&path)
-            .await
-            .wrap_err("Failed to read config file from filesystem")?;
-
-        serde_json::from_str(&config)
-            .map_err(|err| SerdeError::new(config, err))
-            .wrap_err("Failed to deserialize config file")
+    /// Parse the arguments of nimi, exiting with a usage error if they're invalid
+    ///
+    /// Clap can't tell `--config` to be required for every command but `schema`, so this
+    /// reports a missing config the same way clap reports its own required arguments
+    pub fn parse_args() -> Self {
+        Self::try_parse_args(std::env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    fn try_parse_args<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let cli = Self::try_parse_from(args)?;
+        if cli.command.reads_config() && cli.config.is_empty() && cli.config_dir.is_none() {
+            return Err(Self::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "the following required arguments were not provided:\n  --config <CONFIG>",
+            ));
+        }
+
+        Ok(cli)
+    }
+
+    /// Level of nimi's own logs picked with `--quiet` or `--verbose`
+    ///
+    /// `None` if neither was given, leaving it up to `RUST_LOG`
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        match (self.quiet, self.verbose) {
+            (0, 0) => None,
+            (1, _) => Some(LevelFilter::Warn),
+            (2.., _) => Some(LevelFilter::Error),
+            (_, 1) => Some(LevelFilter::Debug),
+            (_, 2..) => Some(LevelFilter::Trace),
+        }
     }
 
     /// Execute the nimi CLI
     ///
-    /// Read the configuration file and runs the specificed `Command`
+    /// Read the configuration file and runs the specificed `Command`, `schema` doesn't
+    /// need one
     pub async fn run(self) -> Result<()> {
-        let config = Self::read_config(&self.config)
-            .await
-            .wrap_err_with(|| format!("Failed to read nimi config ({:?})", self.config))?;
+        let sources = ConfigSources {
+            files: self.config.clone(),
+            dir: self.config_dir.clone(),
+            expand_env: self.expand_env,
+        };
 
         match self.command {
             Command::Validate => {
-                info!("Successfully validated nimi config ({:?})", self.config);
+                let config = Config::load(&sources).await?;
+                let problems = config.problems();
+                for problem in &problems {
+                    error!("{problem}");
+                }
+                eyre::ensure!(
+                    problems.is_empty(),
+                    "Found {} problem(s) in nimi config",
+                    problems.len()
+                );
+
+                info!(
+                    "Successfully validated nimi config ({:?})",
+                    sources.paths().await?
+                );
 
                 Ok(())
             }
             Command::Run => {
+                let config = Config::load(&sources).await?;
+                let pidfile = self.pidfile.as_deref().filter(|_| !self.dry_run);
+                let _pid_file = OptionFuture::from(pidfile.map(PidFile::create))
+                    .await
+                    .transpose()
+                    .wrap_err("Failed to create PID file")?;
+
                 info!("Launching process manager...");
 
-                ProcessManager::new(config.services, config.settings)
+                let mut process_manager = ProcessManager::new(config.services, config.settings);
+                // stdin can't be read a second time, so there's nothing to reload from
+                if !self
+                    .config
+                    .iter()
+                    .any(|path| path == Path::new(Config::STDIN))
+                {
+                    process_manager = process_manager.with_config_sources(sources);
+                }
+                if let Some(status_addr) = self.status_addr {
+                    process_manager = process_manager.with_status_addr(status_addr);
+                }
+                if let Some(control_socket) = self.control_socket {
+                    process_manager = process_manager.with_control_socket(control_socket);
+                }
+                if let Some(drain) = self.drain {
+                    process_manager =
+                        process_manager.with_drain_timeout(Duration::from_secs(drain));
+                }
+                if let Some(runtime_dir) = self.runtime_dir {
+                    process_manager = process_manager.with_runtime_dir(runtime_dir);
+                }
+                match self.summary {
+                    SummaryTarget::Stdout => {
+                        process_manager =
+                            process_manager.with_summary_output(Box::new(io::stdout()));
+                    }
+                    SummaryTarget::Stderr => {
+                        process_manager =
+                            process_manager.with_summary_output(Box::new(io::stderr()));
+                    }
+                    SummaryTarget::Off => {}
+                }
+
+                process_manager
+                    .with_dry_run(self.dry_run)
+                    .with_keep_alive(self.keep_alive)
+                    .with_log_prefix(self.log_prefix)
+                    .with_log_sink(match self.log_target {
+                        LogTarget::Console => LogSink::Console,
+                        LogTarget::Syslog => LogSink::syslog(),
+                    })
                     .run()
                     .await
                     .wrap_err("Failed to run processes")?;
 
                 info!("Process manager finished");
 
+                Ok(())
+            }
+            Command::Schema => {
+                println!("{}", serde_json::to_string_pretty(&Config::schema())?);
+
                 Ok(())
             }
         }
@@ -114,4 +315,73 @@ pub enum Command {
 
     /// Run nimi services based on the config file
     Run,
+
+    /// Print the JSON Schema of the config file format
+    ///
+    /// Doesn't read any config
+    Schema,
+}
+
+impl Command {
+    /// Whether the command needs `--config` or `--config-dir`
+    fn reads_config(&self) -> bool {
+        match self {
+            Self::Validate | Self::Run => true,
+            Self::Schema => false,
+        }
+    }
+}
+
+/// Destination for the output of services
+#[derive(ValueEnum, Debug, Default, Clone, Copy)]
+pub enum LogTarget {
+    /// Print through the regular nimi logger
+    #[default]
+    Console,
+
+    /// Send to the local syslog socket at `/dev/log`
+    ///
+    /// Falls back to the console if the socket is unavailable
+    Syslog,
+}
+
+/// Destination for the shutdown summary
+#[derive(ValueEnum, Debug, Default, Clone, Copy)]
+pub enum SummaryTarget {
+    /// Print to stdout, after the output of every service
+    #[default]
+    Stdout,
+
+    /// Print to stderr
+    Stderr,
+
+    /// Don't print a summary
+    Off,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_is_required_except_for_schema() {
+        let err = Cli::try_parse_args(["nimi", "validate"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+        let err = Cli::try_parse_args(["nimi", "run"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+
+        assert!(Cli::try_parse_args(["nimi", "schema"]).is_ok());
+        assert!(Cli::try_parse_args(["nimi", "--config", "nimi.json", "validate"]).is_ok());
+        assert!(Cli::try_parse_args(["nimi", "--config-dir", "conf.d", "run"]).is_ok());
+    }
+
+    #[test]
+    fn the_summary_goes_to_stdout_unless_told_otherwise() {
+        let cli = Cli::try_parse_args(["nimi", "--config", "nimi.json", "run"]).unwrap();
+        assert!(matches!(cli.summary, SummaryTarget::Stdout));
+
+        let cli = Cli::try_parse_args(["nimi", "--config", "nimi.json", "--summary", "off", "run"])
+            .unwrap();
+        assert!(matches!(cli.summary, SummaryTarget::Off));
+    }
 }
@@ -1,20 +1,1018 @@
 //! Module containing the deserialized representation of the config generated via the NixOS modules
 //! system config for nimi
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{OsStr, OsString},
+    fmt,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
 
-use serde::{Deserialize, Serialize};
+use eyre::{Context, Result, eyre};
+use format_serde_error::SerdeError;
+use log::{debug, info};
+use schemars::{JsonSchema, Schema, schema_for};
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{DeserializeOwned, Error as _, MapAccess, Unexpected, Visitor},
+};
+use serde_json::{Map, Value};
+use tokio::{
+    fs,
+    io::{self, AsyncReadExt},
+};
 
-use crate::process_manager::{Service, Settings};
+use crate::process_manager::{
+    DependencyGraph, Service, Settings,
+    service::{ArgV, StdioMode},
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 /// Representation of the nimi config generated by evaluating a nimi services module
 ///
 /// Create this by using the nix package to run `nimi.mkNimiBin`
 pub struct Config {
     /// Deserializable representation of services
+    #[serde(deserialize_with = "deserialize_services")]
+    #[schemars(with = "HashMap<String, Service>")]
     pub services: HashMap<String, Service>,
 
     /// Process manager settings
+    #[serde(default)]
     pub settings: Settings,
+
+    /// Version of the config format the config was written for
+    ///
+    /// Configs without one predate versioning and are read as version 0
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: Option<u32>,
+}
+
+/// Migration of a config to the next schema version, see `Config::MIGRATIONS`
+type Migration = fn(&mut Map<String, Value>) -> bool;
+
+/// File format of a config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// `.json`, the format generated by the nix modules
+    Json,
+
+    /// `.toml`
+    Toml,
+
+    /// `.yaml` or `.yml`
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Pick the format from the extension of `path`
+    ///
+    /// Falls back to JSON for stdin and unknown extensions
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("toml") => Self::Toml,
+            Some("yaml" | "yml") => Self::Yaml,
+            _ => Self::Json,
+        }
+    }
+
+    /// Deserialize `text` in this format
+    pub fn parse<T: DeserializeOwned>(self, text: String) -> Result<T> {
+        let res = match self {
+            Self::Json => serde_json::from_str(&text).map_err(|err| SerdeError::new(text, err)),
+            Self::Yaml => serde_norway::from_str(&text).map_err(|err| {
+                let (line, column) = err
+                    .location()
+                    .map(|location| (Some(location.line()), Some(location.column() - 1)))
+                    .unwrap_or_default();
+
+                SerdeError::new(text, (err.into(), line, column))
+            }),
+            Self::Toml => toml::from_str(&text).map_err(|err| {
+                let (line, column) = err
+                    .span()
+                    .map(|span| {
+                        let before = &text[..span.start];
+                        let line = before.matches('\n').count() + 1;
+                        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1);
+                        (Some(line), Some(column))
+                    })
+                    .unwrap_or_default();
+                let message = err.message().to_owned();
+
+                SerdeError::new(text, (message.into(), line, column))
+            }),
+        };
+
+        Ok(res?)
+    }
+}
+
+/// Locations the config is read from
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    /// Config files, in the order they are merged
+    pub files: Vec<PathBuf>,
+
+    /// Directory whose config files are merged after `files`, sorted by name
+    pub dir: Option<PathBuf>,
+
+    /// If `${NAME}` references in the services are expanded from the environment of nimi
+    pub expand_env: bool,
+}
+
+impl ConfigSources {
+    /// Every config file to merge, in order
+    pub async fn paths(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = self.files.clone();
+
+        if let Some(dir) = &self.dir {
+            let mut dir_paths = Vec::new();
+            let mut entries = fs::read_dir(dir)
+                .await
+                .wrap_err_with(|| format!("Failed to read config directory {dir:?}"))?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if path.extension().is_some_and(|ext| {
+                    ["json", "toml", "yaml", "yml"]
+                        .map(OsStr::new)
+                        .contains(&ext)
+                }) {
+                    dir_paths.push(path);
+                }
+            }
+
+            dir_paths.sort();
+            paths.extend(dir_paths);
+        }
+
+        Ok(paths)
+    }
+}
+
+impl Config {
+    /// Path which makes `read` take the config from stdin
+    pub const STDIN: &str = "-";
+
+    /// Version of the config format this nimi reads
+    ///
+    /// Bumped along with a new entry in `MIGRATIONS` whenever the format changes in a way
+    /// older configs can't be read as
+    pub const SCHEMA_VERSION: u32 = 1;
+
+    /// Migrations bringing a config from the version of their index to the next one
+    ///
+    /// Each returns `true` if it had to change the config
+    const MIGRATIONS: [Migration; Self::SCHEMA_VERSION as usize] = [
+        // Version 1 only introduced `schemaVersion`, unversioned configs read the same
+        |_| false,
+    ];
+
+    /// JSON Schema of the config format
+    pub fn schema() -> Schema {
+        schema_for!(Self)
+    }
+
+    /// Read and merge the config from every one of `sources`
+    ///
+    /// Services from all files are combined, a service name may only be defined once.
+    /// Settings are merged field by field with later files taking precedence
+    pub async fn load(sources: &ConfigSources) -> Result<Self> {
+        let mut config = Self::load_unexpanded(sources).await?;
+        config.drop_disabled();
+
+        if sources.expand_env {
+            for (name, service) in &mut config.services {
+                service.process.expand_env().wrap_err_with(|| {
+                    format!("Failed to expand environment variables of service {name}")
+                })?;
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Remove the services which aren't enabled
+    ///
+    /// Services ordered after a disabled service no longer wait on it
+    fn drop_disabled(&mut self) {
+        let mut disabled: Vec<_> = self
+            .services
+            .iter()
+            .filter(|(_, service)| !service.enable)
+            .map(|(name, _)| name.clone())
+            .collect();
+        disabled.sort();
+
+        for name in &disabled {
+            info!("Skipping disabled service {name}");
+            self.services.remove(name);
+        }
+
+        for (name, service) in &mut self.services {
+            service.after.retain(|dependency| {
+                let keep = !disabled.contains(dependency);
+                if !keep {
+                    debug!(
+                        "Service {name} is no longer ordered after disabled service {dependency}"
+                    );
+                }
+                keep
+            });
+        }
+    }
+
+    async fn load_unexpanded(sources: &ConfigSources) -> Result<Self> {
+        let paths = sources.paths().await?;
+
+        if let [path] = paths.as_slice() {
+            return Self::read(path)
+                .await
+                .wrap_err_with(|| format!("Failed to read nimi config ({path:?})"));
+        }
+        eyre::ensure!(!paths.is_empty(), "No config files were given");
+
+        let mut merged = Map::new();
+        let mut service_files = HashMap::<String, &Path>::new();
+        for path in &paths {
+            let config = Self::read_text(path)
+                .await
+                .wrap_err_with(|| format!("Failed to read nimi config ({path:?})"))?;
+            let Value::Object(mut config) = ConfigFormat::from_path(path)
+                .parse(config)
+                .wrap_err_with(|| format!("Failed to parse nimi config ({path:?})"))?
+            else {
+                eyre::bail!("Nimi config {path:?} must be a map of `services` and `settings`");
+            };
+            Self::migrate(&mut config)
+                .wrap_err_with(|| format!("Failed to migrate nimi config ({path:?})"))?;
+
+            for (key, value) in config {
+                if key == "services"
+                    && let Value::Object(services) = &value
+                {
+                    for name in services.keys() {
+                        if let Some(previous) = service_files.insert(name.clone(), path) {
+                            eyre::bail!(
+                                "Service {name} is defined in both {previous:?} and {path:?}"
+                            );
+                        }
+                    }
+                }
+
+                match merged.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        merged.insert(key, value);
+                    }
+                }
+            }
+        }
+
+        serde_json::from_value(Value::Object(merged))
+            .wrap_err_with(|| format!("Failed to deserialize merged nimi config from {paths:?}"))
+    }
+
+    /// Read and deserialize the config file at `path`
+    ///
+    /// The format is picked from the file extension. Reads JSON from stdin if `path` is `-`
+    pub async fn read(path: &Path) -> Result<Self> {
+        let config = Self::read_text(path).await?;
+        let format = ConfigFormat::from_path(path);
+
+        let Value::Object(mut raw) = format
+            .parse(config.clone())
+            .wrap_err("Failed to deserialize config file")?
+        else {
+            eyre::bail!("Nimi config must be a map of `services` and `settings`");
+        };
+        if Self::migrate(&mut raw)? {
+            return serde_json::from_value(Value::Object(raw))
+                .wrap_err("Failed to deserialize migrated config file");
+        }
+
+        // Deserializing the text itself points errors at their location in the file
+        format
+            .parse(config)
+            .wrap_err("Failed to deserialize config file")
+    }
+
+    /// Bring a config up to `SCHEMA_VERSION`
+    ///
+    /// Returns `true` if a migration changed the config. Configs for a newer version are
+    /// rejected, nimi can't know what changed in them
+    fn migrate(config: &mut Map<String, Value>) -> Result<bool> {
+        let version = match config.get("schemaVersion") {
+            None | Some(Value::Null) => 0,
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or_else(|| {
+                    eyre!("`schemaVersion` must be a non-negative integer, got {version}")
+                })?,
+        };
+        eyre::ensure!(
+            version <= Self::SCHEMA_VERSION,
+            "Config was written for schemaVersion {version}, but this nimi only supports up to \
+             {}. Update nimi or generate the config with a matching version of its nix modules",
+            Self::SCHEMA_VERSION
+        );
+
+        let mut changed = false;
+        for migration in &Self::MIGRATIONS[version as usize..] {
+            changed |= migration(config);
+        }
+        config.insert("schemaVersion".to_owned(), Self::SCHEMA_VERSION.into());
+
+        Ok(changed)
+    }
+
+    async fn read_text(path: &Path) -> Result<String> {
+        if path == Path::new(Self::STDIN) {
+            let mut config = String::new();
+            io::stdin()
+                .read_to_string(&mut config)
+                .await
+                .wrap_err("Failed to read config from stdin")?;
+
+            Ok(config)
+        } else {
+            fs::read_to_string(&path)
+                .await
+                .wrap_err("Failed to read config file from filesystem")
+        }
+    }
+
+    /// Run the static checks on the config which deserializing can't cover
+    ///
+    /// Returns a description of every problem found, never spawns any processes
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if let Err(e) = DependencyGraph::new(&self.services) {
+            problems.push(format!("{e:#}"));
+        }
+
+        let startup = &self.settings.startup;
+        let startup_bins = startup
+            .run_on_startup
+            .iter()
+            .map(String::as_str)
+            .chain(startup.commands.iter().map(ArgV::binary));
+        let startup_path = startup.environment.get("PATH").map(String::as_str);
+        for bin in startup_bins {
+            if resolve_binary(bin, startup_path).is_none() {
+                problems.push(format!(
+                    "Startup binary {bin:?} was not found or is not executable"
+                ));
+            }
+        }
+
+        let mut names: Vec<_> = self.services.keys().collect();
+        names.sort();
+        for name in names {
+            let service = &self.services[name];
+
+            let binary = service.process.command.binary();
+            let path = service.process.environment.get("PATH").map(String::as_str);
+            if resolve_binary(binary, path).is_none() {
+                problems.push(format!(
+                    "Binary {binary:?} of service {name} was not found or is not executable"
+                ));
+            }
+
+            if service.process.tty && service.process.stdio == StdioMode::Inherit {
+                problems.push(format!(
+                    "Service {name} can't set both `process.tty` and `process.stdio = \"inherit\"`"
+                ));
+            }
+
+            if service.output_watchdog.is_some() && !service.process.output_is_read() {
+                problems.push(format!(
+                    "Service {name} sets `outputWatchdog`, but nimi never sees its output with `process.stdio = \"inherit\"`"
+                ));
+            }
+
+            let mut config_paths = HashMap::new();
+            for (key, cfg) in &service.config_data {
+                if !cfg.enable {
+                    continue;
+                }
+
+                if let Err(e) = cfg.check_path() {
+                    problems.push(format!("Config data {key} of service {name}: {e}"));
+                }
+
+                if let Some(previous) = config_paths.insert(&cfg.path, key) {
+                    let (first, second) = if previous < key {
+                        (previous, key)
+                    } else {
+                        (key, previous)
+                    };
+                    problems.push(format!(
+                        "Config data {first} and {second} of service {name} both write to {:?}",
+                        cfg.path
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+/// Find the executable `binary` refers to, looking it up in `path` or nimi's `PATH`
+/// when it doesn't contain a `/`
+///
+/// Returns `None` if there is no such executable
+pub fn resolve_binary(binary: &str, path: Option<&str>) -> Option<PathBuf> {
+    let is_executable = |candidate: &Path| {
+        std::fs::metadata(candidate)
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    };
+
+    if binary.contains('/') {
+        let binary = PathBuf::from(binary);
+        return is_executable(&binary).then_some(binary);
+    }
+
+    let path = path
+        .map(OsString::from)
+        .or_else(|| env::var_os("PATH"))
+        .unwrap_or_default();
+
+    env::split_paths(&path)
+        .map(|dir| dir.join(binary))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// Deserialize the services, naming the service in any error about its definition
+fn deserialize_services<'de, D>(deserializer: D) -> Result<HashMap<String, Service>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct ServicesVisitor;
+
+    impl<'de> Visitor<'de> for ServicesVisitor {
+        type Value = HashMap<String, Service>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map of service names to services")
+        }
+
+        fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+        where
+            A: MapAccess<'de>,
+        {
+            let mut services = HashMap::with_capacity(map.size_hint().unwrap_or_default());
+            while let Some(name) = map.next_key::<String>()? {
+                let service = map.next_value().map_err(|e| {
+                    A::Error::invalid_value(
+                        Unexpected::Other(&format!("definition of service {name}")),
+                        &format!("a valid service ({e})").as_str(),
+                    )
+                })?;
+                services.insert(name, service);
+            }
+
+            Ok(services)
+        }
+    }
+
+    deserializer.deserialize_map(ServicesVisitor)
+}
+
+/// Merge `value` into `target`, recursing into objects and replacing anything else
+fn merge_json(target: &mut Value, value: Value) {
+    match (target, value) {
+        (Value::Object(target), Value::Object(value)) => {
+            for (key, value) in value {
+                match target.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, value) => *target = value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::signal::Signal;
+    use serde_json::json;
+
+    use super::*;
+
+    /// Settings with every field given, merged files may override parts of them
+    fn settings() -> Value {
+        json!({
+            "restart": {"mode": "never", "time": 100, "count": 1, "startLimit": null, "crashLoop": null},
+            "startup": {"runOnStartup": null, "ignoreFailure": false, "commands": [], "environment": {}},
+            "logging": {"enable": false, "logsDir": "logs", "combineOutput": false, "rotation": {"maxSize": null, "maxFiles": 5}, "maxLineLength": 65536},
+            "shutdown": {"timeout": 2000},
+            "failurePolicy": "stop-all",
+            "forwardSignals": ["SIGUSR1"],
+            "passEnvironment": null,
+        })
+    }
+
+    fn service(argv: &[&str]) -> Value {
+        json!({"configData": {}, "process": {"argv": argv}})
+    }
+
+    fn write(dir: &Path, name: &str, config: &Value) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, config.to_string()).unwrap();
+        path
+    }
+
+    async fn load(files: Vec<PathBuf>, dir: Option<PathBuf>) -> Result<Config> {
+        Config::load(&ConfigSources {
+            files,
+            dir,
+            expand_env: false,
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn services_of_all_files_are_merged_and_later_settings_win() {
+        let tmp = tempfile::tempdir().unwrap();
+        let first = write(
+            tmp.path(),
+            "first.json",
+            &json!({"services": {"a": service(&["a"])}, "settings": settings()}),
+        );
+        let second = write(
+            tmp.path(),
+            "second.json",
+            &json!({
+                "services": {"b": service(&["b"])},
+                "settings": {"shutdown": {"timeout": 5000}},
+            }),
+        );
+
+        let config = load(vec![first, second], None).await.unwrap();
+
+        let mut names: Vec<_> = config.services.keys().collect();
+        names.sort();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(
+            config.settings.shutdown.timeout,
+            std::time::Duration::from_secs(5)
+        );
+        assert_eq!(config.settings.restart.count, 1);
+    }
+
+    #[tokio::test]
+    async fn a_service_defined_twice_names_both_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let first = write(
+            tmp.path(),
+            "first.json",
+            &json!({"services": {"a": service(&["a"])}, "settings": settings()}),
+        );
+        let second = write(
+            tmp.path(),
+            "second.json",
+            &json!({"services": {"a": service(&["b"])}}),
+        );
+
+        let err = load(vec![first.clone(), second.clone()], None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!("Service a is defined in both {first:?} and {second:?}")
+        );
+    }
+
+    #[tokio::test]
+    async fn config_dir_files_are_merged_by_name_after_the_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("conf.d");
+        std::fs::create_dir(&dir).unwrap();
+        let file = write(
+            tmp.path(),
+            "base.json",
+            &json!({"services": {}, "settings": settings()}),
+        );
+        write(
+            &dir,
+            "20-late.json",
+            &json!({"settings": {"shutdown": {"timeout": 3000}}}),
+        );
+        write(
+            &dir,
+            "10-early.json",
+            &json!({"settings": {"shutdown": {"timeout": 1000}}}),
+        );
+        write(
+            &dir,
+            "ignored.txt",
+            &json!({"settings": {"shutdown": {"timeout": 9000}}}),
+        );
+
+        let sources = ConfigSources {
+            files: vec![file.clone()],
+            dir: Some(dir.clone()),
+            expand_env: false,
+        };
+        assert_eq!(
+            sources.paths().await.unwrap(),
+            [file, dir.join("10-early.json"), dir.join("20-late.json")]
+        );
+
+        let config = Config::load(&sources).await.unwrap();
+        assert_eq!(
+            config.settings.shutdown.timeout,
+            std::time::Duration::from_secs(3)
+        );
+    }
+
+    fn config(services: Value) -> Config {
+        serde_json::from_value(json!({"services": services, "settings": settings()})).unwrap()
+    }
+
+    #[test]
+    fn valid_config_has_no_problems() {
+        let config = config(json!({"a": service(&["/bin/sh"]), "b": service(&["sh"])}));
+
+        assert_eq!(config.problems(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn missing_binaries_are_reported() {
+        let config = config(json!({
+            "a": service(&["/nonexistent/bin"]),
+            "b": service(&["nimi-test-missing-binary"]),
+        }));
+
+        assert_eq!(
+            config.problems(),
+            [
+                "Binary \"/nonexistent/bin\" of service a was not found or is not executable",
+                "Binary \"nimi-test-missing-binary\" of service b was not found or is not executable",
+            ]
+        );
+    }
+
+    #[test]
+    fn dependency_cycles_are_reported() {
+        let mut a = service(&["sh"]);
+        a["after"] = json!(["b"]);
+        let mut b = service(&["sh"]);
+        b["after"] = json!(["a"]);
+        let config = config(json!({"a": a, "b": b}));
+
+        assert_eq!(
+            config.problems(),
+            ["Dependency cycle between services: a -> b -> a"]
+        );
+    }
+
+    #[test]
+    fn uncaptured_output_problems_are_reported() {
+        let mut tty = service(&["sh"]);
+        tty["process"]["tty"] = json!(true);
+        tty["process"]["stdio"] = json!("inherit");
+        let mut watched = service(&["sh"]);
+        watched["process"]["stdio"] = json!("inherit");
+        watched["outputWatchdog"] = json!(1000);
+        let mut captured = service(&["sh"]);
+        captured["outputWatchdog"] = json!(1000);
+        let config = config(json!({"captured": captured, "tty": tty, "watched": watched}));
+
+        assert_eq!(
+            config.problems(),
+            [
+                "Service tty can't set both `process.tty` and `process.stdio = \"inherit\"`",
+                "Service watched sets `outputWatchdog`, but nimi never sees its output with `process.stdio = \"inherit\"`",
+            ]
+        );
+    }
+
+    #[test]
+    fn config_data_problems_are_reported() {
+        let mut a = service(&["sh"]);
+        a["configData"] = json!({
+            "first": {"enable": true, "path": "app.conf", "text": "", "source": null},
+            "second": {"enable": true, "path": "app.conf", "text": "", "source": null},
+            "off": {"enable": false, "path": "app.conf", "text": "", "source": null},
+            "escape": {"enable": true, "path": "../etc/passwd", "text": "", "source": null},
+        });
+        let config = config(json!({"a": a}));
+
+        let mut problems = config.problems();
+        problems.sort();
+        assert_eq!(
+            problems,
+            [
+                "Config data escape of service a: Config data path \"../etc/passwd\" must be a relative path inside of the config directory",
+                "Config data first and second of service a both write to \"app.conf\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_argv_is_rejected_when_deserializing() {
+        let err = serde_json::from_value::<Config>(
+            json!({"services": {"a": service(&[])}, "settings": settings()}),
+        )
+        .unwrap_err();
+
+        assert!(
+            err.to_string()
+                .starts_with("invalid value: definition of service a, expected a valid service ("),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn service_errors_keep_the_underlying_error_and_its_location() {
+        let config = r#"{"services": {"web": {"configData": {}, "process": {"argv": ["x"], "umask": "9"}}}}"#;
+
+        let err = serde_json::from_str::<Config>(config).unwrap_err();
+
+        assert!(err.is_data(), "{err}");
+        assert_eq!(err.line(), 1);
+        assert!(
+            err.to_string().contains(
+                "definition of service web, expected a valid service (Invalid umask \"9\""
+            ),
+            "{err}"
+        );
+    }
+
+    const TOML: &str = r#"
+[services.web]
+configData = {}
+logLevel = "warn"
+process.argv = ["/bin/sh", "-c", "echo web"]
+after = ["db"]
+
+[services.db]
+configData = {}
+process.argv = ["/bin/sh", "-c", "echo db"]
+
+[settings]
+restart = {mode = "never", time = 100, count = 1}
+startup = {ignoreFailure = false, commands = [], environment = {}}
+logging = {enable = true, logsDir = "logs", combineOutput = false, rotation = {maxFiles = 3}}
+shutdown = {timeout = 2000}
+failurePolicy = "stop-all"
+forwardSignals = ["SIGUSR1"]
+"#;
+
+    const YAML: &str = r#"
+services:
+  web:
+    configData: {}
+    logLevel: warn
+    process:
+      argv: [/bin/sh, -c, echo web]
+    after: [db]
+  db:
+    configData: {}
+    process:
+      argv: [/bin/sh, -c, echo db]
+settings:
+  restart: {mode: never, time: 100, count: 1}
+  startup: {ignoreFailure: false, commands: [], environment: {}}
+  logging:
+    enable: true
+    logsDir: logs
+    combineOutput: false
+    rotation: {maxFiles: 3}
+  shutdown: {timeout: 2000}
+  failurePolicy: stop-all
+  forwardSignals: [SIGUSR1]
+"#;
+
+    #[tokio::test]
+    async fn json_toml_and_yaml_configs_read_the_same() {
+        let tmp = tempfile::tempdir().unwrap();
+        let json = write(
+            tmp.path(),
+            "nimi.json",
+            &json!({
+                "services": {
+                    "web": {
+                        "configData": {},
+                        "logLevel": "warn",
+                        "process": {"argv": ["/bin/sh", "-c", "echo web"]},
+                        "after": ["db"],
+                    },
+                    "db": service(&["/bin/sh", "-c", "echo db"]),
+                },
+                "settings": {
+                    "restart": {"mode": "never", "time": 100, "count": 1},
+                    "startup": {"ignoreFailure": false, "commands": [], "environment": {}},
+                    "logging": {"enable": true, "logsDir": "logs", "combineOutput": false, "rotation": {"maxFiles": 3}},
+                    "shutdown": {"timeout": 2000},
+                    "failurePolicy": "stop-all",
+                    "forwardSignals": ["SIGUSR1"],
+                },
+            }),
+        );
+        let toml = tmp.path().join("nimi.toml");
+        std::fs::write(&toml, TOML).unwrap();
+        let yaml = tmp.path().join("nimi.yml");
+        std::fs::write(&yaml, YAML).unwrap();
+
+        let read =
+            async |path: &Path| serde_json::to_value(Config::read(path).await.unwrap()).unwrap();
+        let expected = read(&json).await;
+
+        assert_eq!(read(&toml).await, expected);
+        assert_eq!(read(&yaml).await, expected);
+        assert_eq!(expected["services"]["web"]["after"], json!(["db"]));
+    }
+
+    #[tokio::test]
+    async fn yaml_errors_point_at_their_location() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("nimi.yaml");
+        std::fs::write(
+            &path,
+            "services: {}\nsettings:\n  shutdown: {timeout: soon}\n",
+        )
+        .unwrap();
+
+        let err = format!("{:#}", Config::read(&path).await.unwrap_err());
+
+        assert!(err.contains("3 |   shutdown: {timeout: soon}"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn config_which_is_not_a_map_is_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("nimi.yaml");
+        std::fs::write(&path, "- services\n").unwrap();
+
+        let err = Config::read(&path).await.unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Nimi config must be a map of `services` and `settings`"
+        );
+    }
+
+    #[tokio::test]
+    async fn settings_may_be_left_out() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write(
+            tmp.path(),
+            "nimi.json",
+            &json!({"services": {"a": service(&["a"])}}),
+        );
+
+        let config = load(vec![path], None).await.unwrap();
+
+        assert_eq!(
+            config.settings.forward_signals,
+            [Signal::SIGUSR1, Signal::SIGUSR2]
+        );
+        assert_eq!(config.settings.restart.count, 5);
+    }
+
+    #[tokio::test]
+    async fn disabled_services_are_dropped_along_with_the_ordering_on_them() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut disabled = service(&["db"]);
+        disabled["enable"] = json!(false);
+        let mut web = service(&["web"]);
+        web["after"] = json!(["db", "cache"]);
+        let path = write(
+            tmp.path(),
+            "nimi.json",
+            &json!({
+                "services": { "db": disabled, "cache": service(&["cache"]), "web": web },
+                "settings": settings(),
+            }),
+        );
+
+        let config = load(vec![path], None).await.unwrap();
+
+        let mut names: Vec<_> = config.services.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["cache", "web"]);
+        assert_eq!(config.services["web"].after, ["cache"]);
+        assert!(config.services["web"].enable);
+    }
+
+    fn schema_accepts(config: &Value) -> bool {
+        let schema = serde_json::to_value(Config::schema()).unwrap();
+
+        jsonschema::validator_for(&schema).unwrap().is_valid(config)
+    }
+
+    #[test]
+    fn schema_accepts_a_generated_config() {
+        let config = json!({
+            "schemaVersion": Config::SCHEMA_VERSION,
+            "services": {
+                "web": {
+                    "configData": {
+                        "web.conf": {
+                            "enable": true,
+                            "path": "web.conf",
+                            "text": "port = 80",
+                            "source": null,
+                        },
+                    },
+                    "process": {"argv": ["web", "--config", "web.conf"]},
+                },
+                "migrate": {
+                    "type": "oneshot",
+                    "configData": {},
+                    "process": {"argv": [], "command": "migrate up"},
+                    "after": [],
+                },
+            },
+            "settings": settings(),
+        });
+
+        assert!(schema_accepts(&config));
+        let config: Config = serde_json::from_value(config).unwrap();
+        assert_eq!(config.services.len(), 2);
+    }
+
+    #[test]
+    fn schema_rejects_what_deserializing_rejects() {
+        let with_process =
+            |process: Value| json!({"services": {"web": {"configData": {}, "process": process}}});
+
+        assert!(schema_accepts(&with_process(json!({"argv": ["web"]}))));
+        assert!(!schema_accepts(&with_process(
+            json!({"argv": ["web"], "command": "web"})
+        )));
+        assert!(!schema_accepts(&with_process(json!({"argv": []}))));
+        assert!(!schema_accepts(&with_process(json!({}))));
+        assert!(!schema_accepts(
+            &json!({"services": {"web": {"configData": {}}}})
+        ));
+    }
+
+    #[tokio::test]
+    async fn configs_for_a_newer_version_are_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let newer = Config::SCHEMA_VERSION + 1;
+        let path = write(
+            tmp.path(),
+            "nimi.json",
+            &json!({"schemaVersion": newer, "services": {}}),
+        );
+
+        let err = Config::read(&path).await.unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Config was written for schemaVersion {newer}, but this nimi only supports up \
+                 to {}. Update nimi or generate the config with a matching version of its nix \
+                 modules",
+                Config::SCHEMA_VERSION
+            )
+        );
+        let err = load(vec![path.clone(), path], None).await.unwrap_err();
+        assert!(format!("{err:#}").contains("only supports up to"));
+    }
+
+    #[tokio::test]
+    async fn unversioned_configs_are_migrated_to_the_current_version() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write(
+            tmp.path(),
+            "nimi.json",
+            &json!({"services": {"a": service(&["a"])}}),
+        );
+
+        let config = Config::read(&path).await.unwrap();
+        assert!(config.services.contains_key("a"));
+
+        let mut raw = Map::from_iter([("services".to_owned(), json!({}))]);
+        assert!(!Config::migrate(&mut raw).unwrap());
+        assert_eq!(raw["schemaVersion"], Config::SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn invalid_schema_versions_are_rejected() {
+        for version in [json!(-1), json!("1"), json!(1.5)] {
+            let mut config = Map::from_iter([("schemaVersion".to_owned(), version.clone())]);
+
+            let err = Config::migrate(&mut config).unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                format!("`schemaVersion` must be a non-negative integer, got {version}")
+            );
+        }
+    }
 }
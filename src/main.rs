@@ -2,24 +2,132 @@
 
 //! [`Tini`](https://github.com/krallin/tini)-like PID 1 for containers and target for [NixOS modular services](https://nixos.org/manual/nixos/unstable/#modular-services).
 
-use clap::Parser;
+use std::process::ExitCode;
+
 use env_logger::Env;
 use eyre::{Context, Result};
+use log::LevelFilter;
 
-use crate::{cli::Cli, subreaper::Subreaper};
+use crate::{cli::Cli, process_manager::service_manager::ServiceError, subreaper::Subreaper};
 
 pub mod cli;
 pub mod config;
+pub mod pid_file;
 pub mod process_manager;
 pub mod subreaper;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
+    let Err(e) = run().await else {
+        return ExitCode::SUCCESS;
+    };
+    eprintln!("Error: {e:?}");
+
+    exit_code(&e)
+}
+
+async fn run() -> Result<()> {
     color_eyre::install().wrap_err("Failed to setup color_eyre")?;
-    env_logger::Builder::from_env(Env::default().default_filter_or("debug"))
-        .try_init()
-        .wrap_err("Failed to setup env_logger")?;
+    let cli = Cli::parse_args();
+
+    let mut logger = env_logger::Builder::from_env(Env::default().default_filter_or("debug"));
+    if let Some(level) = cli.log_level() {
+        filter_own_logs(&mut logger, level);
+    }
+    logger.try_init().wrap_err("Failed to setup env_logger")?;
 
     Subreaper::enable()?;
-    Cli::parse().run().await.wrap_err("Failed to run nimi CLI")
+    cli.run().await.wrap_err("Failed to run nimi CLI")
+}
+
+/// Modules of nimi whose logs `--quiet` and `--verbose` apply to
+///
+/// Services log under their own names, and one may well be called like the crate, so
+/// the modules are listed instead of filtering on the crate name
+const OWN_MODULES: [&str; 5] = [
+    concat!(env!("CARGO_CRATE_NAME"), "::cli"),
+    concat!(env!("CARGO_CRATE_NAME"), "::config"),
+    concat!(env!("CARGO_CRATE_NAME"), "::pid_file"),
+    concat!(env!("CARGO_CRATE_NAME"), "::process_manager"),
+    concat!(env!("CARGO_CRATE_NAME"), "::subreaper"),
+];
+
+/// Print the logs of nimi itself at `level`, leaving the logs of services alone
+fn filter_own_logs(logger: &mut env_logger::Builder, level: LevelFilter) {
+    for module in OWN_MODULES {
+        logger.filter_module(module, level);
+    }
+}
+
+/// Exit code of nimi for the error it failed with
+///
+/// Mirrors the exit code of a failed service so container runtimes and CI see it
+fn exit_code(e: &eyre::Report) -> ExitCode {
+    e.chain()
+        .find_map(|err| err.downcast_ref::<ServiceError>())
+        .and_then(ServiceError::exit_code)
+        .and_then(|code| u8::try_from(code).ok())
+        .filter(|code| *code != 0)
+        .map_or(ExitCode::FAILURE, ExitCode::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::process_manager::{ProcessManager, Service, Settings};
+
+    #[tokio::test]
+    async fn nimi_exits_with_the_exit_code_of_a_failed_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let service: Service = serde_json::from_value(json!({
+            "configData": {},
+            "process": { "argv": ["sh", "-c", "exit 42"] },
+            "restart": { "mode": "never", "time": 10, "count": 0 },
+        }))
+        .unwrap();
+
+        let err = ProcessManager::new(
+            HashMap::from([("failing".to_owned(), service)]),
+            Settings::default(),
+        )
+        .with_runtime_dir(tmp.path().to_owned())
+        .run()
+        .await
+        .unwrap_err();
+
+        assert_eq!(exit_code(&err), ExitCode::from(42));
+    }
+
+    #[test]
+    fn other_errors_exit_with_a_generic_failure() {
+        assert_eq!(exit_code(&eyre::eyre!("broken config")), ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn quiet_and_verbose_only_apply_to_the_logs_of_nimi() {
+        let mut builder = env_logger::Builder::new();
+        builder.filter_level(LevelFilter::Debug);
+        filter_own_logs(&mut builder, LevelFilter::Warn);
+        let logger = builder.build();
+        let enabled = |target: &str, level: log::Level| {
+            log::Log::enabled(
+                &logger,
+                &log::Metadata::builder().target(target).level(level).build(),
+            )
+        };
+
+        assert!(!enabled(
+            "nimi::process_manager::supervisor",
+            log::Level::Info
+        ));
+        assert!(!enabled("nimi::config", log::Level::Debug));
+        assert!(enabled("nimi::process_manager", log::Level::Warn));
+        // A service called like the crate keeps the level of the other services
+        assert!(enabled("nimi", log::Level::Debug));
+        assert!(enabled("web", log::Level::Debug));
+    }
 }
@@ -0,0 +1,124 @@
+//! PID file support for external supervisors and health tooling
+
+use std::path::{Path, PathBuf};
+
+use eyre::{Context, OptionExt, Result};
+use log::{debug, warn};
+use nix::{errno::Errno, sys::signal::kill, unistd::Pid};
+use tokio::fs;
+
+/// Guard for a PID file containing the PID of nimi
+///
+/// The file is removed again when the guard is dropped
+pub struct PidFile(PathBuf);
+
+impl PidFile {
+    /// Atomically write the PID of the current process to `path`
+    ///
+    /// Fails if the file already exists and refers to a process which is still alive
+    pub async fn create(path: &Path) -> Result<Self> {
+        if let Some(pid) = Self::read_existing(path).await?
+            && Self::is_alive(pid)
+        {
+            eyre::bail!(
+                "PID file {path:?} belongs to running process {pid}, is nimi already running?"
+            );
+        }
+
+        let file_name = path
+            .file_name()
+            .ok_or_eyre(format!("PID file path has no file name: {path:?}"))?;
+        let tmp_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+
+        fs::write(&tmp_path, format!("{}\n", std::process::id()))
+            .await
+            .wrap_err_with(|| format!("Failed to write temporary PID file {tmp_path:?}"))?;
+        fs::rename(&tmp_path, path)
+            .await
+            .wrap_err_with(|| format!("Failed to move PID file into place at {path:?}"))?;
+
+        debug!("Wrote PID file {path:?}");
+
+        Ok(Self(path.to_owned()))
+    }
+
+    async fn read_existing(path: &Path) -> Result<Option<i32>> {
+        match fs::read_to_string(path).await {
+            Ok(contents) => Ok(contents.trim().parse().ok()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).wrap_err_with(|| format!("Failed to read PID file {path:?}")),
+        }
+    }
+
+    fn is_alive(pid: i32) -> bool {
+        if pid <= 0 || pid as u32 == std::process::id() {
+            return false;
+        }
+
+        matches!(kill(Pid::from_raw(pid), None), Ok(()) | Err(Errno::EPERM))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Failed to remove PID file {:?}: {e}", self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn pid_file_holds_the_pid_until_dropped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("nimi.pid");
+
+        let pid_file = PidFile::create(&path).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn pid_file_of_a_running_process_is_refused() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("nimi.pid");
+        let mut running = Command::new("sleep").arg("30").spawn().unwrap();
+        std::fs::write(&path, format!("{}\n", running.id())).unwrap();
+
+        let res = PidFile::create(&path).await;
+        running.kill().unwrap();
+        running.wait().unwrap();
+
+        assert!(res.is_err());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", running.id())
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_pid_file_is_replaced() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("nimi.pid");
+        let mut exited = Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        std::fs::write(&path, format!("{}\n", exited.id())).unwrap();
+
+        let _pid_file = PidFile::create(&path).await.unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+    }
+}
@@ -3,24 +3,61 @@
 //! Can take a rust represntation of some `NixOS` modular services
 //! and runs them streaming logs back to the original console.
 
-use eyre::{Context, Result};
+use eyre::{Context, Result, eyre};
 use futures::future::OptionFuture;
-use log::{debug, info};
+use log::{Level, LevelFilter, debug, error, info, warn};
+use nix::{
+    sys::signal::Signal,
+    unistd::{AccessFlags, access},
+};
 use std::process::Stdio;
-use std::{collections::HashMap, env, io::ErrorKind, path::PathBuf, sync::Arc};
+use std::sync::OnceLock;
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::signal::unix::{SignalKind, signal};
-use tokio::{fs, process::Command, task::JoinSet};
+use tokio::{
+    fs,
+    process::Command,
+    sync::{broadcast, mpsc},
+    task::JoinSet,
+};
 use tokio_util::sync::CancellationToken;
 
+pub mod control;
+pub mod dependency_graph;
+pub mod notify;
+pub mod plan;
 pub mod service;
 pub mod service_manager;
 pub mod settings;
-
-pub use service::Service;
+pub mod status;
+pub mod supervisor;
+pub mod watcher;
+
+pub use dependency_graph::DependencyGraph;
+pub use notify::Notify;
+pub use plan::Plan;
+pub use service::{Service, ServiceType};
 pub use service_manager::ServiceManager;
 pub use settings::Settings;
+pub use status::StatusBoard;
+pub use supervisor::Supervisor;
+
+use crate::process_manager::settings::FailurePolicy;
 
-use crate::process_manager::service_manager::{Logger, ServiceError, ServiceManagerOpts};
+use crate::config::{Config, ConfigSources};
+use crate::process_manager::control::{ControlAction, ControlRequest, ControlServer};
+use crate::process_manager::notify::NOTIFY_SOCKET;
+use crate::process_manager::service_manager::{LogOutput, LogSink, Logger, ServiceError};
+use crate::process_manager::status::{ShutdownSummary, StatusServer};
+use crate::process_manager::supervisor::SupervisorOpts;
 use crate::subreaper::Subreaper;
 
 /// Process Manager Struct
@@ -28,53 +65,206 @@ use crate::subreaper::Subreaper;
 /// Responsible for starting the services and streaming their outputs to the console
 pub struct ProcessManager {
     services: HashMap<String, Service>,
-    settings: Settings,
+    settings: Arc<Settings>,
+
+    shutdown_signal: Arc<OnceLock<Signal>>,
+    drain_tok: CancellationToken,
+    drain_timeout: Option<Duration>,
+    forwarded_signals: broadcast::Sender<Signal>,
+    config_sources: Option<ConfigSources>,
+    log_output: LogOutput,
+
+    status: StatusBoard,
+    status_addr: Option<SocketAddr>,
+    control_socket: Option<PathBuf>,
+    runtime_dir: Option<PathBuf>,
+    dry_run: bool,
+    keep_alive: bool,
+    summary_output: Option<Box<dyn io::Write + Send + Sync>>,
 }
 
 impl ProcessManager {
     /// Create a new process manager instance
     pub fn new(services: HashMap<String, Service>, settings: Settings) -> Self {
-        Self { services, settings }
+        let mut log_output = LogOutput::default();
+        log_output.max_line_length = settings.logging.max_line_length;
+
+        Self {
+            services,
+            settings: Arc::new(settings),
+            shutdown_signal: Arc::new(OnceLock::new()),
+            drain_tok: CancellationToken::new(),
+            drain_timeout: None,
+            forwarded_signals: broadcast::Sender::new(16),
+            config_sources: None,
+            log_output,
+
+            status: StatusBoard::default(),
+            status_addr: None,
+            control_socket: None,
+            runtime_dir: None,
+            dry_run: false,
+            keep_alive: false,
+            summary_output: None,
+        }
+    }
+
+    /// Set the config files the services were read from
+    ///
+    /// Enables reloading the services from these files on `SIGHUP`
+    pub fn with_config_sources(mut self, config_sources: ConfigSources) -> Self {
+        self.config_sources = Some(config_sources);
+        self
+    }
+
+    /// Prefix every log line of the services with a timestamp and their name
+    pub fn with_log_prefix(mut self, log_prefix: bool) -> Self {
+        self.log_output.prefix = log_prefix;
+        self
+    }
+
+    /// Set where the output of the services gets printed to
+    pub fn with_log_sink(mut self, log_sink: LogSink) -> Self {
+        self.log_output.sink = log_sink;
+        self
+    }
+
+    /// Serve the status of the services over HTTP on `status_addr`
+    pub fn with_status_addr(mut self, status_addr: SocketAddr) -> Self {
+        self.status_addr = Some(status_addr);
+        self
+    }
+
+    /// Accept control commands on a unix socket at `control_socket`
+    pub fn with_control_socket(mut self, control_socket: PathBuf) -> Self {
+        self.control_socket = Some(control_socket);
+        self
+    }
+
+    /// Create the config directories of the services inside of `runtime_dir`
+    pub fn with_runtime_dir(mut self, runtime_dir: PathBuf) -> Self {
+        self.runtime_dir = Some(runtime_dir);
+        self
+    }
+
+    /// Drain the services for up to `drain_timeout` before shutting them down
+    ///
+    /// On the first `SIGINT` or `SIGTERM` services are no longer restarted and get the
+    /// chance to exit on their own. They are stopped once the timeout passes or another
+    /// signal is received
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = Some(drain_timeout);
+        self
+    }
+
+    /// Only print the plan of what would be spawned when running, see `Plan`
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Keep running after every service exited, until nimi is told to shut down
+    pub fn with_keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Write the `ShutdownSummary` to `summary_output` right before `run` returns
+    ///
+    /// Written after the output of every service, no summary is written when unset
+    pub fn with_summary_output(mut self, summary_output: Box<dyn io::Write + Send + Sync>) -> Self {
+        self.summary_output = Some(summary_output);
+        self
+    }
+
+    /// Directory the config directories of the services are created in
+    ///
+    /// Falls back to `$XDG_RUNTIME_DIR` and then to the system temp directory
+    fn runtime_dir(&self) -> PathBuf {
+        self.runtime_dir
+            .clone()
+            .or_else(|| {
+                env::var_os("XDG_RUNTIME_DIR")
+                    .map(PathBuf::from)
+                    .filter(|dir| dir.is_absolute())
+            })
+            .unwrap_or_else(env::temp_dir)
     }
 
-    async fn run_startup_process(&self, bin: &str, cancel_tok: &CancellationToken) -> Result<()> {
+    /// Check that config directories can be created in the runtime directory
+    fn check_runtime_dir(&self) -> Result<()> {
+        let runtime_dir = self.runtime_dir();
+
+        eyre::ensure!(
+            runtime_dir.is_dir(),
+            "Runtime directory {runtime_dir:?} doesn't exist or is not a directory"
+        );
+        access(&runtime_dir, AccessFlags::W_OK | AccessFlags::X_OK)
+            .wrap_err_with(|| format!("Runtime directory {runtime_dir:?} is not writable"))
+    }
+
+    async fn run_startup_process(
+        &self,
+        bin: &str,
+        args: &[String],
+        cancel_tok: &CancellationToken,
+    ) -> Result<()> {
         let mut set = JoinSet::new();
 
-        let _pause = Subreaper::pause_reaping();
-        let mut process = Command::new(bin)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .kill_on_drop(true)
-            .spawn()
-            .wrap_err_with(|| format!("Failed to spawn startup binary: {:?}", bin))?;
-        let _child_guard =
-            Subreaper::track_child(process.id()).wrap_err("Failed to track startup child")?;
+        let (mut process, _child_guard) = {
+            let _pause = Subreaper::pause_reaping();
+            let mut command = Command::new(bin);
+            ServiceManager::new_session(&mut command);
+            let process = command
+                .args(args)
+                .envs(&self.settings.startup.environment)
+                .env_remove(NOTIFY_SOCKET)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .wrap_err_with(|| format!("Failed to spawn startup binary: {:?}", bin))?;
+            let guard =
+                Subreaper::track_child(process.id()).wrap_err("Failed to track startup child")?;
+
+            (process, guard)
+        };
 
         let name = Arc::new("startup".to_owned());
-        let logs_dir = Arc::from(None);
 
-        Logger::Stdout.start(
+        Logger::Stdout(Level::Debug).start(
             &mut process.stdout,
             Arc::clone(&name),
-            Arc::clone(&logs_dir),
+            None,
+            self.log_output.clone(),
+            LevelFilter::Trace,
             &mut set,
         )?;
-        Logger::Stderr.start(
+        Logger::Stderr(Level::Error).start(
             &mut process.stderr,
             Arc::clone(&name),
-            Arc::clone(&logs_dir),
+            None,
+            self.log_output.clone(),
+            LevelFilter::Trace,
             &mut set,
         )?;
 
         tokio::select! {
             _ = cancel_tok.cancelled() => {
-                debug!(target: &name, "Received shutdown signal");
-                ServiceManager::shutdown_process(&mut process, self.settings.restart.time).await?;
+                debug!("Startup binary received shutdown signal");
+                ServiceManager::shutdown_process(
+                    &mut process,
+                    ServiceManager::forwarded_signal(&self.shutdown_signal),
+                    self.settings.shutdown.timeout,
+                    self.settings.shutdown.kill_timeout,
+                )
+                .await?;
             }
             status = process.wait() => {
                 let status = status.wrap_err("Failed to get process status")?;
+                // On Ctrl-C the startup binary may get the signal at the same time as nimi
                 eyre::ensure!(
-                    status.success(),
+                    status.success() || cancel_tok.is_cancelled(),
                     ServiceError::ProcessExited { status }
                 );
             }
@@ -111,17 +301,15 @@ impl ProcessManager {
 
     /// Spawn Child Processes
     ///
-    /// Spawns every service this process manager manages into a `JoinSet`
+    /// Spawns every service this process manager manages into a `Supervisor`
     pub async fn spawn_child_processes(
-        self,
+        &mut self,
         cancel_tok: &CancellationToken,
-    ) -> Result<JoinSet<Result<()>>> {
-        let mut join_set = tokio::task::JoinSet::new();
-
-        let settings = Arc::new(self.settings);
+        requests: mpsc::Sender<ControlRequest>,
+    ) -> Result<Supervisor> {
         let logs_dir = Arc::new(
             OptionFuture::from(
-                settings
+                self.settings
                     .logging
                     .logs_dir
                     .as_deref()
@@ -130,69 +318,647 @@ impl ProcessManager {
             .await
             .transpose()?,
         );
-        let tmp_dir = Arc::new(env::temp_dir());
+        let tmp_dir = Arc::new(self.runtime_dir());
 
-        for (name, service) in self.services {
-            let opts = ServiceManagerOpts {
-                logs_dir: Arc::clone(&logs_dir),
-                tmp_dir: Arc::clone(&tmp_dir),
+        let mut supervisor = Supervisor::new(SupervisorOpts {
+            logs_dir,
+            log_output: self.log_output.clone(),
+            tmp_dir,
 
-                settings: Arc::clone(&settings),
+            settings: Arc::clone(&self.settings),
+            shutdown_signal: Arc::clone(&self.shutdown_signal),
+            forwarded_signals: self.forwarded_signals.clone(),
+            status: self.status.clone(),
 
-                name: Arc::new(name),
-                service,
-                cancel_tok: cancel_tok.clone(),
-            };
+            cancel_tok: cancel_tok.clone(),
+            drain_tok: self.drain_tok.clone(),
+            requests,
+        });
+        supervisor.spawn(std::mem::take(&mut self.services)).await?;
+
+        Ok(supervisor)
+    }
+
+    /// Re-read the config file and reconcile the running services with it
+    async fn reload(&self, supervisor: &mut Supervisor) -> Result<()> {
+        let Some(config_sources) = &self.config_sources else {
+            return Ok(());
+        };
+
+        let config = Config::load(config_sources).await?;
 
-            join_set.spawn(async move { ServiceManager::new(opts).await?.run().await });
+        if serde_json::to_value(&config.settings)? != serde_json::to_value(&*self.settings)? {
+            warn!("Changes to settings are only applied after restarting nimi");
         }
 
-        Ok(join_set)
+        supervisor.reload(config.services).await
     }
 
     fn spawn_shutdown_task(&self, cancel_tok: &CancellationToken) {
         let token = cancel_tok.clone();
+        let shutdown_signal = Arc::clone(&self.shutdown_signal);
+        let drain = self
+            .drain_timeout
+            .map(|timeout| (self.drain_tok.clone(), timeout));
         tokio::spawn(async move {
             let mut sigterm =
                 signal(SignalKind::terminate()).wrap_err("Failed to register SIGTERM handler")?;
-            tokio::select! {
-                _ = tokio::signal::ctrl_c() => {},
-                _ = sigterm.recv() => {},
+            let mut sigint =
+                signal(SignalKind::interrupt()).wrap_err("Failed to register SIGINT handler")?;
+            let mut received = tokio::select! {
+                _ = sigint.recv() => Signal::SIGINT,
+                _ = sigterm.recv() => Signal::SIGTERM,
+            };
+
+            if let Some((drain_tok, timeout)) = drain {
+                info!("Received {received}, draining services for up to {timeout:?}...");
+                drain_tok.cancel();
+
+                tokio::select! {
+                    _ = tokio::time::sleep(timeout) => {
+                        info!("Services didn't drain in time, shutting them down...");
+                    }
+                    _ = sigint.recv() => {
+                        received = Signal::SIGINT;
+                        info!("Received {received} while draining, shutting down services...");
+                    }
+                    _ = sigterm.recv() => {
+                        received = Signal::SIGTERM;
+                        info!("Received {received} while draining, shutting down services...");
+                    }
+                }
+            } else {
+                info!("Received {received}, shutting down services...");
             }
+
+            let _ = shutdown_signal.set(received);
             token.cancel();
             Ok::<_, eyre::Report>(())
         });
     }
 
+    /// Relay every signal in `settings.forward_signals` to the running services
+    fn spawn_forward_tasks(&self) -> Result<()> {
+        for &forwarded in &self.settings.forward_signals {
+            let mut received = signal(SignalKind::from_raw(forwarded as i32))
+                .wrap_err_with(|| format!("Failed to register {forwarded} handler"))?;
+            let forwarded_signals = self.forwarded_signals.clone();
+            tokio::spawn(async move {
+                while received.recv().await.is_some() {
+                    debug!("Received {forwarded}, forwarding it to the services");
+                    let _ = forwarded_signals.send(forwarded);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    fn spawn_notify_task(supervisor: &Supervisor, cancel_tok: &CancellationToken) {
+        let all_ready = supervisor.wait_all_ready();
+        let token = cancel_tok.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = all_ready => {
+                    if let Err(e) = Notify::ready() {
+                        warn!("Failed to notify service manager about readiness: {e:?}");
+                    }
+                    token.cancelled().await;
+                },
+                _ = token.cancelled() => {},
+            }
+
+            if let Err(e) = Notify::stopping() {
+                warn!("Failed to notify service manager about shutdown: {e:?}");
+            }
+        });
+    }
+
     /// Run the services defined for the process manager instance
     ///
-    /// Terminates on `Ctrl-C`
-    pub async fn run(self) -> Result<()> {
+    /// Terminates on `SIGINT` or `SIGTERM`, reloads the services on `SIGHUP`
+    /// if a config path was set
+    pub async fn run(mut self) -> Result<()> {
         info!("Starting process manager...");
 
+        DependencyGraph::new(&self.services).wrap_err("Invalid service dependencies")?;
+        self.check_runtime_dir()?;
+
+        if self.dry_run {
+            let runtime_dir = self.runtime_dir();
+            print!(
+                "{}",
+                Plan::new(&self.services, &self.settings, &runtime_dir).render()?
+            );
+            return Ok(());
+        }
+
+        let log_writer = self.log_output.start_writer();
+        let res = self.supervise().await;
+        self.log_output.stop_writer(log_writer).await;
+
+        if let Some(summary_output) = self.summary_output.take() {
+            let summary = ShutdownSummary::new(&self.status, res.as_ref().err());
+            if let Err(e) = summary.write_to(summary_output) {
+                warn!("{e:#}");
+            }
+        }
+
+        res
+    }
+
+    /// Run the startup commands and then the services until they have all stopped
+    async fn supervise(&mut self) -> Result<()> {
         let cancel_tok = CancellationToken::new();
         self.spawn_shutdown_task(&cancel_tok);
+        self.spawn_forward_tasks()?;
+
+        // The server keeps answering until the services have stopped
+        let status_tok = CancellationToken::new();
+        let _status_guard = status_tok.clone().drop_guard();
+        if let Some(status_addr) = self.status_addr {
+            let server = StatusServer::bind(status_addr, self.status.clone()).await?;
+            tokio::spawn(server.serve(status_tok.clone()));
+        }
 
-        if let Some(startup) = &self.settings.startup.run_on_startup {
-            info!("Running startup binary ({})...", startup);
-            self.run_startup_process(startup, &cancel_tok)
-                .await
-                .wrap_err("Failed to run startup process")?;
+        let (control_tx, mut control_rx) = mpsc::channel(8);
+        let watch_tx = control_tx.clone();
+        let _control_socket = match &self.control_socket {
+            Some(path) => Some(
+                ControlServer::bind(path, self.status.clone(), control_tx)?.spawn(path, status_tok),
+            ),
+            None => None,
+        };
+
+        let startup = &self.settings.startup;
+        let startup_commands = startup
+            .run_on_startup
+            .iter()
+            .map(|bin| (bin.as_str(), &[][..]))
+            .chain(
+                startup
+                    .commands
+                    .iter()
+                    .map(|argv| (argv.binary(), argv.args())),
+            );
+        for (bin, args) in startup_commands {
+            info!("Running startup binary ({})...", bin);
+            match self.run_startup_process(bin, args, &cancel_tok).await {
+                Err(e)
+                    if startup.ignore_failure
+                        && matches!(e.downcast_ref(), Some(ServiceError::ProcessExited { .. })) =>
+                {
+                    error!("Startup binary {bin} failed, continuing anyway: {e}");
+                }
+                res => res.wrap_err("Failed to run startup process")?,
+            }
+
+            if cancel_tok.is_cancelled() || self.drain_tok.is_cancelled() {
+                info!("Received shutdown during startup, not starting services");
+                return Ok(());
+            }
         }
 
-        let mut services_set = self.spawn_child_processes(&cancel_tok).await?;
+        let mut failure_policy = self.settings.failure_policy;
+        let mut sighup = self
+            .config_sources
+            .as_ref()
+            .map(|_| signal(SignalKind::hangup()))
+            .transpose()
+            .wrap_err("Failed to register SIGHUP handler")?;
+        let mut supervisor = self.spawn_child_processes(&cancel_tok, watch_tx).await?;
+        Self::spawn_notify_task(&supervisor, &cancel_tok);
+
+        let startup_timeout = self.settings.startup.timeout;
+        let all_ready = supervisor.wait_all_ready();
+        let startup_deadline = OptionFuture::from(
+            startup_timeout.map(|timeout| tokio::time::timeout(timeout, all_ready)),
+        );
+        tokio::pin!(startup_deadline);
+        let mut started = startup_timeout.is_none();
+
+        let mut first_failure = None;
+        // Set once every service exited while nimi is kept alive
+        let mut idle = false;
+        let drain_tok = self.drain_tok.clone();
 
-        while let Some(res) = services_set.join_next().await {
-            let flat: Result<()> = res.map_err(Into::into).and_then(std::convert::identity);
+        loop {
+            let res = tokio::select! {
+                res = supervisor.join_next(), if !idle => match res {
+                    Some(res) => res,
+                    None if self.keep_alive && !drain_tok.is_cancelled() => {
+                        info!("Every service has exited, keeping nimi running until it is stopped");
+                        idle = true;
+                        continue;
+                    }
+                    None => break,
+                },
+                Some(res) = &mut startup_deadline, if !started => {
+                    started = true;
+                    if res.is_ok() {
+                        continue;
+                    }
+
+                    // Every service is stopped, whatever the failure policy
+                    failure_policy = FailurePolicy::StopAll;
+                    Err(eyre!(
+                        "Services weren't all ready within the startup timeout of {:?}",
+                        startup_timeout.unwrap_or_default()
+                    ))
+                }
+                _ = cancel_tok.cancelled(), if idle => break,
+                _ = drain_tok.cancelled(), if idle => break,
+                Some(Some(())) = OptionFuture::from(sighup.as_mut().map(|s| s.recv())) => {
+                    info!("Received SIGHUP, reloading services...");
+                    if let Err(e) = self.reload(&mut supervisor).await {
+                        error!("Failed to reload services: {e:?}");
+                    }
+                    idle = false;
+
+                    continue;
+                }
+                Some(request) = control_rx.recv() => {
+                    let res = match &request.action {
+                        ControlAction::Restart(_) if self.drain_tok.is_cancelled() => {
+                            Err(eyre!("Services are draining, not restarting"))
+                        }
+                        ControlAction::Restart(name) => supervisor.restart(name).await,
+                        ControlAction::Stop(name) => supervisor.stop(name),
+                    };
+                    let _ = request.reply.send(res);
+                    idle = false;
+
+                    continue;
+                }
+            };
 
-            if let Err(e) = flat {
-                cancel_tok.cancel();
-                return Err(e);
+            if let Err(e) = res {
+                match failure_policy {
+                    FailurePolicy::StopAll => {
+                        cancel_tok.cancel();
+                        // Let the remaining services stop gracefully and clean up after themselves
+                        while supervisor.join_next().await.is_some() {}
+
+                        first_failure.get_or_insert(e);
+                        break;
+                    }
+                    FailurePolicy::Ignore | FailurePolicy::RestartOnly => {
+                        error!("{e:?}");
+                        info!(
+                            "Keeping remaining services running (failure policy: {failure_policy})"
+                        );
+
+                        first_failure.get_or_insert(e);
+                    }
+                }
             }
         }
 
         info!("Shutting down process manager...");
 
-        Ok(())
+        first_failure.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::process_manager::{settings::Startup, status::ServiceState};
+
+    /// Service appending `line` to the `order` file in `tmp_dir` and exiting
+    fn recording_service(tmp_dir: &Path, line: &str) -> Service {
+        serde_json::from_value(json!({
+            "configData": {},
+            "process": { "argv": ["sh", "-c", record(tmp_dir, line)] },
+            "restart": { "mode": "never", "time": 10, "count": 0 },
+        }))
+        .unwrap()
+    }
+
+    /// Shell script appending `line` to the `order` file in `tmp_dir`
+    fn record(tmp_dir: &Path, line: &str) -> String {
+        format!("echo {line} >> {}", tmp_dir.join("order").display())
+    }
+
+    /// Lines written by `record`
+    fn recorded(tmp_dir: &Path) -> Vec<String> {
+        std::fs::read_to_string(tmp_dir.join("order"))
+            .map(|order| order.lines().map(str::to_owned).collect())
+            .unwrap_or_default()
+    }
+
+    /// Writer collecting what is written to any of its clones
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Run a process manager with `startup` and a single recording service
+    async fn run_with_startup(tmp_dir: &Path, startup: serde_json::Value) -> Result<()> {
+        let settings = Settings {
+            startup: serde_json::from_value::<Startup>(startup).unwrap(),
+            ..Settings::default()
+        };
+
+        ProcessManager::new(
+            HashMap::from([("svc".to_owned(), recording_service(tmp_dir, "service"))]),
+            settings,
+        )
+        .with_runtime_dir(tmp_dir.to_owned())
+        .run()
+        .await
+    }
+
+    #[tokio::test]
+    async fn services_start_after_the_startup_commands() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        run_with_startup(
+            tmp.path(),
+            json!({
+                "runOnStartup": "true",
+                "commands": [["sh", "-c", record(tmp.path(), "startup")]],
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(recorded(tmp.path()), ["startup", "service"]);
+    }
+
+    #[tokio::test]
+    async fn a_failing_startup_binary_aborts_nimi() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let err = run_with_startup(tmp.path(), json!({ "commands": [["sh", "-c", "exit 3"]] }))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ServiceError::ProcessExited { status }) if status.code() == Some(3)
+        ));
+        assert!(recorded(tmp.path()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_ignored_startup_failure_still_starts_the_services() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        run_with_startup(
+            tmp.path(),
+            json!({ "commands": [["sh", "-c", "exit 3"]], "ignoreFailure": true }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(recorded(tmp.path()), ["service"]);
+    }
+
+    #[tokio::test]
+    async fn a_shutdown_stops_a_hung_startup_binary() {
+        let manager = ProcessManager::new(HashMap::new(), Settings::default());
+        let cancel_tok = CancellationToken::new();
+        let canceller = cancel_tok.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            canceller.cancel();
+        });
+
+        let started_at = std::time::Instant::now();
+        manager
+            .run_startup_process("sleep", &["30".to_owned()], &cancel_tok)
+            .await
+            .unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn startup_commands_run_in_order_and_stop_at_the_first_failure() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let err = run_with_startup(
+            tmp.path(),
+            json!({
+                "commands": [
+                    ["sh", "-c", record(tmp.path(), "first")],
+                    ["sh", "-c", record(tmp.path(), "second")],
+                    ["false"],
+                    ["sh", "-c", record(tmp.path(), "unreachable")],
+                ],
+            }),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), "Failed to run startup process");
+        assert_eq!(recorded(tmp.path()), ["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn startup_commands_get_their_arguments_and_environment() {
+        let tmp = tempfile::tempdir().unwrap();
+        let order = tmp.path().join("order");
+
+        run_with_startup(
+            tmp.path(),
+            json!({
+                "commands": [[
+                    "sh",
+                    "-c",
+                    format!("echo \"$GREETING $1\" >> {}", order.display()),
+                    "sh",
+                    "world",
+                ]],
+                "environment": { "GREETING": "hello" },
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(recorded(tmp.path()), ["hello world", "service"]);
+    }
+
+    #[tokio::test]
+    async fn config_directories_are_created_in_the_runtime_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let runtime_dir = tmp.path().join("runtime");
+        std::fs::create_dir(&runtime_dir).unwrap();
+        let service = serde_json::from_value(json!({
+            "configData": {},
+            "process": {
+                "argv": ["sh", "-c", format!("echo ${{NIMI_CONFIG_DIR}} >> {}", tmp.path().join("order").display())],
+            },
+            "restart": { "mode": "never", "time": 10, "count": 0 },
+        }))
+        .unwrap();
+
+        ProcessManager::new(
+            HashMap::from([("svc".to_owned(), service)]),
+            Settings::default(),
+        )
+        .with_runtime_dir(runtime_dir.clone())
+        .run()
+        .await
+        .unwrap();
+
+        let [config_dir] = &recorded(tmp.path())[..] else {
+            panic!("the service should have run once");
+        };
+        assert_eq!(Path::new(config_dir).parent(), Some(runtime_dir.as_path()));
+    }
+
+    #[test]
+    fn a_missing_runtime_dir_is_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let runtime_dir = tmp.path().join("missing");
+
+        let err = ProcessManager::new(HashMap::new(), Settings::default())
+            .with_runtime_dir(runtime_dir.clone())
+            .check_runtime_dir()
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!("Runtime directory {runtime_dir:?} doesn't exist or is not a directory")
+        );
+    }
+
+    #[tokio::test]
+    async fn the_summary_names_the_crashed_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let crashing = serde_json::from_value(json!({
+            "configData": {},
+            "process": { "argv": ["sh", "-c", "sleep 0.2; exit 3"] },
+            "restart": { "mode": "never", "time": 10, "count": 0 },
+        }))
+        .unwrap();
+        let manager = ProcessManager::new(
+            HashMap::from([
+                ("clean".to_owned(), recording_service(tmp.path(), "clean")),
+                ("crashing".to_owned(), crashing),
+            ]),
+            Settings::default(),
+        )
+        .with_runtime_dir(tmp.path().to_owned());
+        let out = SharedOutput::default();
+
+        let err = manager
+            .with_summary_output(Box::new(out.clone()))
+            .run()
+            .await
+            .unwrap_err();
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        let summary: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(summary["failedService"], "crashing");
+        assert_eq!(summary["error"], format!("{err:#}"));
+        let services = summary["services"].as_array().unwrap();
+        let service = |name: &str| services.iter().find(|s| s["name"] == name).unwrap();
+        assert_eq!(service("clean")["state"], "stopped");
+        assert_eq!(service("clean")["lastExitCode"], 0);
+        assert_eq!(service("crashing")["state"], "failed");
+        assert_eq!(service("crashing")["lastExitCode"], 3);
+        assert_eq!(service("crashing")["restarts"], 0);
+    }
+
+    #[tokio::test]
+    async fn keep_alive_keeps_nimi_running_after_a_oneshot_completed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let oneshot = serde_json::from_value(json!({
+            "type": "oneshot",
+            "configData": {},
+            "process": { "argv": ["sh", "-c", record(tmp.path(), "oneshot")] },
+        }))
+        .unwrap();
+        let manager = ProcessManager::new(
+            HashMap::from([("oneshot".to_owned(), oneshot)]),
+            Settings::default(),
+        )
+        .with_runtime_dir(tmp.path().to_owned())
+        .with_keep_alive(true);
+        let drain_tok = manager.drain_tok.clone();
+        let status = manager.status.clone();
+        let run = tokio::spawn(manager.run());
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while status.state("oneshot") != Some(ServiceState::Stopped) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        assert!(!run.is_finished());
+        assert_eq!(recorded(tmp.path()), ["oneshot"]);
+        drain_tok.cancel();
+        tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_service_which_never_becomes_ready_hits_the_startup_timeout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let service = |readiness: serde_json::Value| -> Service {
+            serde_json::from_value(json!({
+                "configData": {},
+                "process": { "argv": ["sleep", "30"] },
+                "readiness": readiness,
+                "restart": { "mode": "never", "time": 10, "count": 0 },
+            }))
+            .unwrap()
+        };
+        let settings = Settings {
+            startup: serde_json::from_value::<Startup>(json!({ "timeout": 300 })).unwrap(),
+            failure_policy: FailurePolicy::Ignore,
+            ..Settings::default()
+        };
+        let manager = ProcessManager::new(
+            HashMap::from([
+                ("ready".to_owned(), service(serde_json::Value::Null)),
+                (
+                    "never".to_owned(),
+                    service(json!({
+                        "command": ["false"],
+                        "interval": 10,
+                        "timeout": 1000,
+                        "retries": 100000,
+                    })),
+                ),
+            ]),
+            settings,
+        )
+        .with_runtime_dir(tmp.path().to_owned());
+        let status = manager.status.clone();
+
+        let started_at = std::time::Instant::now();
+        let err = manager.run().await.unwrap_err();
+
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            err.to_string(),
+            "Services weren't all ready within the startup timeout of 300ms"
+        );
+        for name in ["ready", "never"] {
+            assert_ne!(status.state(name), Some(ServiceState::Running), "{name}");
+        }
     }
 }
@@ -0,0 +1,317 @@
+//! Control Module
+//!
+//! Unix socket which lets operators inspect and restart services of a running nimi
+//!
+//! Every line sent to the socket is a command, answered with a single line:
+//!
+//! - `list`: JSON list of every service and its state
+//! - `status <name>`: JSON state of a single service
+//! - `restart <name>`: stop the service and start it again
+//! - `stop <name>`: stop the service
+//!
+//! Actions answer `ok` or `error: <reason>`.
+
+use std::{
+    fs::Permissions,
+    io::ErrorKind,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context, Result, eyre};
+use log::{debug, info, warn};
+use nix::unistd::mkdtemp;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{mpsc, oneshot},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::process_manager::status::StatusBoard;
+
+/// Action on a service requested through the control socket
+#[derive(Debug)]
+pub enum ControlAction {
+    /// Stop the service and start it again
+    Restart(String),
+
+    /// Stop the service
+    Stop(String),
+}
+
+/// Request for the process manager to perform a `ControlAction`
+#[derive(Debug)]
+pub struct ControlRequest {
+    /// The action to perform
+    pub action: ControlAction,
+
+    /// Receives the outcome of the action
+    pub reply: oneshot::Sender<Result<()>>,
+}
+
+/// Server accepting commands on the control socket
+pub struct ControlServer {
+    listener: UnixListener,
+    board: StatusBoard,
+    requests: mpsc::Sender<ControlRequest>,
+}
+
+/// Guard for the control socket file, which is removed when dropped
+pub struct ControlSocket(PathBuf);
+
+impl ControlServer {
+    /// Bind the control socket at `path`
+    ///
+    /// A socket left behind by an earlier run is replaced. The socket is only accessible
+    /// to the user nimi runs as
+    pub fn bind(
+        path: &Path,
+        board: StatusBoard,
+        requests: mpsc::Sender<ControlRequest>,
+    ) -> Result<Self> {
+        match std::fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)
+                .wrap_err_with(|| format!("Failed to remove stale control socket {path:?}"))?,
+            Ok(_) => eyre::bail!("Control socket path {path:?} exists and is not a socket"),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).wrap_err_with(|| format!("Failed to read metadata of {path:?}"));
+            }
+        }
+
+        let listener = Self::bind_private(path)?;
+        info!("Listening for control commands on {path:?}");
+
+        Ok(Self {
+            listener,
+            board,
+            requests,
+        })
+    }
+
+    /// Bind a socket only the user nimi runs as can connect to at `path`
+    ///
+    /// The socket is bound inside of a fresh `0700` directory next to `path` and only
+    /// moved into place once its permissions are restricted, so nobody else can connect
+    /// in between. Changing the umask instead would affect every thread of nimi
+    fn bind_private(path: &Path) -> Result<UnixListener> {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let dir = mkdtemp(&parent.join(".nimi-control-XXXXXX"))
+            .wrap_err_with(|| format!("Failed to create a directory to bind {path:?} in"))?;
+        let staged = dir.join("control.sock");
+
+        let res = UnixListener::bind(&staged)
+            .wrap_err_with(|| format!("Failed to bind control socket {path:?}"))
+            .and_then(|listener| {
+                std::fs::set_permissions(&staged, Permissions::from_mode(0o600)).wrap_err_with(
+                    || format!("Failed to set permissions of control socket {path:?}"),
+                )?;
+                std::fs::rename(&staged, path)
+                    .wrap_err_with(|| format!("Failed to move control socket to {path:?}"))?;
+
+                Ok(listener)
+            });
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            warn!("Failed to remove directory {dir:?} the control socket was bound in: {e}");
+        }
+
+        res
+    }
+
+    /// Accept connections in the background until `cancel_tok` is cancelled
+    pub fn spawn(self, path: &Path, cancel_tok: CancellationToken) -> ControlSocket {
+        tokio::spawn(async move {
+            loop {
+                let stream = tokio::select! {
+                    res = self.listener.accept() => match res {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            debug!("Failed to accept control connection: {e}");
+                            continue;
+                        }
+                    },
+                    _ = cancel_tok.cancelled() => break,
+                };
+
+                let board = self.board.clone();
+                let requests = self.requests.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = Self::handle(stream, &board, &requests).await {
+                        debug!("Failed to answer control command: {e:#}");
+                    }
+                });
+            }
+        });
+
+        ControlSocket(path.to_owned())
+    }
+
+    async fn handle(
+        stream: UnixStream,
+        board: &StatusBoard,
+        requests: &mpsc::Sender<ControlRequest>,
+    ) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            let mut response = Self::answer(line.trim(), board, requests).await;
+            response.push('\n');
+            writer.write_all(response.as_bytes()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn answer(
+        line: &str,
+        board: &StatusBoard,
+        requests: &mpsc::Sender<ControlRequest>,
+    ) -> String {
+        let (command, name) = line.split_once(' ').unwrap_or((line, ""));
+        let name = name.trim();
+
+        let res = match (command, name) {
+            ("list", "") => serde_json::to_string(&board.snapshot()).map_err(Into::into),
+            ("status", name) if !name.is_empty() => board
+                .snapshot()
+                .into_iter()
+                .find(|status| status.name == name)
+                .ok_or_else(|| eyre!("Unknown service {name}"))
+                .and_then(|status| Ok(serde_json::to_string(&status)?)),
+            ("restart", name) if !name.is_empty() => {
+                Self::request(ControlAction::Restart(name.to_owned()), requests).await
+            }
+            ("stop", name) if !name.is_empty() => {
+                Self::request(ControlAction::Stop(name.to_owned()), requests).await
+            }
+            _ => Err(eyre!("Unknown command {line:?}")),
+        };
+
+        res.unwrap_or_else(|e| format!("error: {e:#}"))
+    }
+
+    async fn request(
+        action: ControlAction,
+        requests: &mpsc::Sender<ControlRequest>,
+    ) -> Result<String> {
+        let (reply, outcome) = oneshot::channel();
+        requests
+            .send(ControlRequest { action, reply })
+            .await
+            .map_err(|_| eyre!("nimi is shutting down"))?;
+        outcome
+            .await
+            .map_err(|_| eyre!("nimi is shutting down"))??;
+
+        Ok("ok".to_owned())
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Failed to remove control socket {:?}: {e}", self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use tokio::io::AsyncBufRead;
+
+    use super::*;
+    use crate::process_manager::status::ServiceState;
+
+    /// Send `command` and read the single line answering it
+    async fn send(
+        writer: &mut (impl AsyncWriteExt + Unpin),
+        reader: &mut (impl AsyncBufRead + Unpin),
+        command: &str,
+    ) -> String {
+        writer
+            .write_all(format!("{command}\n").as_bytes())
+            .await
+            .unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+
+        line
+    }
+
+    #[tokio::test]
+    async fn the_socket_is_only_accessible_to_the_owner() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("control.sock");
+        let (requests, _) = mpsc::channel(1);
+
+        let _server = ControlServer::bind(&path, StatusBoard::default(), requests).unwrap();
+
+        let meta = std::fs::symlink_metadata(&path).unwrap();
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.mode() & 0o777, 0o600);
+        // The staging directory is gone again
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn commands_are_answered_over_the_socket() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("control.sock");
+        let board = StatusBoard::default();
+        board.set_state("web", ServiceState::Running);
+        let (requests, mut received) = mpsc::channel(1);
+        let cancel_tok = CancellationToken::new();
+        let _socket = ControlServer::bind(&path, board, requests)
+            .unwrap()
+            .spawn(&path, cancel_tok.clone());
+        tokio::spawn(async move {
+            while let Some(request) = received.recv().await {
+                let res = match request.action {
+                    ControlAction::Restart(name) if name == "web" => Ok(()),
+                    action => Err(eyre!("Can't {action:?}")),
+                };
+                let _ = request.reply.send(res);
+            }
+        });
+
+        let (reader, mut writer) = UnixStream::connect(&path).await.unwrap().into_split();
+        let mut reader = BufReader::new(reader);
+
+        let list = send(&mut writer, &mut reader, "list").await;
+        let list: serde_json::Value = serde_json::from_str(&list).unwrap();
+        assert_eq!(list[0]["name"], "web");
+        assert_eq!(list[0]["state"], "running");
+        assert_eq!(send(&mut writer, &mut reader, "restart web").await, "ok\n");
+        assert_eq!(
+            send(&mut writer, &mut reader, "stop web").await,
+            "error: Can't Stop(\"web\")\n"
+        );
+        assert_eq!(
+            send(&mut writer, &mut reader, "status db").await,
+            "error: Unknown service db\n"
+        );
+        assert_eq!(
+            send(&mut writer, &mut reader, "reload").await,
+            "error: Unknown command \"reload\"\n"
+        );
+        cancel_tok.cancel();
+    }
+
+    #[tokio::test]
+    async fn a_path_which_is_not_a_socket_is_left_alone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("control.sock");
+        std::fs::write(&path, "data").unwrap();
+        let (requests, _) = mpsc::channel(1);
+
+        assert!(ControlServer::bind(&path, StatusBoard::default(), requests).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+    }
+}
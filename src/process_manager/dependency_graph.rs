@@ -0,0 +1,300 @@
+//! Dependency Graph Module
+//!
+//! Validates the `after` and `conflicts` relationships between services
+
+use std::collections::{BTreeMap, HashMap};
+
+use eyre::{Result, eyre};
+
+use crate::process_manager::{Service, ServiceType};
+
+/// Graph of the `after` dependencies between services
+///
+/// Constructing one guarantees every dependency exists and that there are no cycles.
+/// Conflicting services can't both be longrunning, as those would never stop for each
+/// other
+pub struct DependencyGraph<'a> {
+    edges: BTreeMap<&'a str, Vec<&'a str>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    InProgress,
+    Done,
+}
+
+impl<'a> DependencyGraph<'a> {
+    /// Build and validate the dependency graph for a set of services
+    pub fn new(services: &'a HashMap<String, Service>) -> Result<Self> {
+        let mut edges = BTreeMap::new();
+
+        for (name, service) in services {
+            let mut dependencies = Vec::with_capacity(service.after.len());
+
+            for dependency in &service.after {
+                if !services.contains_key(dependency) {
+                    return Err(eyre!(
+                        "Service {name} is ordered after unknown service {dependency}"
+                    ));
+                }
+
+                dependencies.push(dependency.as_str());
+            }
+
+            edges.insert(name.as_str(), dependencies);
+        }
+
+        let graph = Self { edges };
+        graph.check_cycles()?;
+        Self::check_conflicts(services)?;
+
+        Ok(graph)
+    }
+
+    /// Order in which `services` are started
+    ///
+    /// Every service comes after the services it is ordered `after`, services which
+    /// aren't ordered against each other are sorted by name. Dependencies outside of
+    /// `services` are ignored, so this also orders a subset of the services on reload
+    pub fn start_order(services: &HashMap<String, Service>) -> Vec<&str> {
+        let mut pending: BTreeMap<_, _> = services.iter().collect();
+        let mut order = Vec::with_capacity(services.len());
+
+        while !pending.is_empty() {
+            let next = pending
+                .iter()
+                .find(|(_, service)| {
+                    service
+                        .after
+                        .iter()
+                        .all(|dependency| !pending.contains_key(dependency))
+                })
+                // Only reachable with a cycle, which `new` rejects
+                .or_else(|| pending.first_key_value())
+                .map(|(name, _)| *name);
+
+            if let Some(next) = next {
+                pending.remove(next);
+                order.push(next.as_str());
+            }
+        }
+
+        order
+    }
+
+    fn check_conflicts(services: &HashMap<String, Service>) -> Result<()> {
+        for (name, service) in services {
+            for conflict in &service.conflicts {
+                let Some(other) = services.get(conflict) else {
+                    return Err(eyre!(
+                        "Service {name} conflicts with unknown service {conflict}"
+                    ));
+                };
+
+                if service.kind == ServiceType::Longrunning
+                    && other.kind == ServiceType::Longrunning
+                {
+                    return Err(eyre!(
+                        "Longrunning services {name} and {conflict} conflict with each other"
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_cycles(&self) -> Result<()> {
+        let mut visits = HashMap::new();
+        let mut path = Vec::new();
+
+        for name in self.edges.keys() {
+            self.visit(name, &mut visits, &mut path)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit(
+        &self,
+        name: &'a str,
+        visits: &mut HashMap<&'a str, Visit>,
+        path: &mut Vec<&'a str>,
+    ) -> Result<()> {
+        match visits.get(name) {
+            Some(Visit::Done) => return Ok(()),
+            Some(Visit::InProgress) => {
+                let start = path.iter().position(|n| *n == name).unwrap_or_default();
+                let cycle = path[start..]
+                    .iter()
+                    .chain(std::iter::once(&name))
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+
+                return Err(eyre!("Dependency cycle between services: {cycle}"));
+            }
+            None => {}
+        }
+
+        visits.insert(name, Visit::InProgress);
+        path.push(name);
+
+        for dependency in &self.edges[name] {
+            self.visit(dependency, visits, path)?;
+        }
+
+        path.pop();
+        visits.insert(name, Visit::Done);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Services running `true`, each ordered after the given services
+    fn services(after: &[(&str, &[&str])]) -> HashMap<String, Service> {
+        after
+            .iter()
+            .map(|(name, after)| {
+                let service = json!({
+                    "configData": {},
+                    "process": { "argv": ["true"] },
+                    "after": after,
+                });
+
+                (name.to_string(), serde_json::from_value(service).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn services_start_after_their_dependencies() {
+        let services = services(&[
+            ("app", &["migrate"]),
+            ("migrate", &["db"]),
+            ("db", &[]),
+            ("cache", &[]),
+        ]);
+
+        DependencyGraph::new(&services).unwrap();
+
+        assert_eq!(
+            DependencyGraph::start_order(&services),
+            ["cache", "db", "migrate", "app"]
+        );
+    }
+
+    #[test]
+    fn cycles_are_rejected_naming_the_cycle() {
+        let services = services(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"])]);
+
+        let err = DependencyGraph::new(&services).err().unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "Dependency cycle between services: a -> b -> c -> a"
+        );
+    }
+
+    #[test]
+    fn unknown_dependencies_are_rejected() {
+        let services = services(&[("app", &["db"])]);
+
+        let err = DependencyGraph::new(&services).err().unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "Service app is ordered after unknown service db"
+        );
+    }
+
+    /// Services running `true` of the given type, each conflicting with the given services
+    fn conflicting(services: &[(&str, &str, &[&str])]) -> HashMap<String, Service> {
+        services
+            .iter()
+            .map(|(name, kind, conflicts)| {
+                let service = json!({
+                    "type": kind,
+                    "configData": {},
+                    "process": { "argv": ["true"] },
+                    "conflicts": conflicts,
+                });
+
+                (name.to_string(), serde_json::from_value(service).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn conflicting_services_do_not_need_to_be_ordered() {
+        let services = conflicting(&[
+            ("migrate", "oneshot", &["server"]),
+            ("server", "longrunning", &[]),
+        ]);
+
+        assert!(DependencyGraph::new(&services).is_ok());
+    }
+
+    #[test]
+    fn longrunning_services_may_not_conflict() {
+        let services = conflicting(&[("a", "longrunning", &["b"]), ("b", "longrunning", &[])]);
+
+        let err = DependencyGraph::new(&services).err().unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "Longrunning services a and b conflict with each other"
+        );
+    }
+
+    #[test]
+    fn unknown_conflicts_are_rejected() {
+        let services = conflicting(&[("a", "oneshot", &["b"])]);
+
+        let err = DependencyGraph::new(&services).err().unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "Service a conflicts with unknown service b"
+        );
+    }
+
+    #[test]
+    fn unordered_services_start_sorted_by_name() {
+        let services = services(&[("web", &[]), ("cache", &[]), ("db", &[]), ("api", &[])]);
+
+        assert_eq!(
+            DependencyGraph::start_order(&services),
+            ["api", "cache", "db", "web"]
+        );
+    }
+
+    #[test]
+    fn services_start_after_their_dependencies_and_by_name_otherwise() {
+        let services = services(&[
+            ("api", &["db", "migrate"]),
+            ("migrate", &["db"]),
+            ("db", &[]),
+            ("cache", &[]),
+            ("web", &["api"]),
+        ]);
+
+        assert_eq!(
+            DependencyGraph::start_order(&services),
+            ["cache", "db", "migrate", "api", "web"]
+        );
+    }
+
+    #[test]
+    fn dependencies_outside_of_the_services_are_ignored() {
+        let services = services(&[("web", &["db"]), ("api", &["web"])]);
+
+        assert_eq!(DependencyGraph::start_order(&services), ["web", "api"]);
+    }
+}
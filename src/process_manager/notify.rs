@@ -0,0 +1,121 @@
+//! Notify Module
+//!
+//! Implements the client side of the [`sd_notify`](https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html)
+//! protocol, so a supervising systemd (e.g. `Type=notify`) knows when nimi is up
+
+use std::{env, ffi::OsStr, io, os::unix::net::UnixDatagram, path::Path};
+
+use eyre::{Context, Result};
+use log::debug;
+
+/// Name of the environment variable holding the notification socket path
+pub const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+
+/// Sends service manager notifications
+pub struct Notify;
+
+impl Notify {
+    /// Tell the service manager that nimi and all its services are up
+    pub fn ready() -> Result<()> {
+        Self::send("READY=1")
+    }
+
+    /// Tell the service manager that nimi is shutting down
+    pub fn stopping() -> Result<()> {
+        Self::send("STOPPING=1")
+    }
+
+    /// Send a raw state string to the notification socket
+    ///
+    /// Silently does nothing when `$NOTIFY_SOCKET` is unset
+    pub fn send(state: &str) -> Result<()> {
+        let Some(socket_path) = env::var_os(NOTIFY_SOCKET) else {
+            return Ok(());
+        };
+
+        Self::send_to(&socket_path, state)
+    }
+
+    /// Send a raw state string to the socket at `socket_path`
+    ///
+    /// A path starting with `@` names an abstract socket
+    fn send_to(socket_path: &OsStr, state: &str) -> Result<()> {
+        let socket = UnixDatagram::unbound().wrap_err("Failed to create notify socket")?;
+
+        let bytes = socket_path.as_encoded_bytes();
+        match bytes.strip_prefix(b"@") {
+            Some(name) => Self::send_to_abstract(&socket, name, state),
+            None => socket.send_to(state.as_bytes(), Path::new(socket_path)),
+        }
+        .wrap_err_with(|| format!("Failed to send {state:?} to {socket_path:?}"))?;
+
+        debug!("Sent {state:?} to the service manager");
+
+        Ok(())
+    }
+
+    /// Send a raw state string to the abstract socket `name`
+    #[cfg(target_os = "linux")]
+    fn send_to_abstract(socket: &UnixDatagram, name: &[u8], state: &str) -> io::Result<usize> {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+    }
+
+    /// Abstract sockets only exist on Linux
+    #[cfg(not(target_os = "linux"))]
+    fn send_to_abstract(_: &UnixDatagram, _: &[u8], _: &str) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "abstract notify sockets are only supported on Linux",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(socket: &UnixDatagram) -> String {
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[test]
+    fn states_are_sent_to_the_socket_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("notify");
+        let socket = UnixDatagram::bind(&path).unwrap();
+
+        Notify::send_to(path.as_os_str(), "READY=1").unwrap();
+        Notify::send_to(path.as_os_str(), "STOPPING=1").unwrap();
+
+        assert_eq!(received(&socket), "READY=1");
+        assert_eq!(received(&socket), "STOPPING=1");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn states_are_sent_to_abstract_sockets() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let name = format!("nimi-test-notify-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let socket = UnixDatagram::bind_addr(&addr).unwrap();
+
+        Notify::send_to(OsStr::new(&format!("@{name}")), "READY=1").unwrap();
+
+        assert_eq!(received(&socket), "READY=1");
+    }
+
+    #[test]
+    fn missing_sockets_are_an_error() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        assert!(Notify::send_to(tmp.path().join("missing").as_os_str(), "READY=1").is_err());
+    }
+}
@@ -0,0 +1,370 @@
+//! Plan Module
+//!
+//! Describes what running the config would spawn, without spawning anything
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::Path,
+};
+
+use eyre::Result;
+
+use crate::{
+    config::resolve_binary,
+    process_manager::{
+        DependencyGraph, Service, ServiceType, Settings,
+        service::{ConfigData, Materialize},
+        service_manager::{CONFIG_DIR_VAR, ConfigDir},
+        settings::{Restart, RestartMode},
+    },
+};
+
+/// Spawn plan of every service, printed by `--dry-run`
+///
+/// Services are listed in the order they are started, so the same config always gives
+/// the same plan
+pub struct Plan<'a> {
+    services: &'a HashMap<String, Service>,
+    settings: &'a Settings,
+    runtime_dir: &'a Path,
+}
+
+impl<'a> Plan<'a> {
+    /// Plan the services, with their config directories inside of `runtime_dir`
+    pub fn new(
+        services: &'a HashMap<String, Service>,
+        settings: &'a Settings,
+        runtime_dir: &'a Path,
+    ) -> Self {
+        Self {
+            services,
+            settings,
+            runtime_dir,
+        }
+    }
+
+    /// Render the plan as text
+    ///
+    /// Only reads the filesystem to resolve binaries, nothing is created
+    pub fn render(&self) -> Result<String> {
+        let mut out = String::new();
+
+        let startup = &self.settings.startup;
+        let startup_commands = startup
+            .run_on_startup
+            .iter()
+            .map(|bin| (bin.as_str(), &[][..]))
+            .chain(
+                startup
+                    .commands
+                    .iter()
+                    .map(|argv| (argv.binary(), argv.args())),
+            );
+        let startup_path = startup.environment.get("PATH").map(String::as_str);
+        for (bin, args) in startup_commands {
+            writeln!(out, "startup")?;
+            Self::write_command(&mut out, bin, args, startup_path)?;
+            Self::write_variables(&mut out, &startup.environment)?;
+        }
+
+        for name in DependencyGraph::start_order(self.services) {
+            let service = &self.services[name];
+            let process = &service.process;
+
+            writeln!(out, "service {name}")?;
+            writeln!(
+                out,
+                "  type: {}",
+                match service.kind {
+                    ServiceType::Longrunning => "longrunning",
+                    ServiceType::Oneshot => "oneshot",
+                }
+            )?;
+            let config_dir = match ConfigDir::shared_path(self.runtime_dir, service)? {
+                Some(path) => path,
+                None => self.runtime_dir.join("nimi-config-XXXXXX"),
+            };
+
+            let args = process.command.args().into_iter().map(|arg| {
+                ConfigDir::interpolate(&config_dir, arg.as_ref())
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            Self::write_command(
+                &mut out,
+                process.command.binary(),
+                args,
+                process.environment.get("PATH").map(String::as_str),
+            )?;
+            match &process.working_directory {
+                Some(dir) => writeln!(out, "  working directory: {dir:?}")?,
+                None => writeln!(out, "  working directory: inherited from nimi")?,
+            }
+
+            let passed = self.settings.passed_environment(process);
+            match &passed {
+                None => writeln!(out, "  environment: inherited from nimi")?,
+                Some(passed) => {
+                    writeln!(out, "  environment: passes {} from nimi", passed.join(", "))?;
+                }
+            }
+
+            let config_env_var = &service.config_env_var;
+            let inherited = passed
+                .as_ref()
+                .is_none_or(|passed| passed.contains(&config_env_var.as_str()))
+                .then(|| format!("${config_env_var}").into());
+            let mut variables = process.environment.clone();
+            variables.insert(
+                config_env_var.clone(),
+                service
+                    .config_env_value(config_dir.as_os_str(), &process.environment, inherited)
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+            variables.insert(
+                CONFIG_DIR_VAR.to_owned(),
+                config_dir.to_string_lossy().into_owned(),
+            );
+            Self::write_variables(&mut out, &variables)?;
+            for path in &process.environment_files {
+                writeln!(out, "    read from {path:?}")?;
+            }
+            for path in &process.environment_secret_files {
+                writeln!(out, "    secrets read from {path:?}")?;
+            }
+
+            let config_data: BTreeMap<_, _> = service
+                .config_data
+                .iter()
+                .filter(|(_, cfg)| cfg.enable)
+                .collect();
+            if config_data.is_empty() {
+                writeln!(out, "  config data: none")?;
+            } else {
+                writeln!(out, "  config data: in {config_dir:?}")?;
+                for cfg in config_data.into_values() {
+                    Self::write_config_data(&mut out, cfg, service.materialize)?;
+                }
+            }
+
+            if !service.after.is_empty() {
+                writeln!(out, "  after: {}", service.after.join(", "))?;
+            }
+            if !service.conflicts.is_empty() {
+                writeln!(out, "  conflicts: {}", service.conflicts.join(", "))?;
+            }
+            if let Some(delay) = service.start_delay {
+                writeln!(out, "  start delay: {delay:?}")?;
+            }
+
+            match service.kind {
+                ServiceType::Longrunning => Self::write_restart(
+                    &mut out,
+                    service.restart.as_ref().unwrap_or(&self.settings.restart),
+                )?,
+                ServiceType::Oneshot => writeln!(out, "  restart: never, oneshot")?,
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn write_command<S: AsRef<str>>(
+        out: &mut String,
+        binary: &str,
+        args: impl IntoIterator<Item = S>,
+        path: Option<&str>,
+    ) -> Result<()> {
+        match resolve_binary(binary, path) {
+            Some(resolved) => writeln!(out, "  binary: {resolved:?}")?,
+            None => writeln!(out, "  binary: {binary:?} (not found)")?,
+        }
+        let args: Vec<_> = args
+            .into_iter()
+            .map(|arg| format!("{:?}", arg.as_ref()))
+            .collect();
+        writeln!(out, "  args: [{}]", args.join(", "))?;
+
+        Ok(())
+    }
+
+    fn write_variables(out: &mut String, variables: &HashMap<String, String>) -> Result<()> {
+        let variables: BTreeMap<_, _> = variables.iter().collect();
+        for (name, value) in variables {
+            writeln!(out, "    {name}={value}")?;
+        }
+
+        Ok(())
+    }
+
+    fn write_config_data(
+        out: &mut String,
+        cfg: &ConfigData,
+        materialize: Materialize,
+    ) -> Result<()> {
+        let mode = cfg
+            .mode
+            .map(|mode| format!(", mode {:04o}", mode.bits()))
+            .unwrap_or_default();
+
+        match (&cfg.source, &cfg.text) {
+            (Some(source), _) if materialize == Materialize::Symlink && cfg.mode.is_none() => {
+                writeln!(out, "    {:?}: symlink to {source:?}", cfg.path)?;
+            }
+            (Some(source), _) => writeln!(out, "    {:?}: copy of {source:?}{mode}", cfg.path)?,
+            (None, Some(text)) => {
+                writeln!(
+                    out,
+                    "    {:?}: text of {} bytes{mode}",
+                    cfg.path,
+                    text.len()
+                )?;
+            }
+            (None, None) => writeln!(out, "    {:?}: neither source nor text set", cfg.path)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_restart(out: &mut String, restart: &Restart) -> Result<()> {
+        write!(out, "  restart: {}", restart.mode)?;
+        if !matches!(restart.mode, RestartMode::Never) {
+            match &restart.backoff {
+                Some(backoff) => write!(
+                    out,
+                    ", after {:?} growing by {} up to {:?}",
+                    backoff.initial, backoff.multiplier, backoff.max
+                )?,
+                None => write!(out, ", after {:?}", restart.time)?,
+            }
+        }
+        if matches!(restart.mode, RestartMode::UpToCount) {
+            write!(out, ", at most {} times", restart.count)?;
+        }
+        writeln!(out)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn services() -> HashMap<String, Service> {
+        serde_json::from_value(json!({
+            "web": {
+                "configData": {
+                    "app": { "enable": true, "path": "app.conf", "text": "listen 80" },
+                    "off": { "enable": false, "path": "off.conf", "text": "unused" },
+                },
+                "process": {
+                    "argv": ["sh", "-c", "exec web ${NIMI_CONFIG_DIR}/app.conf"],
+                    "environment": { "PORT": "80" },
+                    "passEnvironment": ["HOME"],
+                },
+                "after": ["db"],
+            },
+            "db": {
+                "type": "oneshot",
+                "configData": {},
+                "process": { "argv": ["/nonexistent/migrate"] },
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn plan_lists_services_in_start_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (services, settings) = (services(), Settings::default());
+
+        let plan = Plan::new(&services, &settings, tmp.path())
+            .render()
+            .unwrap();
+        let headers: Vec<_> = plan
+            .lines()
+            .filter(|line| line.starts_with("service "))
+            .collect();
+
+        assert_eq!(headers, ["service db", "service web"]);
+        let web = &plan[plan.find("service web").unwrap()..];
+        for expected in [
+            "  type: longrunning",
+            "  environment: passes HOME, PATH from nimi",
+            "    PORT=80",
+            "    \"app.conf\": text of 9 bytes",
+            "  after: db",
+            "  restart: on-failure, after 1s",
+        ] {
+            assert!(
+                web.lines().any(|line| line == expected),
+                "{expected}\n{plan}"
+            );
+        }
+        assert!(!web.contains("off.conf"), "{plan}");
+        assert!(plan.contains("  binary: \"/nonexistent/migrate\" (not found)\n"));
+        assert!(plan.contains("  restart: never, oneshot\n"));
+    }
+
+    #[test]
+    fn plan_interpolates_the_config_directory_without_creating_it() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (services, settings) = (services(), Settings::default());
+
+        let plan = Plan::new(&services, &settings, tmp.path())
+            .render()
+            .unwrap();
+        let config_dir = ConfigDir::shared_path(tmp.path(), &services["web"])
+            .unwrap()
+            .unwrap();
+
+        assert!(plan.contains(&format!("\"exec web {}/app.conf\"", config_dir.display())));
+        assert!(plan.contains(&format!("    NIMI_CONFIG_DIR={}\n", config_dir.display())));
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn the_same_config_always_gives_the_same_plan() {
+        let tmp = tempfile::tempdir().unwrap();
+        let settings = Settings::default();
+
+        let plans: Vec<_> = (0..5)
+            .map(|_| {
+                Plan::new(&services(), &settings, tmp.path())
+                    .render()
+                    .unwrap()
+            })
+            .collect();
+
+        assert!(plans.iter().all(|plan| *plan == plans[0]));
+    }
+
+    #[test]
+    fn plan_lists_secret_files_without_reading_them() {
+        let tmp = tempfile::tempdir().unwrap();
+        let secret = tmp.path().join("secret");
+        std::fs::write(&secret, "TOKEN=hunter2\n").unwrap();
+        let mut services = services();
+        services
+            .get_mut("web")
+            .unwrap()
+            .process
+            .environment_secret_files
+            .push(secret.clone());
+
+        let plan = Plan::new(&services, &Settings::default(), tmp.path())
+            .render()
+            .unwrap();
+
+        assert!(
+            plan.contains(&format!("    secrets read from {secret:?}\n")),
+            "{plan}"
+        );
+        assert!(!plan.contains("hunter2"), "{plan}");
+    }
+}
@@ -2,24 +2,359 @@
 //!
 //! Singly handles (de)serialization of the service data to/from the nix type
 
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+    sync::OnceLock,
+    time::Duration,
+};
+
+use log::{Level, LevelFilter};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_with::{DurationMilliSeconds, serde_as};
+
+use crate::process_manager::settings::Restart;
 
+mod cgroup;
 mod config_data;
+mod limits;
+mod log_level_regex;
 mod process;
+mod readiness;
+mod socket;
 
-pub use config_data::ConfigDataMap;
-pub use process::Process;
+pub use cgroup::Cgroup;
+pub use config_data::{ConfigData, ConfigDataMap, FileMode, Materialize};
+pub use limits::{Limit, LimitsMap};
+pub use log_level_regex::LogLevelRegex;
+pub use process::{ArgV, Identity, Nice, Process, ProcessCommand, Stdin, StdioMode, Umask};
+pub use readiness::Readiness;
+pub use socket::{Socket, SocketType};
 
 /// Service Data Struct
 ///
 /// Rust based mirror of the services as defined in the [NixOS Modular Services
 /// Modules](https://github.com/NixOS/nixpkgs/blob/3574a048b30fdc5131af4069bd5e14980ce0a6d8/nixos/modules/system/service/portable/service.nix).
-#[derive(Debug, Serialize, Deserialize)]
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Service {
+    /// If the service is run at all
+    ///
+    /// Disabled services are dropped when the config is loaded
+    #[serde(default = "Service::default_enable")]
+    pub enable: bool,
+
+    /// How the service is expected to run
+    #[serde(rename = "type", default)]
+    pub kind: ServiceType,
+
+    /// Restart policy of the service, overriding `settings.restart` when set
+    ///
+    /// Never applies to oneshot services
+    #[serde(default)]
+    pub restart: Option<Restart>,
+
     /// Configuration files for the service
     #[serde(rename = "configData")]
     pub config_data: ConfigDataMap,
 
+    /// How the config data files are placed into the config directory
+    #[serde(default)]
+    pub materialize: Materialize,
+
+    /// If the service gets a freshly created config directory of its own
+    ///
+    /// Otherwise services with identical config data share a directory
+    #[serde(rename = "uniqueConfigDir", default)]
+    pub unique_config_dir: bool,
+
+    /// Name of the shared config directory, hashed from `config_data` on first use
+    ///
+    /// Lets every start of the service reuse the name rather than hashing the config
+    /// data again, which is never changed once loaded
+    #[serde(skip)]
+    pub(crate) config_dir_name: OnceLock<String>,
+
+    /// Environment variable the config directory is passed to the service in
+    #[serde(rename = "configEnvVar", default = "Service::default_config_env_var")]
+    pub config_env_var: String,
+
+    /// How the config directory is added to `config_env_var`
+    #[serde(rename = "configEnvMode", default)]
+    pub config_env_mode: ConfigEnvMode,
+
     /// Process configuration
     pub process: Process,
+
+    /// Names of services which have to be started before this one
+    #[serde(default)]
+    pub after: Vec<String>,
+
+    /// Names of services which may never run at the same time as this one
+    ///
+    /// They are stopped before this service is started
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+
+    /// Check to determine when the service is ready
+    ///
+    /// The service is ready as soon as its process is spawned when unset
+    #[serde(default)]
+    pub readiness: Option<Readiness>,
+
+    /// The amount of time (in milliseconds) the service may take to become ready
+    ///
+    /// Only applies to services with a readiness check and oneshot services, which are
+    /// stopped and count as failed once it elapses. Unlimited when unset
+    #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+    #[serde(rename = "startTimeout", default)]
+    pub start_timeout: Option<Duration>,
+
+    /// The amount of time (in milliseconds) to wait before the service is first started
+    ///
+    /// Counts from the moment its dependencies are ready, restarts aren't delayed
+    #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+    #[serde(rename = "startDelay", default)]
+    pub start_delay: Option<Duration>,
+
+    /// The amount of time (in milliseconds) the service may go without writing a line
+    ///
+    /// The process is killed and handled like a failed run once it elapses. Only counts
+    /// output nimi captures, never fires when unset
+    #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+    #[serde(rename = "outputWatchdog", default)]
+    pub output_watchdog: Option<Duration>,
+
+    /// The amount of time (in milliseconds) between the `sd_notify` watchdog pings the
+    /// service has to send
+    ///
+    /// The service gets `NOTIFY_SOCKET` and `WATCHDOG_USEC`, and is killed and handled
+    /// like a failed run once it misses a ping. Not watched when unset
+    #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+    #[serde(default)]
+    pub watchdog: Option<Duration>,
+
+    /// Minimum level for the lines of the service to be printed at
+    ///
+    /// Applies to the level of each line, whichever stream it came from. Every line
+    /// is printed when unset, subject to the global `RUST_LOG` filter
+    #[serde(rename = "logLevel", default)]
+    #[schemars(with = "Option<String>")]
+    pub log_level: Option<LevelFilter>,
+
+    /// Level the stdout lines of the service are printed at
+    #[serde(rename = "stdoutLevel", default = "Service::default_stdout_level")]
+    #[schemars(with = "String")]
+    pub stdout_level: Level,
+
+    /// Level the stderr lines of the service are printed at
+    #[serde(rename = "stderrLevel", default = "Service::default_stderr_level")]
+    #[schemars(with = "String")]
+    pub stderr_level: Level,
+
+    /// Regex reading the level a line was logged at from the output of the service
+    ///
+    /// Lines it doesn't classify are printed at `stdout_level` or `stderr_level`
+    #[serde(rename = "logLevelRegex", default)]
+    pub log_level_regex: Option<LogLevelRegex>,
+
+    /// Files whose changes make nimi restart the service
+    ///
+    /// Polled rather than watched through the kernel, so a symlink switched to
+    /// another target counts as a change too
+    #[serde(rename = "watchPaths", default)]
+    pub watch_paths: Vec<PathBuf>,
+
+    /// File to append the output of the service to
+    ///
+    /// Takes precedence over the file in `settings.logging.logsDir`
+    #[serde(rename = "logFile", default)]
+    pub log_file: Option<PathBuf>,
+
+    /// Commands run to completion before the process is started
+    ///
+    /// The service isn't started if any of them fails
+    #[serde(rename = "execStartPre", default)]
+    pub exec_start_pre: Vec<ArgV>,
+
+    /// Commands run after the process was started
+    #[serde(rename = "execStartPost", default)]
+    pub exec_start_post: Vec<ArgV>,
+
+    /// Commands run on shutdown before the process is sent the stop signal
+    #[serde(rename = "execStopPre", default)]
+    pub exec_stop_pre: Vec<ArgV>,
+
+    /// Listening sockets bound by nimi and passed on to the process
+    #[serde(default)]
+    pub sockets: Vec<Socket>,
+}
+
+impl Service {
+    fn default_enable() -> bool {
+        true
+    }
+
+    fn default_stdout_level() -> Level {
+        Level::Debug
+    }
+
+    fn default_stderr_level() -> Level {
+        Level::Error
+    }
+
+    fn default_config_env_var() -> String {
+        "XDG_CONFIG_HOME".to_owned()
+    }
+
+    /// Value of `config_env_var` for a process of the service using `config_dir`
+    ///
+    /// `inherited` is the value the process would get from nimi otherwise. A value in
+    /// the `environment` of the service replaces the config directory when setting the
+    /// variable, and is prepended to like an inherited one otherwise
+    pub fn config_env_value(
+        &self,
+        config_dir: &OsStr,
+        environment: &HashMap<String, String>,
+        inherited: Option<OsString>,
+    ) -> OsString {
+        let configured = environment.get(&self.config_env_var).map(OsString::from);
+
+        match self.config_env_mode {
+            ConfigEnvMode::Set => configured.unwrap_or_else(|| config_dir.to_owned()),
+            ConfigEnvMode::Prepend => {
+                let mut value = config_dir.to_owned();
+                if let Some(rest) = configured.or(inherited).filter(|rest| !rest.is_empty()) {
+                    value.push(":");
+                    value.push(rest);
+                }
+
+                value
+            }
+        }
+    }
+}
+
+/// Config Env Mode
+///
+/// Selects how the config directory is passed to the service
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigEnvMode {
+    /// Set the variable to the config directory
+    #[default]
+    Set,
+
+    /// Put the config directory in front of the `:` separated list the variable holds
+    ///
+    /// Suits search paths like `XDG_CONFIG_DIRS`
+    Prepend,
+}
+
+/// Service Type
+///
+/// Selects how the lifetime of the service process is treated
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum ServiceType {
+    /// Process which keeps running and is restarted according to the restart settings
+    #[default]
+    #[serde(rename = "longrunning")]
+    Longrunning,
+
+    /// Task which runs to completion once and is expected to exit successfully
+    ///
+    /// Never restarted, and only becomes ready for dependents once it completed
+    #[serde(rename = "oneshot")]
+    Oneshot,
+}
+
+/// Parse a permission mode given as an octal string like `"0644"`
+///
+/// Returns `None` for anything but up to four octal digits with a value of at most `0o777`
+fn parse_octal_mode(raw: &str) -> Option<u32> {
+    if raw.is_empty() || raw.len() > 4 || !raw.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return None;
+    }
+
+    u32::from_str_radix(raw, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn service(config_env: serde_json::Value) -> Service {
+        let mut service = json!({ "configData": {}, "process": { "argv": ["true"] } });
+        service
+            .as_object_mut()
+            .unwrap()
+            .extend(config_env.as_object().unwrap().clone());
+
+        serde_json::from_value(service).unwrap()
+    }
+
+    fn value(service: &Service, configured: Option<&str>, inherited: Option<&str>) -> OsString {
+        let environment = configured
+            .map(|configured| (service.config_env_var.clone(), configured.to_owned()))
+            .into_iter()
+            .collect();
+
+        service.config_env_value(
+            OsStr::new("/run/nimi-config"),
+            &environment,
+            inherited.map(OsString::from),
+        )
+    }
+
+    #[test]
+    fn the_config_dir_is_set_in_xdg_config_home_by_default() {
+        let service = service(json!({}));
+
+        assert_eq!(service.config_env_var, "XDG_CONFIG_HOME");
+        assert_eq!(
+            value(&service, None, Some("/home/nimi")),
+            "/run/nimi-config"
+        );
+        assert_eq!(value(&service, Some("/etc/app"), None), "/etc/app");
+    }
+
+    #[test]
+    fn the_config_dir_is_prepended_to_search_paths() {
+        let service = service(json!({
+            "configEnvVar": "XDG_CONFIG_DIRS",
+            "configEnvMode": "prepend",
+        }));
+
+        assert_eq!(value(&service, None, None), "/run/nimi-config");
+        assert_eq!(value(&service, None, Some("")), "/run/nimi-config");
+        assert_eq!(
+            value(&service, None, Some("/etc/xdg")),
+            "/run/nimi-config:/etc/xdg"
+        );
+        assert_eq!(
+            value(&service, Some("/etc/app"), Some("/etc/xdg")),
+            "/run/nimi-config:/etc/app"
+        );
+    }
+
+    #[test]
+    fn stdout_and_stderr_levels_default_to_debug_and_error() {
+        let default = service(json!({}));
+        let configured = service(json!({ "stdoutLevel": "info", "stderrLevel": "warn" }));
+
+        assert_eq!(
+            (default.stdout_level, default.stderr_level),
+            (Level::Debug, Level::Error)
+        );
+        assert_eq!(
+            (configured.stdout_level, configured.stderr_level),
+            (Level::Info, Level::Warn)
+        );
+    }
 }
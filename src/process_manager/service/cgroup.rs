@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// cgroup v2 the service process is placed into
+///
+/// Gives resource accounting and limits which apply to the whole process tree of the
+/// service, unlike the per-process `limits`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Cgroup {
+    /// Path of the cgroup, relative to the root of the cgroup v2 hierarchy
+    ///
+    /// Defaults to `nimi/<service name>` when unset
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+
+    /// Memory limit of the cgroup in bytes, written to `memory.max`
+    #[serde(rename = "memoryMax", default)]
+    pub memory_max: Option<u64>,
+
+    /// CPU time the cgroup may use, in percent of a single CPU, written to `cpu.max`
+    ///
+    /// `200` allows using two full CPUs
+    #[serde(rename = "cpuQuota", default)]
+    pub cpu_quota: Option<u32>,
+}
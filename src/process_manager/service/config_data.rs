@@ -1,11 +1,20 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Component, PathBuf},
+};
 
-use serde::{Deserialize, Serialize};
+use eyre::Result;
+
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::process_manager::service::parse_octal_mode;
 
 /// Convenience type for the map of config data
 pub type ConfigDataMap = HashMap<String, ConfigData>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 /// Service confguration data
 pub struct ConfigData {
     /// If this piece of config data was enabled
@@ -13,7 +22,160 @@ pub struct ConfigData {
     /// The path to the output configuration file
     pub path: PathBuf,
     /// Contents of the config data
+    ///
+    /// Only written out when no `source` is given
     pub text: Option<String>,
     /// The source from the nix store of the configuration file
-    pub source: PathBuf,
+    ///
+    /// Takes precedence over `text` when both are set
+    pub source: Option<PathBuf>,
+    /// Permissions of the file
+    ///
+    /// A `source` with a mode is always copied, since a symlink has no permissions of
+    /// its own. Files written from `text` default to `0644`
+    #[serde(default)]
+    pub mode: Option<FileMode>,
+}
+
+impl ConfigData {
+    /// Check that `path` is a relative path which stays inside of the config directory
+    ///
+    /// Only plain file names are allowed as components, so equal paths are always
+    /// spelled the same way
+    pub fn check_path(&self) -> Result<()> {
+        let contained = self
+            .path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        eyre::ensure!(
+            contained && self.path.components().next().is_some(),
+            "Config data path {:?} must be a relative path inside of the config directory",
+            self.path
+        );
+
+        Ok(())
+    }
+}
+
+/// Permissions of a config file, given as an octal string like `"0600"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMode(u32);
+
+impl FileMode {
+    /// Mode of files written from `text` when no mode is given
+    pub const DEFAULT: Self = Self(0o644);
+
+    /// The mode as passed to `chmod(2)`
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl Serialize for FileMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&format!("{:04o}", self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for FileMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = String::deserialize(deserializer)?;
+
+        parse_octal_mode(&raw).map(Self).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "Invalid file mode {raw:?}, expected an octal string like \"0600\""
+            ))
+        })
+    }
+}
+
+impl JsonSchema for FileMode {
+    fn schema_name() -> Cow<'static, str> {
+        "FileMode".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "Permissions of a config file, given as an octal string like \"0600\"",
+            "type": "string",
+            "pattern": "^[0-7]{1,4}$",
+        })
+    }
+}
+
+/// How the `source` of config data is placed into the config directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Materialize {
+    /// Symlink to the source
+    #[default]
+    Symlink,
+
+    /// Copy the source, so it can be read without the nix store being available
+    Copy,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn config_data(path: &str) -> ConfigData {
+        serde_json::from_value(json!({ "enable": true, "path": path, "text": "" })).unwrap()
+    }
+
+    #[test]
+    fn relative_paths_inside_of_the_config_directory_are_accepted() {
+        for path in ["app.conf", "app/conf.d/foo.conf", "app//foo.conf", "app/"] {
+            assert!(config_data(path).check_path().is_ok(), "{path}");
+        }
+    }
+
+    #[test]
+    fn paths_leaving_or_aliasing_the_config_directory_are_rejected() {
+        for path in [
+            "",
+            ".",
+            "./app.conf",
+            "../app.conf",
+            "app/../../x",
+            "/etc/passwd",
+        ] {
+            let err = config_data(path).check_path().unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "Config data path {path:?} must be a relative path inside of the config directory"
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn file_modes_are_read_as_octal() {
+        let mode: FileMode = serde_json::from_value(json!("0600")).unwrap();
+
+        assert_eq!(mode.bits(), 0o600);
+        assert_eq!(serde_json::to_value(mode).unwrap(), "0600");
+    }
+
+    #[test]
+    fn invalid_file_modes_are_rejected() {
+        for raw in ["", "0800", "01000", "+x", "-644"] {
+            let err = serde_json::from_value::<FileMode>(json!(raw)).unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                format!("Invalid file mode {raw:?}, expected an octal string like \"0600\"")
+            );
+        }
+    }
 }
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use nix::sys::resource::{Resource, rlim_t, setrlimit};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Convenience type for the map of resource limits applied to a service
+pub type LimitsMap = HashMap<Limit, rlim_t>;
+
+/// Resource limits which can be applied to a service process
+///
+/// Each maps onto the `RLIMIT_*` constant of the same name, the configured
+/// value is used as both the soft and the hard limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Limit {
+    /// `RLIMIT_AS`, maximum size of the virtual address space in bytes
+    As,
+    /// `RLIMIT_CORE`, maximum size of a core dump in bytes
+    Core,
+    /// `RLIMIT_CPU`, maximum CPU time in seconds
+    Cpu,
+    /// `RLIMIT_DATA`, maximum size of the data segment in bytes
+    Data,
+    /// `RLIMIT_FSIZE`, maximum size of created files in bytes
+    Fsize,
+    /// `RLIMIT_MEMLOCK`, maximum amount of locked memory in bytes
+    Memlock,
+    /// `RLIMIT_NOFILE`, maximum number of open file descriptors
+    Nofile,
+    /// `RLIMIT_NPROC`, maximum number of processes for the user
+    Nproc,
+    /// `RLIMIT_STACK`, maximum size of the stack in bytes
+    Stack,
+}
+
+impl Limit {
+    fn resource(self) -> Resource {
+        match self {
+            Self::As => Resource::RLIMIT_AS,
+            Self::Core => Resource::RLIMIT_CORE,
+            Self::Cpu => Resource::RLIMIT_CPU,
+            Self::Data => Resource::RLIMIT_DATA,
+            Self::Fsize => Resource::RLIMIT_FSIZE,
+            Self::Memlock => Resource::RLIMIT_MEMLOCK,
+            Self::Nofile => Resource::RLIMIT_NOFILE,
+            Self::Nproc => Resource::RLIMIT_NPROC,
+            Self::Stack => Resource::RLIMIT_STACK,
+        }
+    }
+
+    /// Apply every limit in the map to the current process
+    ///
+    /// Intended to be called from a `pre_exec` hook before privileges are dropped
+    pub fn apply_all(limits: &LimitsMap) -> std::io::Result<()> {
+        for (limit, value) in limits {
+            setrlimit(limit.resource(), *value, *value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_limits_are_read() {
+        let limits: LimitsMap =
+            serde_json::from_str(r#"{ "nofile": 1024, "as": 536870912 }"#).unwrap();
+
+        assert_eq!(limits[&Limit::Nofile], 1024);
+        assert_eq!(limits[&Limit::As], 536870912);
+    }
+
+    #[test]
+    fn unknown_limits_are_rejected() {
+        let err = serde_json::from_str::<LimitsMap>(r#"{ "nofiles": 1024 }"#).unwrap_err();
+
+        assert!(err.to_string().contains("unknown variant `nofiles`"));
+    }
+}
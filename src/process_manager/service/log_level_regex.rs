@@ -0,0 +1,75 @@
+use std::borrow::Cow;
+
+use log::Level;
+use regex::Regex;
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Regex finding the level a line of service output was logged at
+///
+/// The level is taken from the group named `level`, or from the first group if there
+/// is none, e.g. `^\[(\w+)\]` for lines like `[WARN] disk almost full`
+#[derive(Debug, Clone)]
+pub struct LogLevelRegex(Regex);
+
+impl LogLevelRegex {
+    /// Level `line` says it was logged at
+    ///
+    /// `None` if the regex doesn't match or the captured level is unknown
+    pub fn level_of(&self, line: &str) -> Option<Level> {
+        let captures = self.0.captures(line)?;
+        let level = captures.name("level").or_else(|| captures.get(1))?;
+
+        // Also covers the names used by syslog severities and common loggers
+        match level.as_str().to_ascii_lowercase().as_str() {
+            "error" | "err" | "fatal" | "critical" | "crit" | "alert" | "emerg" | "panic" => {
+                Some(Level::Error)
+            }
+            "warn" | "warning" => Some(Level::Warn),
+            "info" | "notice" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for LogLevelRegex {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for LogLevelRegex {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = String::deserialize(deserializer)?;
+        let regex = Regex::new(&raw).map_err(serde::de::Error::custom)?;
+        if regex.captures_len() < 2 {
+            return Err(serde::de::Error::custom(format!(
+                "Log level regex {raw:?} needs a capture group for the level"
+            )));
+        }
+
+        Ok(Self(regex))
+    }
+}
+
+impl JsonSchema for LogLevelRegex {
+    fn schema_name() -> Cow<'static, str> {
+        "LogLevelRegex".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "Regex capturing the level of a line of output, in its `level` group or else its first group",
+            "type": "string",
+            "format": "regex",
+        })
+    }
+}
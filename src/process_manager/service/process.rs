@@ -1,21 +1,611 @@
-use eyre::{Error, Result, eyre};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+use eyre::{Context, Error, Result, eyre};
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
 use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::process_manager::{
+    service::{Cgroup, LimitsMap, parse_octal_mode},
+    service_manager::CONFIG_DIR_VAR,
+};
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 /// Service process configuration
 pub struct Process {
-    /// Argv used to run the service
-    pub argv: ArgV,
+    /// Command used to run the service
+    #[serde(flatten)]
+    pub command: ProcessCommand,
+
+    /// Environment variables to set for the service
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+
+    /// Files of `KEY=VALUE` lines to read environment variables from
+    ///
+    /// Read every time a process of the service is spawned, in order. `environment`
+    /// takes precedence over the files
+    #[serde(rename = "environmentFiles", default)]
+    pub environment_files: Vec<PathBuf>,
+
+    /// Environment files holding secrets, read after `environment_files`
+    ///
+    /// Only their paths are kept in the config, nimi never prints the values they hold
+    #[serde(rename = "environmentSecretFiles", default)]
+    pub environment_secret_files: Vec<PathBuf>,
+
+    /// Variables of the environment of nimi passed on to the service
+    ///
+    /// The whole environment is inherited when unset. `PATH` is always passed on
+    #[serde(rename = "passEnvironment", default)]
+    pub pass_environment: Option<Vec<String>>,
+
+    /// Directory to run the service in
+    ///
+    /// Inherits the working directory of nimi when unset
+    #[serde(rename = "workingDirectory", default)]
+    pub working_directory: Option<PathBuf>,
+
+    /// Where the standard input of the service is read from
+    #[serde(default)]
+    pub stdin: Stdin,
+
+    /// Where the standard output and error of the service go
+    #[serde(default)]
+    pub stdio: StdioMode,
+
+    /// If the service process runs on a pseudo terminal of its own
+    ///
+    /// Its output is read from the terminal and logged like captured output, hooks
+    /// of the service keep using pipes
+    #[serde(default)]
+    pub tty: bool,
+
+    /// File mode creation mask of the service
+    ///
+    /// Inherits the umask of nimi when unset
+    #[serde(default)]
+    pub umask: Option<Umask>,
+
+    /// Scheduling priority of the service, from `-20` (highest) to `19` (lowest)
+    ///
+    /// Inherits the niceness of nimi when unset
+    #[serde(default)]
+    pub nice: Option<Nice>,
+
+    /// User to run the service as
+    #[serde(default)]
+    pub user: Option<Identity>,
+
+    /// Group to run the service as
+    ///
+    /// Defaults to the primary group of `user` when unset
+    #[serde(default)]
+    pub group: Option<Identity>,
+
+    /// Groups the service is a member of in addition to the groups of `user`, or of
+    /// nimi when no user is set
+    #[serde(rename = "supplementaryGroups", default)]
+    pub supplementary_groups: Vec<Identity>,
+
+    /// Resource limits to apply to the service
+    #[serde(default)]
+    pub limits: LimitsMap,
+
+    /// cgroup v2 to place the service process into
+    ///
+    /// The process stays in the cgroup of nimi when unset
+    #[serde(default)]
+    pub cgroup: Option<Cgroup>,
+
+    /// Exit codes which count as a successful exit
+    ///
+    /// Only an exit code of `0` is successful when empty
+    #[serde(rename = "successExitCodes", default)]
+    pub success_exit_codes: Vec<i32>,
+}
+
+impl Process {
+    /// Check if nimi reads the output of the process, from pipes or its pty
+    ///
+    /// Inherited output goes straight to nimi's stdout and stderr, a pty can't be combined
+    /// with it
+    pub fn output_is_read(&self) -> bool {
+        self.stdio == StdioMode::Capture
+    }
+
+    /// Check if the process exited successfully according to `success_exit_codes`
+    pub fn is_success(&self, status: ExitStatus) -> bool {
+        if self.success_exit_codes.is_empty() {
+            return status.success();
+        }
+
+        status
+            .code()
+            .is_some_and(|code| self.success_exit_codes.contains(&code))
+    }
+
+    /// Variables set for the service, from `environment_files`,
+    /// `environment_secret_files` and then `environment`
+    pub async fn read_environment(&self) -> Result<HashMap<String, String>> {
+        let files = self
+            .environment_files
+            .iter()
+            .map(|path| (path, false))
+            .chain(
+                self.environment_secret_files
+                    .iter()
+                    .map(|path| (path, true)),
+            );
+
+        let mut environment = HashMap::new();
+        for (path, secret) in files {
+            let contents = tokio::fs::read_to_string(path)
+                .await
+                .wrap_err_with(|| format!("Failed to read environment file {path:?}"))?;
+            environment.extend(parse_environment_file(path, &contents, secret)?);
+        }
+        environment.extend(
+            self.environment
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+
+        Ok(environment)
+    }
+
+    /// Expand `${NAME}` and `${NAME:-default}` in the argv, environment values and
+    /// working directory using the environment of nimi
+    ///
+    /// Errors on variables which are unset and have no default. `${NIMI_CONFIG_DIR}` is
+    /// left alone, it is only known once the service is spawned
+    pub fn expand_env(&mut self) -> Result<()> {
+        match &mut self.command {
+            ProcessCommand::Argv(argv) => {
+                for arg in &mut argv.0 {
+                    *arg = expand_env_vars(arg)?;
+                }
+            }
+            ProcessCommand::Shell(command) => *command = expand_env_vars(command)?,
+        }
+
+        for (name, value) in &mut self.environment {
+            *value = expand_env_vars(value)
+                .wrap_err_with(|| format!("Failed to expand environment variable {name}"))?;
+        }
+
+        if let Some(working_directory) = &mut self.working_directory
+            && let Some(dir) = working_directory.to_str()
+        {
+            *working_directory = expand_env_vars(dir)
+                .wrap_err("Failed to expand working directory")?
+                .into();
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the `KEY=VALUE` lines of the environment file at `path`
+///
+/// Blank lines and lines starting with `#` are skipped, a leading `export` is ignored.
+/// Values may be wrapped in single quotes, taken literally, or double quotes, in which
+/// `\"`, `\\`, `\n` and `\$` are unescaped. A `#` after whitespace starts a comment
+/// in unquoted values
+///
+/// Errors in a `secret` file never quote its contents
+fn parse_environment_file(
+    path: &Path,
+    contents: &str,
+    secret: bool,
+) -> Result<Vec<(String, String)>> {
+    let mut variables = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parse = || -> Result<(String, String)> {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| eyre!("Expected KEY=VALUE"))?;
+            let name = name.trim();
+            eyre::ensure!(
+                name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                "Invalid variable name {}",
+                if secret {
+                    "****".into()
+                } else {
+                    format!("{name:?}")
+                }
+            );
+
+            Ok((name.to_owned(), parse_environment_value(value.trim())?))
+        };
+
+        variables.push(parse().wrap_err_with(|| {
+            format!(
+                "Failed to parse environment file {path:?} at line {}",
+                number + 1
+            )
+        })?);
+    }
+
+    Ok(variables)
 }
 
+/// Unquote a single value of an environment file
+fn parse_environment_value(value: &str) -> Result<String> {
+    if let Some(quoted) = value.strip_prefix('\'') {
+        let end = quoted
+            .find('\'')
+            .ok_or_else(|| eyre!("Unterminated single quote"))?;
+        eyre::ensure!(
+            is_trailing_comment(&quoted[end + 1..]),
+            "Unexpected characters after the closing quote"
+        );
+
+        return Ok(quoted[..end].to_owned());
+    }
+
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut unquoted = String::with_capacity(quoted.len());
+        let mut chars = quoted.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    eyre::ensure!(
+                        is_trailing_comment(&quoted[i + 1..]),
+                        "Unexpected characters after the closing quote"
+                    );
+                    return Ok(unquoted);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => unquoted.push('\n'),
+                    Some((_, c @ ('"' | '\\' | '$'))) => unquoted.push(c),
+                    Some((_, c)) => {
+                        unquoted.push('\\');
+                        unquoted.push(c);
+                    }
+                    None => break,
+                },
+                c => unquoted.push(c),
+            }
+        }
+
+        eyre::bail!("Unterminated double quote");
+    }
+
+    let comment = value
+        .match_indices('#')
+        .map(|(i, _)| i)
+        .find(|i| value[..*i].ends_with(char::is_whitespace));
+    let value = match comment {
+        Some(comment) => &value[..comment],
+        None => value,
+    };
+
+    Ok(value.trim_end().to_owned())
+}
+
+/// If `rest` is nothing but whitespace and an optional comment
+fn is_trailing_comment(rest: &str) -> bool {
+    let rest = rest.trim_start();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+/// Replace every `${NAME}` and `${NAME:-default}` in `value` with the variable from
+/// the environment of nimi
+fn expand_env_vars(value: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+
+        let reference = &rest[start + 2..];
+        let end = reference
+            .find('}')
+            .ok_or_else(|| eyre!("Unterminated variable reference in {value:?}"))?;
+        let (name, default) = match reference[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&reference[..end], None),
+        };
+
+        if name == CONFIG_DIR_VAR {
+            expanded.push_str(&rest[start..start + end + 3]);
+            rest = &reference[end + 1..];
+            continue;
+        }
+
+        match (env::var(name), default) {
+            (Ok(var), _) => expanded.push_str(&var),
+            (Err(_), Some(default)) => expanded.push_str(default),
+            (Err(e), None) => {
+                return Err(e).wrap_err_with(|| {
+                    format!("Failed to expand variable {name} referenced in {value:?}")
+                });
+            }
+        }
+
+        rest = &reference[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// A user or group given either by name or numeric id
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Identity {
+    /// Numeric uid or gid
+    Id(u32),
+
+    /// User or group name to resolve at spawn time
+    Name(String),
+}
+
+impl<'de> Deserialize<'de> for Identity {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Id(u32),
+            Name(String),
+        }
+
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::Id(id) => Self::Id(id),
+            Raw::Name(name) => match name.parse() {
+                Ok(id) => Self::Id(id),
+                Err(_) => Self::Name(name),
+            },
+        })
+    }
+}
+
+/// Command used to run the service process
+///
+/// Given as either `process.argv` or `process.command`, but never both
+#[derive(Debug, Serialize)]
+pub enum ProcessCommand {
+    /// Binary and arguments to run directly
+    #[serde(rename = "argv")]
+    Argv(ArgV),
+
+    /// Shell command run through `sh -c`
+    #[serde(rename = "command")]
+    Shell(String),
+}
+
+impl ProcessCommand {
+    /// The binary to run
+    pub fn binary(&self) -> &str {
+        match self {
+            Self::Argv(argv) => argv.binary(),
+            Self::Shell(_) => "sh",
+        }
+    }
+
+    /// The arguments passed to the binary
+    pub fn args(&self) -> Vec<&str> {
+        match self {
+            Self::Argv(argv) => argv.args().iter().map(String::as_str).collect(),
+            Self::Shell(command) => vec!["-c", command],
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ProcessCommand {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Raw {
+            #[serde(default)]
+            argv: Vec<String>,
+            #[serde(default)]
+            command: Option<String>,
+        }
+
+        // nix defaults `argv` to an empty list when only `command` is given
+        let raw = Raw::deserialize(deserializer)?;
+        match (raw.argv.is_empty(), raw.command) {
+            (true, Some(command)) => Ok(Self::Shell(command)),
+            (false, None) => ArgV::try_from(raw.argv)
+                .map(Self::Argv)
+                .map_err(serde::de::Error::custom),
+            (false, Some(_)) => Err(serde::de::Error::custom(
+                "Only one of `process.argv` and `process.command` may be given",
+            )),
+            (true, None) => Err(serde::de::Error::custom(
+                "You must give either `process.argv` or `process.command` to run a service",
+            )),
+        }
+    }
+}
+
+impl JsonSchema for ProcessCommand {
+    fn schema_name() -> Cow<'static, str> {
+        "ProcessCommand".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "Command used to run the service process, either a non-empty `argv` or a shell `command`",
+            "type": "object",
+            "properties": {
+                "argv": generator.subschema_for::<Vec<String>>(),
+                "command": generator.subschema_for::<Option<String>>(),
+            },
+            // nix sets `argv` to an empty list when only `command` is given
+            "oneOf": [
+                {
+                    "required": ["argv"],
+                    "properties": {
+                        "argv": { "minItems": 1 },
+                        "command": { "type": "null" },
+                    },
+                },
+                {
+                    "required": ["command"],
+                    "properties": {
+                        "argv": { "maxItems": 0 },
+                        "command": { "type": "string" },
+                    },
+                },
+            ],
+        })
+    }
+}
+
+/// Source of the standard input of a service process
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Stdin {
+    /// Read from `/dev/null`
+    #[default]
+    Null,
+
+    /// Share the standard input of nimi
+    Inherit,
+
+    /// Read from a file
+    File(PathBuf),
+}
+
+/// Destination of the standard output and error of a service process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StdioMode {
+    /// Read by nimi and logged line by line under the name of the service
+    #[default]
+    Capture,
+
+    /// Share the standard output and error of nimi, bypassing its logging
+    Inherit,
+}
+
+/// File mode creation mask, given as an octal string like `"0027"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Umask(u32);
+
+impl Umask {
+    /// The mask as passed to `umask(2)`
+    pub fn mode(self) -> libc::mode_t {
+        self.0 as libc::mode_t
+    }
+}
+
+impl Serialize for Umask {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&format!("{:04o}", self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Umask {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = String::deserialize(deserializer)?;
+
+        parse_octal_mode(&raw).map(Self).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "Invalid umask {raw:?}, expected an octal string like \"0027\""
+            ))
+        })
+    }
+}
+
+impl JsonSchema for Umask {
+    fn schema_name() -> Cow<'static, str> {
+        "Umask".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "File mode creation mask, given as an octal string like \"0027\"",
+            "type": "string",
+            "pattern": "^[0-7]{1,4}$",
+        })
+    }
+}
+
+/// Niceness of a process, between `-20` and `19`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Nice(i32);
+
+impl Nice {
+    const RANGE: std::ops::RangeInclusive<i32> = -20..=19;
+
+    /// The niceness as passed to `setpriority(2)`
+    pub fn value(self) -> libc::c_int {
+        self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for Nice {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = i32::deserialize(deserializer)?;
+        if !Self::RANGE.contains(&raw) {
+            return Err(serde::de::Error::custom(format!(
+                "Invalid nice value {raw}, expected a value between -20 and 19"
+            )));
+        }
+
+        Ok(Self(raw))
+    }
+}
+
+impl JsonSchema for Nice {
+    fn schema_name() -> Cow<'static, str> {
+        "Nice".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "Niceness of a process, between -20 and 19",
+            "type": "integer",
+            "minimum": Self::RANGE.start(),
+            "maximum": Self::RANGE.end(),
+        })
+    }
+}
+
+/// Non-empty list of arguments used to run a command
+///
+/// Used for `process.argv` as well as hooks, readiness checks and startup commands
 #[derive(Debug, Serialize)]
 pub struct ArgV(Vec<String>);
 
 impl ArgV {
+    /// The binary to run
     pub fn binary(&self) -> &str {
         &self.0[0]
     }
 
+    /// The arguments passed to the binary
     pub fn args(&self) -> &[String] {
         &self.0[1..]
     }
@@ -27,8 +617,7 @@ impl TryFrom<Vec<String>> for ArgV {
     fn try_from(value: Vec<String>) -> Result<Self> {
         if value.is_empty() {
             return Err(eyre!(
-                "You must give at least one argument to `process.argv` to run a service. Got: {:?}",
-                value
+                "A command needs at least the binary to run, got an empty argument list"
             ));
         }
 
@@ -45,3 +634,278 @@ impl<'de> Deserialize<'de> for ArgV {
         ArgV::try_from(v).map_err(serde::de::Error::custom)
     }
 }
+
+impl JsonSchema for ArgV {
+    fn schema_name() -> Cow<'static, str> {
+        "ArgV".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "Binary to run followed by its arguments",
+            "type": "array",
+            "items": { "type": "string" },
+            "minItems": 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn process(process: serde_json::Value) -> Process {
+        serde_json::from_value(process).unwrap()
+    }
+
+    fn exited(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[test]
+    fn only_exit_code_zero_is_successful_by_default() {
+        let process = process(json!({ "argv": ["true"] }));
+
+        assert!(process.is_success(exited(0)));
+        assert!(!process.is_success(exited(2)));
+    }
+
+    #[test]
+    fn success_exit_codes_replace_zero() {
+        let process = process(json!({ "argv": ["true"], "successExitCodes": [2, 143] }));
+
+        assert!(process.is_success(exited(2)));
+        assert!(process.is_success(exited(143)));
+        assert!(!process.is_success(exited(0)));
+        assert!(!process.is_success(ExitStatus::from_raw(libc::SIGTERM)));
+    }
+
+    #[test]
+    fn defined_variables_are_expanded() {
+        let path = env::var("PATH").unwrap();
+
+        assert_eq!(
+            expand_env_vars("--path=${PATH}:/extra").unwrap(),
+            format!("--path={path}:/extra")
+        );
+    }
+
+    #[test]
+    fn defaults_apply_to_unset_variables() {
+        assert_eq!(
+            expand_env_vars("${NIMI_TEST_UNSET:-fallback} ${PATH:-unused}").unwrap(),
+            format!("fallback {}", env::var("PATH").unwrap())
+        );
+    }
+
+    #[test]
+    fn unset_variables_without_a_default_fail() {
+        let err = expand_env_vars("run ${NIMI_TEST_UNSET}").unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Failed to expand variable NIMI_TEST_UNSET referenced in \"run ${NIMI_TEST_UNSET}\""
+        );
+        assert!(expand_env_vars("${PATH").is_err());
+    }
+
+    #[test]
+    fn config_dir_references_are_left_for_spawning() {
+        let mut process = process(json!({
+            "argv": ["app", "--config", "${NIMI_CONFIG_DIR}/app.conf"],
+            "environment": {"FALLBACK": "${NIMI_TEST_UNSET:-x}"},
+        }));
+
+        process.expand_env().unwrap();
+
+        assert_eq!(
+            process.command.args(),
+            ["--config", "${NIMI_CONFIG_DIR}/app.conf"]
+        );
+        assert_eq!(process.environment["FALLBACK"], "x");
+    }
+
+    #[test]
+    fn argv_runs_its_binary_directly() {
+        let process = process(json!({ "argv": ["echo", "a b", "c"] }));
+
+        assert_eq!(process.command.binary(), "echo");
+        assert_eq!(process.command.args(), ["a b", "c"]);
+    }
+
+    #[test]
+    fn command_runs_through_sh() {
+        // nix gives an empty `argv` along with `command`
+        let process = process(json!({ "argv": [], "command": "echo a | tr a b" }));
+
+        assert_eq!(process.command.binary(), "sh");
+        assert_eq!(process.command.args(), ["-c", "echo a | tr a b"]);
+    }
+
+    #[test]
+    fn exactly_one_of_argv_and_command_is_needed() {
+        let err = |process| {
+            serde_json::from_value::<Process>(process)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            err(json!({ "argv": ["echo"], "command": "echo" })),
+            "Only one of `process.argv` and `process.command` may be given"
+        );
+        assert_eq!(
+            err(json!({ "argv": [] })),
+            "You must give either `process.argv` or `process.command` to run a service"
+        );
+    }
+
+    #[test]
+    fn umask_is_read_as_octal() {
+        let umask = |raw: &str| serde_json::from_value::<Umask>(json!(raw));
+
+        assert_eq!(umask("0027").unwrap().mode(), 0o027);
+        assert_eq!(umask("77").unwrap().mode(), 0o077);
+        assert_eq!(serde_json::to_value(umask("7").unwrap()).unwrap(), "0007");
+    }
+
+    #[test]
+    fn invalid_umasks_are_rejected() {
+        for raw in ["", "0800", "01000", "00777", "rwx"] {
+            let err = serde_json::from_value::<Umask>(json!(raw)).unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                format!("Invalid umask {raw:?}, expected an octal string like \"0027\"")
+            );
+        }
+    }
+
+    #[test]
+    fn empty_commands_are_rejected_wherever_they_are_used() {
+        let err = serde_json::from_value::<ArgV>(json!([])).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "A command needs at least the binary to run, got an empty argument list"
+        );
+    }
+
+    #[test]
+    fn argv_splits_the_binary_from_its_arguments() {
+        let argv = serde_json::from_value::<ArgV>(json!(["a"])).unwrap();
+        assert_eq!(argv.binary(), "a");
+        assert!(argv.args().is_empty());
+
+        let argv = serde_json::from_value::<ArgV>(json!(["a", "b", "c"])).unwrap();
+        assert_eq!(argv.binary(), "a");
+        assert_eq!(argv.args(), ["b", "c"]);
+    }
+
+    #[test]
+    fn process_command_schema_takes_one_of_argv_and_command() {
+        let schema = serde_json::to_value(schemars::schema_for!(ProcessCommand)).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+
+        assert!(validator.is_valid(&json!({"argv": ["web"]})));
+        assert!(validator.is_valid(&json!({"argv": ["web"], "command": null})));
+        assert!(validator.is_valid(&json!({"command": "web --port 80"})));
+        assert!(validator.is_valid(&json!({"argv": [], "command": "web --port 80"})));
+        assert!(!validator.is_valid(&json!({"argv": ["web"], "command": "web"})));
+        assert!(!validator.is_valid(&json!({"argv": []})));
+        assert!(!validator.is_valid(&json!({})));
+    }
+
+    #[test]
+    fn nice_values_outside_the_range_are_rejected() {
+        for raw in [-20, 0, 19] {
+            assert_eq!(
+                serde_json::from_value::<Nice>(json!(raw)).unwrap().value(),
+                raw
+            );
+        }
+        for raw in [-21, 20] {
+            let err = serde_json::from_value::<Nice>(json!(raw)).unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                format!("Invalid nice value {raw}, expected a value between -20 and 19")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn environment_overrides_secret_files_which_override_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("env");
+        let secret = tmp.path().join("secret");
+        std::fs::write(&file, "# comment\nA=file\nexport B=file\nC=file\n").unwrap();
+        std::fs::write(&secret, "B=secret\nC=secret\n").unwrap();
+        let process = process(json!({
+            "argv": ["true"],
+            "environmentFiles": [file],
+            "environmentSecretFiles": [secret],
+            "environment": {"C": "environment"},
+        }));
+
+        let environment = process.read_environment().await.unwrap();
+
+        assert_eq!(environment["A"], "file");
+        assert_eq!(environment["B"], "secret");
+        assert_eq!(environment["C"], "environment");
+    }
+
+    #[tokio::test]
+    async fn missing_environment_files_fail() {
+        let tmp = tempfile::tempdir().unwrap();
+        let missing = tmp.path().join("missing");
+        let process = process(json!({"argv": ["true"], "environmentFiles": [missing]}));
+
+        let err = process.read_environment().await.unwrap_err();
+
+        assert!(err.to_string().contains("Failed to read environment file"));
+    }
+
+    #[test]
+    fn environment_file_values_are_unquoted() {
+        let contents = "SINGLE='a \"b\" $c' # comment\nDOUBLE=\"a\\n\\\"b\\\"\"\nBARE=a b\n";
+
+        let variables = parse_environment_file(Path::new("env"), contents, false).unwrap();
+
+        assert_eq!(
+            variables,
+            [
+                ("SINGLE".to_owned(), "a \"b\" $c".to_owned()),
+                ("DOUBLE".to_owned(), "a\n\"b\"".to_owned()),
+                ("BARE".to_owned(), "a b".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn environment_file_errors_name_the_file_and_line() {
+        let err = parse_environment_file(Path::new("env"), "A=1\n\nB='unterminated\n", false)
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Failed to parse environment file \"env\" at line 3"
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_names_in_secret_files_are_hidden() {
+        let tmp = tempfile::tempdir().unwrap();
+        let secret = tmp.path().join("secret");
+        std::fs::write(&secret, "hunter-2=x\n").unwrap();
+        let process = process(json!({"argv": ["true"], "environmentSecretFiles": [secret]}));
+
+        let err = format!("{:#}", process.read_environment().await.unwrap_err());
+
+        assert!(!err.contains("hunter"), "{err}");
+    }
+}
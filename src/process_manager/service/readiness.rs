@@ -0,0 +1,28 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::{DurationMilliSeconds, serde_as};
+use std::time::Duration;
+
+use crate::process_manager::service::ArgV;
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+/// Service readiness check configuration
+///
+/// The command is polled after the service has been spawned until it exits
+/// successfully, at which point the service is considered ready
+pub struct Readiness {
+    /// Argv of the command to run as the readiness check
+    pub command: ArgV,
+
+    /// The amount of time (in milliseconds) to wait between checks
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub interval: Duration,
+
+    /// The amount of time (in milliseconds) a single check may take
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub timeout: Duration,
+
+    /// The maximum amount of checks before the service is considered failed
+    pub retries: usize,
+}
@@ -0,0 +1,32 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Listening socket which nimi binds and passes on to the service
+///
+/// Follows the systemd socket activation protocol, so the service finds the sockets
+/// starting at file descriptor 3 with `LISTEN_FDS` and `LISTEN_FDNAMES` set
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Socket {
+    /// Address to listen on, either `host:port` or the path of a unix socket
+    pub listen: String,
+
+    /// Kind of socket to bind
+    #[serde(rename = "type", default)]
+    pub kind: SocketType,
+
+    /// Name given to the socket in `LISTEN_FDNAMES`, defaults to the service name
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// Kind of a listening socket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SocketType {
+    /// TCP or unix stream socket
+    #[default]
+    Stream,
+
+    /// UDP or unix datagram socket
+    Datagram,
+}
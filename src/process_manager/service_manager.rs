@@ -4,94 +4,342 @@
 //! `Service`
 
 use std::{
-    path::PathBuf,
+    collections::{HashMap, HashSet, VecDeque},
+    env,
+    ffi::{OsStr, OsString},
+    fmt,
+    io::{self, ErrorKind},
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
     process::{ExitStatus, Stdio},
-    sync::Arc,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
 };
 
-use eyre::{Context, Result};
-use log::{debug, info};
+use eyre::{Context, Result, eyre};
+use futures::future::OptionFuture;
+use log::{LevelFilter, debug, error, info, warn};
+use nix::{
+    sys::signal::{Signal, killpg},
+    unistd::{Pid, dup2, setsid},
+};
 use thiserror::Error;
-use tokio::time::timeout;
+use tokio::time::{timeout, timeout_at};
 use tokio::{
+    fs,
     process::{Child, Command},
+    sync::{Mutex, Notify, broadcast, mpsc, watch},
     task::JoinSet,
 };
 
+pub mod cgroup;
 pub mod config_dir;
+pub mod credentials;
+pub mod log_file;
 pub mod logger;
+pub mod pty;
+pub mod sockets;
+pub mod syslog;
+pub mod watchdog;
 
-pub use config_dir::ConfigDir;
-pub use logger::Logger;
+pub use cgroup::ServiceCgroup;
+pub use config_dir::{CONFIG_DIR_VAR, ConfigDir};
+pub use credentials::Credentials;
+pub use log_file::{LogFile, SharedLogFile};
+pub use logger::{LogOutput, LogSink, Logger};
+pub use pty::{Pty, PtyReader};
+pub use sockets::ListenSockets;
 use tokio_util::sync::CancellationToken;
+pub use watchdog::WatchdogSocket;
 
-use crate::process_manager::{Service, Settings, settings::RestartMode};
+use crate::config::resolve_binary;
+use crate::process_manager::{
+    Service, ServiceType, Settings,
+    notify::NOTIFY_SOCKET,
+    service::{ArgV, Limit, Readiness, Stdin, StdioMode},
+    settings::{FailurePolicy, Restart, RestartMode},
+    status::{ServiceState, StatusBoard},
+    supervisor::StopHandles,
+};
 use crate::subreaper::{ChildGuard, Subreaper};
 
 /// Responsible for the running of and managing of service state
 pub struct ServiceManager {
     settings: Arc<Settings>,
     cancel_tok: CancellationToken,
+    drain_tok: CancellationToken,
+    stop_handles: StopHandles,
+    launched: CancellationToken,
+    previous_launched: Option<CancellationToken>,
+    shutdown_signal: Arc<OnceLock<Signal>>,
+    forwarded_signals: broadcast::Sender<Signal>,
+    status: StatusBoard,
 
     name: Arc<String>,
     service: Service,
 
     current_restart_count: usize,
+    restart_attempt: u32,
+    recent_starts: VecDeque<Instant>,
+    crash_looping: bool,
 
     config_dir: ConfigDir,
-    logs_dir: Arc<Option<PathBuf>>,
+    logs_file: Option<PathBuf>,
+    log_output: LogOutput,
+    sockets: ListenSockets,
+    cgroup: Option<ServiceCgroup>,
+    watchdog: Option<WatchdogSocket>,
+
+    ready: watch::Sender<bool>,
+    dependencies: HashMap<String, watch::Receiver<bool>>,
+    commands: Option<mpsc::Receiver<ServiceCommand>>,
+}
+
+/// Command sent to a running `ServiceManager`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceCommand {
+    /// Stop the service gracefully and don't restart it, leaving the other services
+    /// running
+    Stop,
 }
 
 /// Errors which can occur during service management
 #[derive(Error, Debug)]
 pub enum ServiceError {
     /// Error for when the process exits with a non zero exit code
-    #[error("Service exited with status - {status:?}")]
+    #[error("Service exited with {}", ExitDescription(*.status))]
     ProcessExited {
         /// Exit status
         status: ExitStatus,
     },
+
+    /// Error for when the process never passed its readiness check
+    #[error("Service failed its readiness check")]
+    NotReady,
+
+    /// Error for when the service didn't become ready within its start timeout
+    #[error("Service didn't become ready within its start timeout")]
+    StartTimedOut,
+
+    /// Error for when the service wrote no output within its output watchdog timeout
+    #[error("Service wrote no output for {0:?}")]
+    OutputStalled(Duration),
+
+    /// Error for when the service stopped sending its watchdog pings
+    #[error("Service missed its watchdog ping for {0:?}")]
+    WatchdogTimedOut(Duration),
+
+    /// Error for when the service was started too often within the start limit's window
+    #[error("Service was started too often, giving up")]
+    StartLimitHit,
+
+    /// Error for when a hook of the service exits with a non zero exit code
+    #[error("Service hook {hook:?} exited with {}", ExitDescription(*.status))]
+    HookFailed {
+        /// Binary of the hook
+        hook: String,
+        /// Exit status
+        status: ExitStatus,
+    },
+}
+
+impl ServiceError {
+    /// Exit code nimi should exit with because of this error
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::ProcessExited { status } | Self::HookFailed { status, .. } => {
+                ExitDescription(*status).exit_code()
+            }
+            Self::NotReady
+            | Self::StartTimedOut
+            | Self::OutputStalled(_)
+            | Self::WatchdogTimedOut(_)
+            | Self::StartLimitHit => None,
+        }
+    }
+}
+
+/// Human readable description of how a process exited
+///
+/// Names the signal which killed the process, where `ExitStatus` only gives its number
+pub struct ExitDescription(pub ExitStatus);
+
+impl ExitDescription {
+    /// Exit code of the process
+    ///
+    /// Follows the shell convention of `128 + signal` for processes killed by a signal
+    pub fn exit_code(&self) -> Option<i32> {
+        let status = self.0;
+        status
+            .code()
+            .or_else(|| status.signal().map(|signal| 128 + signal))
+    }
+}
+
+impl fmt::Display for ExitDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = self.0;
+        if let Some(code) = status.code() {
+            return write!(f, "exit code {code}");
+        }
+        let Some(signal) = status.signal() else {
+            return write!(f, "{status}");
+        };
+
+        // Real-time signals have no name
+        let name = Signal::try_from(signal)
+            .map_or_else(|_| signal.to_string(), |signal| signal.as_str().to_owned());
+        write!(f, "signal {name}")?;
+        if status.core_dumped() {
+            f.write_str(" (core dumped)")?;
+        }
+
+        Ok(())
+    }
 }
 
 /// Used to initialize the Service Manager in a structured manner
 pub struct ServiceManagerOpts {
     /// Directory to store logs in
     pub logs_dir: Arc<Option<PathBuf>>,
+    /// How log lines get printed
+    pub log_output: LogOutput,
     /// Temporary directory
     pub tmp_dir: Arc<PathBuf>,
 
     /// Process manager settings
     pub settings: Arc<Settings>,
 
+    /// Signal which triggered the shutdown, forwarded to the service
+    pub shutdown_signal: Arc<OnceLock<Signal>>,
+
+    /// Signals received by nimi which are relayed to the service process
+    pub forwarded_signals: broadcast::Sender<Signal>,
+
+    /// State of the services, updated as this one changes
+    pub status: StatusBoard,
+
     /// Service name
     pub name: Arc<String>,
 
     /// Service config
     pub service: Service,
 
-    /// Cancellation token
+    /// Config directory of the service, created before its task is spawned
+    pub config_dir: ConfigDir,
+
+    /// Cancellation token stopping only this service
+    ///
+    /// Cancelling it runs the graceful shutdown of the process and makes `run` return
+    /// `Ok(())` without restarting. Pass a child token to keep stopping a single service
+    /// apart from the shutdown of nimi
     pub cancel_tok: CancellationToken,
+
+    /// Cancellation token for draining
+    ///
+    /// Once cancelled the service is no longer started or restarted, but a running process
+    /// is left to exit on its own
+    pub drain_tok: CancellationToken,
+
+    /// Tokens for stopping the other services, used to stop the conflicting ones
+    pub stop_handles: StopHandles,
+
+    /// Cancelled once this service launched its process, or has to wait before it can,
+    /// which lets the next service in the start order launch
+    pub launched: CancellationToken,
+
+    /// `launched` of the service before this one in the start order
+    pub previous_launched: Option<CancellationToken>,
+
+    /// Readiness of this service
+    pub ready: watch::Sender<bool>,
+
+    /// Readiness of the services this one is ordered `after`
+    pub dependencies: HashMap<String, watch::Receiver<bool>>,
+
+    /// Commands for this service, see `ServiceCommand`
+    pub commands: mpsc::Receiver<ServiceCommand>,
 }
 
 impl ServiceManager {
+    /// How often `shutdown_process` checks if the process group is gone
+    const GROUP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
     /// Creates a new Service Manager
     ///
     /// This creates the corresponding processes and supervises the operation for a given
     /// `Service`.
     ///
-    /// This also produces a `ConfigDir` instance per service.
+    /// This also binds the sockets and watchdog socket of the service and creates its
+    /// cgroup.
     pub async fn new(opts: ServiceManagerOpts) -> Result<Self> {
+        opts.status.set_state(&opts.name, ServiceState::Starting);
+        let sockets = ListenSockets::bind(&opts.service.sockets, &opts.name)?;
+        let cgroup = match &opts.service.process.cgroup {
+            Some(cgroup) => ServiceCgroup::create(cgroup, &opts.name)
+                .await
+                .inspect_err(|e| {
+                    warn!("Running service {} without its cgroup: {e:#}", opts.name);
+                })
+                .ok(),
+            None => None,
+        };
+        let watchdog = match opts.service.watchdog {
+            Some(interval) => Some(
+                async {
+                    // Services running as another user have to be able to reach the socket
+                    let owner = opts
+                        .service
+                        .process
+                        .user
+                        .as_ref()
+                        .map(Credentials::resolve_uid)
+                        .transpose()?;
+
+                    WatchdogSocket::bind(&opts.tmp_dir, interval, owner).await
+                }
+                .await
+                .wrap_err_with(|| {
+                    format!("Failed to set up the watchdog of service {}", opts.name)
+                })?,
+            ),
+            None => None,
+        };
+        let logs_file = opts.service.log_file.clone().or_else(|| {
+            (*opts.logs_dir)
+                .as_ref()
+                .map(|logs_dir| logs_dir.join(format!("{}.txt", opts.name)))
+        });
+
         Ok(Self {
-            config_dir: ConfigDir::new(&opts.tmp_dir, &opts.service.config_data).await?,
+            config_dir: opts.config_dir,
 
             settings: opts.settings,
             cancel_tok: opts.cancel_tok,
+            drain_tok: opts.drain_tok,
+            stop_handles: opts.stop_handles,
+            launched: opts.launched,
+            previous_launched: opts.previous_launched,
+            shutdown_signal: opts.shutdown_signal,
+            forwarded_signals: opts.forwarded_signals,
+            status: opts.status,
 
             name: opts.name,
             service: opts.service,
 
             current_restart_count: 0,
-            logs_dir: opts.logs_dir,
+            restart_attempt: 0,
+            recent_starts: VecDeque::new(),
+            crash_looping: false,
+            logs_file,
+            log_output: opts.log_output,
+            sockets,
+            cgroup,
+            watchdog,
+
+            ready: opts.ready,
+            dependencies: opts.dependencies,
+            commands: Some(opts.commands),
         })
     }
 
@@ -99,141 +347,2570 @@ impl ServiceManager {
     ///
     /// This will handle restarts, attach logging processes and manage linking the config
     /// directory.
+    ///
+    /// Returns `Ok(())` without restarting once a `ServiceCommand::Stop` is received
     pub async fn run(&mut self) -> Result<()> {
-        while let Err(e) = self.spawn_service_process().await {
-            match e.downcast_ref() {
-                Some(ServiceError::ProcessExited { status }) => {
-                    info!("Process {} exited with status {}", &self.name, status)
+        let Some(mut commands) = self.commands.take() else {
+            return self.run_service().await;
+        };
+        // Stopping goes through the same graceful shutdown as the shutdown of nimi
+        let cancel_tok = self.cancel_tok.clone();
+        let name = Arc::clone(&self.name);
+
+        let run = self.run_service();
+        tokio::pin!(run);
+        loop {
+            tokio::select! {
+                res = &mut run => return res,
+                Some(command) = commands.recv() => match command {
+                    ServiceCommand::Stop => {
+                        debug!("Service {name} was told to stop");
+                        cancel_tok.cancel();
+                    }
+                },
+            }
+        }
+    }
+
+    async fn run_service(&mut self) -> Result<()> {
+        if !self.wait_for_launch_turn().await {
+            return Ok(());
+        }
+        let blocked = self.dependencies.values().any(|ready| !*ready.borrow());
+        if blocked || self.service.start_delay.is_some() {
+            self.launched.cancel();
+        }
+
+        if !self.wait_for_dependencies().await? {
+            info!(
+                "Received shutdown while {} was waiting on dependencies",
+                self.name
+            );
+            return Ok(());
+        }
+        if !self.wait_for_start_delay().await {
+            info!(
+                "Received shutdown while {} was waiting for its start delay",
+                self.name
+            );
+            return Ok(());
+        }
+
+        loop {
+            let started_at = Instant::now();
+            self.recent_starts.push_back(started_at);
+            self.forget_old_starts();
+            let result = self.spawn_service_process().await;
+
+            if self.cancel_tok.is_cancelled() {
+                break;
+            }
+
+            let failure = match result {
+                Ok(()) => {
+                    info!("Process {} exited successfully", &self.name);
+                    None
+                }
+                Err(e) => match e.downcast_ref() {
+                    Some(ServiceError::ProcessExited { status }) => {
+                        if status.signal().is_some() {
+                            warn!(
+                                "Process {} was killed unexpectedly by {}",
+                                self.name,
+                                ExitDescription(*status)
+                            );
+                        } else {
+                            info!(
+                                "Process {} exited with {}",
+                                self.name,
+                                ExitDescription(*status)
+                            );
+                        }
+                        Some(e)
+                    }
+                    Some(
+                        ServiceError::NotReady
+                        | ServiceError::StartTimedOut
+                        | ServiceError::OutputStalled(_)
+                        | ServiceError::WatchdogTimedOut(_)
+                        | ServiceError::HookFailed { .. },
+                    ) => Some(e),
+                    None if self.settings.failure_policy == FailurePolicy::RestartOnly => {
+                        error!("Service {} failed: {e:?}", self.name);
+                        Some(e)
+                    }
+                    _ => return Err(e),
+                },
+            };
+            let failed = failure.is_some();
+
+            if self.service.kind == ServiceType::Oneshot {
+                if !failed {
+                    info!("Oneshot service {} completed", self.name);
                 }
-                None => return Err(e),
+
+                return failure.map_or(Ok(()), Err);
+            }
+
+            if self.drain_tok.is_cancelled() {
+                info!("Not restarting {} while draining", self.name);
+
+                return failure.map_or(Ok(()), Err);
+            }
+
+            if self
+                .restart()
+                .success_threshold
+                .is_some_and(|threshold| started_at.elapsed() >= threshold)
+            {
+                debug!("Service {} was stable, resetting restart count", self.name);
+                self.current_restart_count = 0;
+                self.restart_attempt = 0;
+            }
+
+            if !failed && !matches!(self.restart().mode, RestartMode::Always) {
+                info!("Not restarting {} after a successful exit", self.name);
+
+                break;
             }
 
-            match self.settings.restart.mode {
+            if !matches!(self.restart().mode, RestartMode::Never) && self.start_limit_hit() {
+                return Err(failure.unwrap_or_else(|| ServiceError::StartLimitHit.into()));
+            }
+
+            match self.restart().mode {
                 RestartMode::Always => info!("restarting (mode: always)"),
+                RestartMode::OnFailure => info!("Restarting (mode: on-failure)"),
                 RestartMode::UpToCount => {
-                    if self.current_restart_count >= self.settings.restart.count {
+                    if self.current_restart_count >= self.restart().count {
                         info!(
                             "Not restarting (mode: up-to-count {}/{})",
-                            self.current_restart_count, self.settings.restart.count
+                            self.current_restart_count,
+                            self.restart().count
                         );
-                        break;
+
+                        return failure.map_or(Ok(()), Err);
                     }
 
                     self.current_restart_count += 1;
 
                     info!(
                         "Restarting (mode: up-to-count {}/{})",
-                        self.current_restart_count, self.settings.restart.count
+                        self.current_restart_count,
+                        self.restart().count
                     );
                 }
                 RestartMode::Never => {
                     info!("Not restarting (mode: never)");
 
-                    break;
+                    return failure.map_or(Ok(()), Err);
                 }
             }
 
+            let crash_looping = self.check_crash_loop();
+            self.status.record_restart(&self.name, crash_looping);
+            let delay = self.restart().delay(self.restart_attempt);
+            self.restart_attempt = self.restart_attempt.saturating_add(1);
+            debug!("Waiting {delay:?} before restarting {}", self.name);
+
             tokio::select! {
-                _ = tokio::time::sleep(self.settings.restart.time) => {},
+                _ = tokio::time::sleep(delay) => {},
                 _ = self.cancel_tok.cancelled() => {
                     info!("Received shutdown during restart delay for {}", self.name);
                     break;
                 }
+                _ = self.drain_tok.cancelled() => {
+                    info!("Received drain during restart delay for {}", self.name);
+                    break;
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Restart settings of the service, falling back to `settings.restart`
+    fn restart(&self) -> &Restart {
+        self.service
+            .restart
+            .as_ref()
+            .unwrap_or(&self.settings.restart)
+    }
+
+    /// Forget about the starts which fell out of the windows of both
+    /// `restart.start_limit` and `restart.crash_loop`
+    fn forget_old_starts(&mut self) {
+        let restart = self.restart();
+        let window = [
+            restart.start_limit.map(|limit| limit.interval),
+            restart.crash_loop.map(|crash_loop| crash_loop.interval),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or_default();
+
+        while self
+            .recent_starts
+            .front()
+            .is_some_and(|start| start.elapsed() > window)
+        {
+            self.recent_starts.pop_front();
+        }
+    }
+
+    /// Number of starts of the service within the last `window`
+    fn starts_within(&self, window: Duration) -> usize {
+        self.recent_starts
+            .iter()
+            .filter(|start| start.elapsed() <= window)
+            .count()
+    }
+
+    /// Check if another start would exceed `restart.start_limit`
+    fn start_limit_hit(&self) -> bool {
+        let Some(limit) = self.restart().start_limit else {
+            return false;
+        };
+
+        let hit = self.starts_within(limit.interval) >= limit.burst;
+        if hit {
+            info!(
+                "Not restarting {} (start limit: {} starts within {:?})",
+                self.name, limit.burst, limit.interval
+            );
+        }
+
+        hit
+    }
+
+    /// Check if the service is crash-looping according to `restart.crash_loop`
+    ///
+    /// Logs once when the service starts and stops crash-looping
+    fn check_crash_loop(&mut self) -> bool {
+        let Some(crash_loop) = self.restart().crash_loop else {
+            return false;
+        };
+
+        let restarts = self.starts_within(crash_loop.interval);
+        let crash_looping = restarts >= crash_loop.restarts;
+        match (self.crash_looping, crash_looping) {
+            (false, true) => error!(
+                "Service {} is crash-looping ({restarts} restarts within {:?})",
+                self.name, crash_loop.interval
+            ),
+            (true, false) => info!("Service {} is no longer crash-looping", self.name),
+            _ => {}
+        }
+        self.crash_looping = crash_looping;
+
+        crash_looping
+    }
+
+    /// Wait for the service before this one in the start order to launch
+    ///
+    /// Returns `false` if the service was stopped in the meantime
+    async fn wait_for_launch_turn(&self) -> bool {
+        let Some(previous) = &self.previous_launched else {
+            return true;
+        };
+
+        tokio::select! {
+            _ = previous.cancelled() => true,
+            _ = self.cancel_tok.cancelled() => false,
+        }
+    }
+
+    /// Wait for every service this one is ordered `after` to become ready
+    ///
+    /// A dependency is ready once its readiness check passed or, without one, once
+    /// its process has been spawned. Returns `false` if a shutdown or drain was received
+    /// before the service could start.
+    async fn wait_for_dependencies(&mut self) -> Result<bool> {
+        if self.drain_tok.is_cancelled() {
+            return Ok(false);
+        }
+
+        for (dependency, ready) in &mut self.dependencies {
+            debug!(
+                "Service {} is waiting for dependency {dependency}",
+                self.name
+            );
+
+            tokio::select! {
+                res = ready.wait_for(|ready| *ready) => {
+                    res.wrap_err_with(|| {
+                        format!("Dependency {dependency} stopped before becoming ready")
+                    })?;
+                }
+                _ = self.cancel_tok.cancelled() => return Ok(false),
+                _ = self.drain_tok.cancelled() => return Ok(false),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Stop the services this one conflicts with and wait for them to finish
+    ///
+    /// Conflicts which have already stopped or exited are left alone, as are the ones
+    /// still starting because they are ordered after this service. Returns `false` if
+    /// this service was stopped in the meantime
+    async fn stop_conflicts(&self) -> bool {
+        for conflict in &self.service.conflicts {
+            match self.status.state(conflict) {
+                Some(ServiceState::Stopped | ServiceState::Failed) => continue,
+                // Not having a state yet means its manager hasn't been created yet
+                None | Some(ServiceState::Starting) if self.is_waited_on_by(conflict) => continue,
+                _ => {}
+            }
+            let Some(handle) = self.stop_handles.get(conflict) else {
+                continue;
+            };
+
+            info!(
+                "Stopping service {conflict} before starting {}, they conflict",
+                self.name
+            );
+            handle.cancel_tok.cancel();
+            tokio::select! {
+                _ = handle.finished.cancelled() => {}
+                _ = self.cancel_tok.cancelled() => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Check if the service `name` is ordered after this one, directly or through others
+    fn is_waited_on_by(&self, name: &str) -> bool {
+        let mut pending = vec![name.to_owned()];
+        let mut seen = HashSet::new();
+
+        while let Some(name) = pending.pop() {
+            let Some(handle) = self.stop_handles.get(&name) else {
+                continue;
+            };
+            for dependency in handle.after.iter() {
+                if dependency.as_str() == self.name.as_str() {
+                    return true;
+                }
+                if seen.insert(dependency.clone()) {
+                    pending.push(dependency.clone());
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Wait for `startDelay` to pass
+    ///
+    /// Returns `false` if nimi started shutting down or draining in the meantime
+    async fn wait_for_start_delay(&self) -> bool {
+        let Some(delay) = self.service.start_delay else {
+            return true;
+        };
+        debug!("Service {} is waiting {delay:?} before starting", self.name);
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => true,
+            _ = self.cancel_tok.cancelled() => false,
+            _ = self.drain_tok.cancelled() => false,
+        }
+    }
+
+    /// Open the logs file shared by the stdout and stderr loggers
+    ///
+    /// Failing to open it is logged and the output only goes to the console
+    async fn open_logs_file(&self) -> Option<SharedLogFile> {
+        let path = self.logs_file.as_deref()?;
+
+        match LogFile::open(path, self.settings.logging.rotation).await {
+            Ok(logs_file) => Some(Arc::new(Mutex::new(Some(logs_file)))),
+            Err(e) => {
+                error!("Failed to open logs file of {}: {e:#}", self.name);
+                None
+            }
+        }
+    }
+
     /// Spawns a service process
     ///
     /// Attaches loggers and `wait`s on the process, forwarding
     /// shutdown sequeneces
     pub async fn spawn_service_process(&mut self) -> Result<()> {
-        let (mut process, _child_guard) = self.create_service_child().await?;
+        // Stopping conflicts and running hooks may take a while, the next service in the
+        // start order doesn't have to wait for them
+        if !self.service.conflicts.is_empty() || !self.service.exec_start_pre.is_empty() {
+            self.launched.cancel();
+        }
+        if !self.stop_conflicts().await {
+            return Ok(());
+        }
+        let logs_file = self.open_logs_file().await;
+
+        for hook in &self.service.exec_start_pre {
+            self.run_hook(hook, logs_file.clone(), &self.cancel_tok)
+                .await?;
+        }
+        if self.cancel_tok.is_cancelled() {
+            return Ok(());
+        }
+
+        // A oneshot service only becomes ready once it completed
+        let oneshot = self.service.kind == ServiceType::Oneshot;
+        let readiness_check = self.service.readiness.as_ref().filter(|_| !oneshot);
+
+        let (mut process, _child_guard, pty) = self.create_service_child().await?;
+        // Marked running first, the start times then follow the start order
+        self.status.set_state(&self.name, ServiceState::Running);
+        self.launched.cancel();
+        let mut forwarded_signals = self.forwarded_signals.subscribe();
+        if readiness_check.is_none() && !oneshot {
+            self.ready.send_replace(true);
+        }
+        let activity = Arc::new(Notify::new());
+        let output = self.log_output.clone().with_activity(Arc::clone(&activity));
         let mut set = JoinSet::new();
+        match pty {
+            Some(pty) => self.start_pty_logger(pty, logs_file.clone(), output, &mut set)?,
+            None => self.start_loggers(&mut process, logs_file.clone(), output, &mut set)?,
+        }
+
+        let post_hooks = async {
+            for hook in &self.service.exec_start_post {
+                self.run_hook(hook, logs_file.clone(), &self.cancel_tok)
+                    .await?;
+            }
+
+            Ok::<_, eyre::Report>(())
+        };
+        tokio::pin!(post_hooks);
+        let mut running_post_hooks = !self.service.exec_start_post.is_empty();
+
+        let readiness =
+            OptionFuture::from(readiness_check.map(|readiness| self.wait_until_ready(readiness)));
+        tokio::pin!(readiness);
+        let mut checking_readiness = readiness_check.is_some();
+
+        let start_timeout = OptionFuture::from(
+            self.service
+                .start_timeout
+                .filter(|_| oneshot || checking_readiness)
+                .map(tokio::time::sleep),
+        );
+        tokio::pin!(start_timeout);
+        let mut starting = self.service.start_timeout.is_some() && (oneshot || checking_readiness);
+
+        let output_watchdog = OptionFuture::from(
+            self.service
+                .output_watchdog
+                .filter(|_| self.service.process.output_is_read())
+                .map(|watchdog| Self::wait_for_silence(&activity, watchdog)),
+        );
+        tokio::pin!(output_watchdog);
+
+        // Every process of the service is in the process group led by the service process
+        let watchdog = OptionFuture::from(
+            self.watchdog
+                .as_ref()
+                .zip(process.id())
+                .map(|(watchdog, pid)| watchdog.missed_ping(&self.name, Pid::from_raw(pid as i32))),
+        );
+        tokio::pin!(watchdog);
+
+        let result = loop {
+            tokio::select! {
+                _ = self.cancel_tok.cancelled() => {
+                    debug!("Service {} received shutdown signal", self.name);
+                    break self.stop_service_process(&mut process, logs_file.clone()).await;
+                }
+                Ok(signal) = forwarded_signals.recv() => {
+                    if let Some(pid) = process.id() {
+                        debug!("Forwarding {signal} to {}", self.name);
+                        let _ = killpg(Pid::from_raw(pid as i32), signal);
+                    }
+                }
+                status = process.wait() => {
+                    if let Ok(status) = &status {
+                        self.status.record_exit(&self.name, ExitDescription(*status).exit_code());
+                    }
+                    break match status.wrap_err("Failed to get process status") {
+                        Ok(status) if self.service.process.is_success(status) => Ok(()),
+                        Ok(status) => Err(ServiceError::ProcessExited { status }.into()),
+                        Err(e) => Err(e),
+                    };
+                }
+                ready = &mut readiness, if checking_readiness => {
+                    checking_readiness = false;
+
+                    match ready.transpose() {
+                        Ok(Some(true)) => {
+                            info!("Service {} is ready", self.name);
+                            self.ready.send_replace(true);
+                            starting = false;
+                        }
+                        Ok(_) => {
+                            info!("Service {} failed its readiness check", self.name);
+                            self.abort_start(&mut process).await?;
+
+                            break Err(ServiceError::NotReady.into());
+                        }
+                        Err(e) => break Err(e),
+                    }
+                }
+                _ = &mut start_timeout, if starting => {
+                    info!("Service {} didn't become ready within its start timeout", self.name);
+                    self.abort_start(&mut process).await?;
+
+                    break Err(ServiceError::StartTimedOut.into());
+                }
+                Some(()) = &mut watchdog => {
+                    let interval = self.service.watchdog.unwrap_or_default();
+                    warn!("Service {} missed its watchdog ping for {interval:?}, killing it", self.name);
+                    self.stop_stalled(&mut process).await?;
+
+                    break Err(ServiceError::WatchdogTimedOut(interval).into());
+                }
+                Some(watchdog) = &mut output_watchdog => {
+                    warn!("Service {} wrote no output for {watchdog:?}, killing it", self.name);
+                    self.stop_stalled(&mut process).await?;
+
+                    break Err(ServiceError::OutputStalled(watchdog).into());
+                }
+                res = &mut post_hooks, if running_post_hooks => {
+                    running_post_hooks = false;
+
+                    if let Err(e) = res {
+                        self.abort_start(&mut process).await?;
+
+                        break Err(e);
+                    }
+                }
+            }
+        };
+
+        if oneshot && result.is_ok() && !self.cancel_tok.is_cancelled() {
+            self.ready.send_replace(true);
+        }
+
+        let logs: Result<()> = set.join_all().await.into_iter().collect();
+
+        result.and(logs)
+    }
+
+    /// Resolve once `watchdog` passes without `activity` being notified
+    async fn wait_for_silence(activity: &Notify, watchdog: Duration) -> Duration {
+        while timeout(watchdog, activity.notified()).await.is_ok() {}
+
+        watchdog
+    }
+
+    /// Attach the stdout and stderr loggers to a process of the service
+    ///
+    /// Nothing is attached when the service inherits the output of nimi
+    fn start_loggers(
+        &self,
+        process: &mut Child,
+        logs_file: Option<SharedLogFile>,
+        output: LogOutput,
+        set: &mut JoinSet<Result<()>>,
+    ) -> Result<()> {
+        if !self.service.process.output_is_read() {
+            return Ok(());
+        }
+        let output = output.with_level_regex(self.service.log_level_regex.clone());
+        let log_level = self.service.log_level.unwrap_or(LevelFilter::Trace);
 
-        Logger::Stdout.start(
+        Logger::Stdout(self.service.stdout_level).start(
             &mut process.stdout,
             Arc::clone(&self.name),
-            Arc::clone(&self.logs_dir),
-            &mut set,
-        )?;
-        Logger::Stderr.start(
-            &mut process.stderr,
-            Arc::clone(&self.name),
-            Arc::clone(&self.logs_dir),
-            &mut set,
+            logs_file.clone(),
+            output.clone(),
+            log_level,
+            set,
         )?;
-
-        tokio::select! {
-            _ = self.cancel_tok.cancelled() => {
-                debug!(target: &self.name, "Received shutdown signal");
-                Self::shutdown_process(&mut process, self.settings.restart.time).await?;
-            }
-            status = process.wait() => {
-                let status = status.wrap_err("Failed to get process status")?;
-                eyre::ensure!(
-                    status.success(),
-                    ServiceError::ProcessExited { status }
-                );
-            }
+        if !self.settings.logging.combine_output {
+            Logger::Stderr(self.service.stderr_level).start(
+                &mut process.stderr,
+                Arc::clone(&self.name),
+                logs_file,
+                output,
+                log_level,
+                set,
+            )?;
         }
 
-        set.join_all().await.into_iter().collect()
+        Ok(())
     }
 
-    /// Kill a service process gracefully
-    pub async fn shutdown_process(
+    /// Attach the stdout logger to the pty of the service process
+    fn start_pty_logger(
+        &self,
+        pty: PtyReader,
+        logs_file: Option<SharedLogFile>,
+        output: LogOutput,
+        set: &mut JoinSet<Result<()>>,
+    ) -> Result<()> {
+        Logger::Stdout(self.service.stdout_level).start(
+            &mut Some(pty),
+            Arc::clone(&self.name),
+            logs_file,
+            output.with_level_regex(self.service.log_level_regex.clone()),
+            self.service.log_level.unwrap_or(LevelFilter::Trace),
+            set,
+        )
+    }
+
+    /// Stop the service process gracefully
+    ///
+    /// Runs the `exec_stop_pre` hooks before forwarding the shutdown signal. The hooks
+    /// share the shutdown timeout with the process, which gets killed once it runs out
+    async fn stop_service_process(
+        &self,
         process: &mut Child,
-        timeout_duration: std::time::Duration,
+        logs_file: Option<SharedLogFile>,
     ) -> Result<()> {
-        #[cfg(unix)]
-        {
-            use nix::sys::signal::{Signal, kill};
-            use nix::unistd::Pid;
+        let deadline = Instant::now() + self.settings.shutdown.timeout;
 
-            if let Some(pid) = process.id() {
-                let pid = Pid::from_raw(pid as i32);
-                let _ = kill(pid, Signal::SIGTERM);
-                if timeout(timeout_duration, process.wait()).await.is_err() {
-                    let _ = kill(pid, Signal::SIGKILL);
-                    let _ = process.wait().await;
+        if !self.service.exec_stop_pre.is_empty() {
+            // The shutdown already cancelled `cancel_tok`, so the hooks get their own
+            let hooks_tok = CancellationToken::new();
+            let hooks = async {
+                for hook in &self.service.exec_stop_pre {
+                    self.run_hook(hook, logs_file.clone(), &hooks_tok).await?;
                 }
 
-                return Ok(());
+                Ok::<_, eyre::Report>(())
+            };
+
+            match tokio::time::timeout_at(deadline.into(), hooks).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("Stop hooks of {} failed: {e:?}", self.name),
+                Err(_) => info!(
+                    "Stop hooks of {} exceeded the shutdown timeout, killing it",
+                    self.name
+                ),
             }
         }
 
-        process
-            .kill()
-            .await
-            .wrap_err("Failed to kill service process")
+        let status = Self::shutdown_process(
+            process,
+            Self::forwarded_signal(&self.shutdown_signal),
+            deadline.saturating_duration_since(Instant::now()),
+            self.settings.shutdown.kill_timeout,
+        )
+        .await?;
+        self.record_stop(status);
+        if let Some(status) = status {
+            info!(
+                "Process {} was stopped by nimi, it exited with {}",
+                self.name,
+                ExitDescription(status)
+            );
+        }
+
+        Ok(())
     }
 
-    /// Create service child
+    /// Stop a process which failed to start, without running the stop hooks
+    async fn abort_start(&self, process: &mut Child) -> Result<()> {
+        self.stop_without_hooks(process).await?;
+
+        Ok(())
+    }
+
+    /// Stop a running process which stopped responding, without running the stop hooks
     ///
-    /// Responsible for creating the actual child process for the
-    /// service
-    pub async fn create_service_child(&self) -> Result<(Child, ChildGuard)> {
-        let _pause = Subreaper::pause_reaping();
-        let process = Command::new(self.service.process.argv.binary())
-            .args(self.service.process.argv.args())
-            .env("XDG_CONFIG_HOME", &self.config_dir)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .kill_on_drop(true)
-            .spawn()
-            .wrap_err_with(|| {
-                format!(
-                    "Failed to start process for service: {:?}",
-                    self.service.process
+    /// Its watchdog already gave up on it, it can't be expected to react to the hooks
+    async fn stop_stalled(&self, process: &mut Child) -> Result<()> {
+        if let Some(status) = self.stop_without_hooks(process).await? {
+            info!(
+                "Process {} was stopped by nimi after stalling, it exited with {}",
+                self.name,
+                ExitDescription(status)
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn stop_without_hooks(&self, process: &mut Child) -> Result<Option<ExitStatus>> {
+        let shutdown = &self.settings.shutdown;
+        let status = Self::shutdown_process(
+            process,
+            Signal::SIGTERM,
+            shutdown.timeout,
+            shutdown.kill_timeout,
+        )
+        .await?;
+        self.record_stop(status);
+
+        Ok(status)
+    }
+
+    /// Record the exit of a process stopped by nimi, which has no exit code when it
+    /// was left behind
+    fn record_stop(&self, status: Option<ExitStatus>) {
+        self.status.record_exit(
+            &self.name,
+            status.and_then(|status| ExitDescription(status).exit_code()),
+        );
+    }
+
+    /// Run a hook of the service to completion
+    ///
+    /// Stops the hook early once `cancel_tok` is cancelled
+    async fn run_hook(
+        &self,
+        hook: &ArgV,
+        logs_file: Option<SharedLogFile>,
+        cancel_tok: &CancellationToken,
+    ) -> Result<()> {
+        debug!("Running hook {:?} of {}", hook.binary(), self.name);
+
+        let mut command = self.service_command(hook.binary(), hook.args()).await?;
+        let (mut process, _child_guard) = {
+            let _pause = Subreaper::pause_reaping();
+            let process = command
+                .spawn()
+                .map_err(|e| Self::spawn_error(&command, hook.binary(), e))
+                .wrap_err_with(|| {
+                    format!(
+                        "Failed to start hook {:?} for service {}",
+                        hook.binary(),
+                        self.name
+                    )
+                })?;
+            let guard =
+                Subreaper::track_child(process.id()).wrap_err("Failed to track hook child")?;
+
+            (process, guard)
+        };
+
+        let mut set = JoinSet::new();
+        self.start_loggers(&mut process, logs_file, self.log_output.clone(), &mut set)?;
+
+        let result = tokio::select! {
+            _ = cancel_tok.cancelled() => {
+                Self::shutdown_process(
+                    &mut process,
+                    Self::forwarded_signal(&self.shutdown_signal),
+                    self.settings.shutdown.timeout,
+                    self.settings.shutdown.kill_timeout,
                 )
-            })?;
+                .await
+                .map(|_| ())
+            }
+            status = process.wait() => match status.wrap_err("Failed to get hook status") {
+                Ok(status) if status.success() => Ok(()),
+                Ok(status) => Err(ServiceError::HookFailed {
+                    hook: hook.binary().to_owned(),
+                    status,
+                }
+                .into()),
+                Err(e) => Err(e),
+            },
+        };
+
+        let logs: Result<()> = set.join_all().await.into_iter().collect();
+
+        result.and(logs)
+    }
+
+    /// Poll the readiness check until it succeeds
+    ///
+    /// Returns `false` once every retry has been used up
+    async fn wait_until_ready(&self, readiness: &Readiness) -> Result<bool> {
+        for attempt in 1..=readiness.retries {
+            tokio::time::sleep(readiness.interval).await;
+
+            if self.run_readiness_check(readiness).await? {
+                return Ok(true);
+            }
+
+            debug!(
+                "Readiness check of {} failed ({attempt}/{})",
+                self.name, readiness.retries
+            );
+        }
+
+        Ok(false)
+    }
+
+    async fn run_readiness_check(&self, readiness: &Readiness) -> Result<bool> {
+        let environment = self.read_environment().await?;
+        let mut command = Command::new(readiness.command.binary());
+        command
+            .args(self.interpolate_args(readiness.command.args()))
+            .envs(&environment)
+            .env(
+                &self.service.config_env_var,
+                self.config_env_value(&environment),
+            )
+            .env(CONFIG_DIR_VAR, &self.config_dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+
+        let (mut check, _child_guard) = {
+            let _pause = Subreaper::pause_reaping();
+            let check = command.spawn().wrap_err_with(|| {
+                format!("Failed to start readiness check for service {}", self.name)
+            })?;
+            let guard = Subreaper::track_child(check.id())
+                .wrap_err("Failed to track readiness check child")?;
+
+            (check, guard)
+        };
+
+        match timeout(readiness.timeout, check.wait()).await {
+            Ok(status) => Ok(status
+                .wrap_err("Failed to get readiness check status")?
+                .success()),
+            Err(_) => {
+                let _ = check.kill().await;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Signal to forward to services on shutdown
+    ///
+    /// Uses the signal nimi itself received, defaulting to `SIGTERM`
+    pub fn forwarded_signal(shutdown_signal: &OnceLock<Signal>) -> Signal {
+        shutdown_signal.get().copied().unwrap_or(Signal::SIGTERM)
+    }
+
+    /// Run the process spawned by `command` in a session of its own
+    ///
+    /// The process then leads its own process group, which lets `shutdown_process` signal
+    /// every process it forked along with it
+    pub fn new_session(command: &mut Command) {
+        // SAFETY: `setsid(2)` is async-signal-safe, it only fails for a process group
+        // leader which the freshly forked child never is
+        unsafe {
+            command.pre_exec(|| {
+                setsid()?;
+                Ok(())
+            });
+        }
+    }
+
+    /// Kill a service process and its process group gracefully
+    ///
+    /// Sends `signal` to the whole group first and escalates to `SIGKILL` once
+    /// `timeout_duration` elapses, also when only descendants of the process are left.
+    /// The process has to be started with `new_session`
+    ///
+    /// Returns how the process itself exited, or `None` when it is still there
+    /// `kill_timeout` after `SIGKILL` and was left behind
+    pub async fn shutdown_process(
+        process: &mut Child,
+        signal: Signal,
+        timeout_duration: std::time::Duration,
+        kill_timeout: Duration,
+    ) -> Result<Option<ExitStatus>> {
+        #[cfg(unix)]
+        {
+            if let Some(pid) = process.id() {
+                let pgid = Pid::from_raw(pid as i32);
+                let deadline = Instant::now() + timeout_duration;
+
+                let _ = killpg(pgid, signal);
+                let status = match timeout_at(deadline.into(), process.wait()).await {
+                    Ok(status) => status,
+                    Err(_) => {
+                        let _ = killpg(pgid, Signal::SIGKILL);
+                        return Self::wait_killed(process, kill_timeout).await;
+                    }
+                };
+
+                while killpg(pgid, None).is_ok() {
+                    if Instant::now() >= deadline {
+                        let _ = killpg(pgid, Signal::SIGKILL);
+                        break;
+                    }
+                    tokio::time::sleep(Self::GROUP_POLL_INTERVAL).await;
+                }
+
+                return status.map(Some).wrap_err("Failed to get process status");
+            }
+        }
+
+        process
+            .start_kill()
+            .wrap_err("Failed to kill service process")?;
+        Self::wait_killed(process, kill_timeout).await
+    }
+
+    /// Wait for a process which was sent `SIGKILL`, for at most `kill_timeout`
+    ///
+    /// A process in uninterruptible sleep only dies once its syscall returns, waiting
+    /// for it without a bound could hang the shutdown forever
+    async fn wait_killed(
+        process: &mut Child,
+        kill_timeout: Duration,
+    ) -> Result<Option<ExitStatus>> {
+        match timeout(kill_timeout, process.wait()).await {
+            Ok(status) => status.map(Some).wrap_err("Failed to get process status"),
+            Err(_) => {
+                warn!(
+                    "Process {} is still there {kill_timeout:?} after SIGKILL and seems to be unkillable, leaving it behind",
+                    process.id().map_or("?".to_owned(), |pid| pid.to_string())
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Create service child
+    ///
+    /// Responsible for creating the actual child process for the
+    /// service. Returns the reader of its pty when `process.tty` is set
+    pub async fn create_service_child(&self) -> Result<(Child, ChildGuard, Option<PtyReader>)> {
+        let mut command = self
+            .service_command(
+                self.service.process.command.binary(),
+                self.service.process.command.args(),
+            )
+            .await?;
+        let pty = if self.service.process.tty {
+            eyre::ensure!(
+                self.service.process.stdio != StdioMode::Inherit,
+                "Service {} can't set both `process.tty` and `process.stdio = \"inherit\"`",
+                self.name
+            );
+            let pty = Pty::open()
+                .wrap_err_with(|| format!("Failed to set up the pty of service {}", self.name))?;
+            pty.attach(&mut command)?;
+            Some(pty)
+        } else {
+            None
+        };
+        match &self.service.process.stdin {
+            Stdin::Null => {}
+            Stdin::Inherit => {
+                command.stdin(Stdio::inherit());
+            }
+            Stdin::File(path) => {
+                let file = fs::File::open(path).await.wrap_err_with(|| {
+                    format!(
+                        "Failed to open stdin file {path:?} for service {}",
+                        self.name
+                    )
+                })?;
+                command.stdin(file.into_std().await);
+            }
+        }
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.pass_to(&mut command);
+        }
+        // Has to come after every other `pre_exec` hook, including those of
+        // `service_command` and `Pty::attach`: its hook execs the program, so hooks
+        // registered later would never run
+        if !self.sockets.is_empty() {
+            self.sockets
+                .pass_to(&mut command, self.passed_environment().is_some())?;
+        }
+
+        let pause = Subreaper::pause_reaping();
+        let process = command
+            .spawn()
+            .map_err(|e| Self::spawn_error(&command, self.service.process.command.binary(), e))
+            .wrap_err_with(|| format!("Failed to start process of service {}", self.name))?;
 
         let guard =
             Subreaper::track_child(process.id()).wrap_err("Failed to track service child")?;
+        drop(pause);
+        if let (Some(cgroup), Some(pid)) = (&self.cgroup, process.id())
+            && let Err(e) = cgroup.add(pid).await
+        {
+            warn!(
+                "Failed to move service {} into its cgroup: {e:#}",
+                self.name
+            );
+        }
+
+        Ok((process, guard, pty.map(Pty::into_reader).transpose()?))
+    }
+
+    /// Variables of the environment of nimi passed on to the service
+    ///
+    /// See `Settings::passed_environment`
+    fn passed_environment(&self) -> Option<Vec<&str>> {
+        self.settings.passed_environment(&self.service.process)
+    }
+
+    /// Variables set for the service, reading its environment files again
+    async fn read_environment(&self) -> Result<HashMap<String, String>> {
+        self.service
+            .process
+            .read_environment()
+            .await
+            .wrap_err_with(|| format!("Failed to read environment of service {}", self.name))
+    }
+
+    /// Value of the variable pointing the service to its config directory
+    fn config_env_value(&self, environment: &HashMap<String, String>) -> OsString {
+        let name = self.service.config_env_var.as_str();
+        let inherited = self
+            .passed_environment()
+            .is_none_or(|passed| passed.contains(&name))
+            .then(|| env::var_os(name))
+            .flatten();
+
+        self.service
+            .config_env_value(self.config_dir.as_ref(), environment, inherited)
+    }
+
+    /// Explain why spawning `binary` with `command` failed
+    ///
+    /// A binary which doesn't exist is named along with the `PATH` it was looked up in,
+    /// instead of the bare "No such file or directory" of the OS
+    fn spawn_error(command: &Command, binary: &str, e: io::Error) -> eyre::Report {
+        if e.kind() != ErrorKind::NotFound {
+            return e.into();
+        }
+
+        if binary.contains('/') {
+            if Path::new(binary).exists() {
+                // The binary is there, so its interpreter or the working directory is not
+                return e.into();
+            }
+            return eyre!("Binary {binary:?} doesn't exist");
+        }
+
+        let path = match command
+            .as_std()
+            .get_envs()
+            .find(|(name, _)| *name == "PATH")
+        {
+            Some((_, path)) => path.map(OsStr::to_os_string),
+            None => env::var_os("PATH"),
+        }
+        .unwrap_or_default();
+        if resolve_binary(binary, path.to_str()).is_some() {
+            return e.into();
+        }
+
+        eyre!(
+            "Binary {binary:?} was not found in PATH {path:?}, give an absolute path or add its directory to `process.environment.PATH`"
+        )
+    }
+
+    /// Replace `${NIMI_CONFIG_DIR}` in `args` with the config directory of the service
+    fn interpolate_args<S>(&self, args: impl IntoIterator<Item = S>) -> Vec<OsString>
+    where
+        S: AsRef<OsStr>,
+    {
+        let dir = Path::new(&self.config_dir);
+        args.into_iter()
+            .map(|arg| ConfigDir::interpolate(dir, arg.as_ref()))
+            .collect()
+    }
+
+    /// Build a command running in the environment of the service
+    ///
+    /// Shared by the main process and the hooks of the service, so they get the same
+    /// environment, config directory, working directory, limits and credentials
+    async fn service_command<S>(
+        &self,
+        binary: &str,
+        args: impl IntoIterator<Item = S>,
+    ) -> Result<Command>
+    where
+        S: AsRef<OsStr>,
+    {
+        let mut command = Command::new(binary);
+        Self::new_session(&mut command);
+        if let Some(pass_environment) = self.passed_environment() {
+            command.env_clear();
+            for name in pass_environment {
+                if let Some(value) = env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+        }
+        let environment = self.read_environment().await?;
+        command
+            .args(self.interpolate_args(args))
+            .envs(&environment)
+            .env(
+                &self.service.config_env_var,
+                self.config_env_value(&environment),
+            )
+            .env(CONFIG_DIR_VAR, &self.config_dir)
+            .env_remove(NOTIFY_SOCKET)
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+
+        if self.service.process.stdio == StdioMode::Inherit {
+            command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        } else if self.settings.logging.combine_output {
+            command.stdout(Stdio::piped()).stderr(Stdio::null());
+            // SAFETY: `dup2` is async-signal-safe and the hook touches no other state
+            unsafe {
+                command.pre_exec(|| {
+                    dup2(libc::STDOUT_FILENO, libc::STDERR_FILENO)?;
+                    Ok(())
+                });
+            }
+        } else {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+
+        if let Some(working_directory) = &self.service.process.working_directory {
+            let is_dir = fs::metadata(working_directory)
+                .await
+                .is_ok_and(|meta| meta.is_dir());
+            eyre::ensure!(
+                is_dir,
+                "Working directory {:?} for service {} does not exist",
+                working_directory,
+                self.name
+            );
+
+            command.current_dir(working_directory);
+        }
+
+        if !self.service.process.limits.is_empty() {
+            let limits = self.service.process.limits.clone();
+            // SAFETY: `Limit::apply_all` only calls `setrlimit` while iterating the map
+            // moved into the hook, which doesn't allocate
+            unsafe {
+                command.pre_exec(move || Limit::apply_all(&limits));
+            }
+        }
+
+        if let Some(umask) = self.service.process.umask {
+            // SAFETY: `umask(2)` is async-signal-safe and can't fail, the mask is copied
+            // into the hook so the forked child doesn't allocate or touch shared state
+            unsafe {
+                command.pre_exec(move || {
+                    libc::umask(umask.mode());
+                    Ok(())
+                });
+            }
+        }
+
+        if let Some(nice) = self.service.process.nice {
+            // SAFETY: `setpriority(2)` is a plain syscall without locks or allocations, the
+            // niceness is copied into the hook and a failure only reads `errno`
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice.value()) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.enter_on_exec(&mut command)?;
+        }
+
+        let credentials = Credentials::resolve(
+            self.service.process.user.as_ref(),
+            self.service.process.group.as_ref(),
+            &self.service.process.supplementary_groups,
+        )
+        .wrap_err_with(|| format!("Failed to resolve credentials for service {}", self.name))?;
+        if let Some(credentials) = credentials {
+            // SAFETY: `Credentials::apply` only calls `setgroups`, `setgid` and `setuid` with
+            // ids resolved before forking, the group list doesn't get allocated in the child
+            unsafe {
+                command.pre_exec(move || credentials.apply());
+            }
+        }
+
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::unistd::Uid;
+    use serde_json::json;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+    use super::*;
+
+    /// Service manager for `service`, creating its config directory inside of `tmp_dir`
+    async fn manager(tmp_dir: &Path, service: serde_json::Value) -> ServiceManager {
+        manager_with(tmp_dir, service, Settings::default()).await
+    }
+
+    async fn manager_with(
+        tmp_dir: &Path,
+        service: serde_json::Value,
+        settings: Settings,
+    ) -> ServiceManager {
+        ServiceManager::new(opts(tmp_dir, service, settings).await)
+            .await
+            .unwrap()
+    }
+
+    /// Options for a manager of `service` which gets no commands
+    async fn opts(
+        tmp_dir: &Path,
+        service: serde_json::Value,
+        settings: Settings,
+    ) -> ServiceManagerOpts {
+        let service: Service = serde_json::from_value(service).unwrap();
+        let config_dir = ConfigDir::new(tmp_dir, &service).await.unwrap();
+
+        ServiceManagerOpts {
+            logs_dir: Arc::new(None),
+            log_output: LogOutput::default(),
+            tmp_dir: Arc::new(tmp_dir.to_owned()),
+            settings: Arc::new(settings),
+            shutdown_signal: Arc::new(OnceLock::new()),
+            forwarded_signals: broadcast::Sender::new(4),
+            status: StatusBoard::default(),
+            name: Arc::new("test".to_owned()),
+            service,
+            config_dir,
+            cancel_tok: CancellationToken::new(),
+            drain_tok: CancellationToken::new(),
+            stop_handles: StopHandles::default(),
+            launched: CancellationToken::new(),
+            previous_launched: None,
+            ready: watch::Sender::new(false),
+            dependencies: HashMap::new(),
+            commands: mpsc::channel(1).1,
+        }
+    }
+
+    /// Spawn the process of the service and collect everything it writes to stdout
+    async fn output(manager: &ServiceManager) -> String {
+        let (mut process, _guard, _) = manager.create_service_child().await.unwrap();
+        let mut output = String::new();
+        process
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut output)
+            .await
+            .unwrap();
+        process.wait().await.unwrap();
+
+        output
+    }
+
+    /// Service appending a line to the `runs` file in `tmp_dir` and exiting with `code`
+    fn counted_service(tmp_dir: &Path, code: i32, restart: serde_json::Value) -> serde_json::Value {
+        let runs = tmp_dir.join("runs");
+        json!({
+            "configData": {},
+            "process": {
+                "argv": ["sh", "-c", format!("echo run >> {}; exit {code}", runs.display())],
+            },
+            "restart": restart,
+        })
+    }
+
+    /// Number of times a `counted_service` ran
+    fn runs(tmp_dir: &Path) -> usize {
+        std::fs::read_to_string(tmp_dir.join("runs"))
+            .map(|runs| runs.lines().count())
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn environment_is_passed_to_the_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["env"], "environment": { "GREETING": "hello" } },
+            }),
+        )
+        .await;
+
+        let output = output(&manager).await;
+
+        assert!(output.lines().any(|line| line == "GREETING=hello"));
+        assert!(output.lines().any(|line| {
+            line.strip_prefix("XDG_CONFIG_HOME=")
+                .is_some_and(|dir| Path::new(dir) == Path::new(&manager.config_dir))
+        }));
+    }
+
+    #[tokio::test]
+    async fn environment_overrides_the_config_directory_variable() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": {
+                    "argv": ["env"],
+                    "environment": { "XDG_CONFIG_HOME": "/etc/custom" },
+                },
+            }),
+        )
+        .await;
+
+        let output = output(&manager).await;
+
+        assert!(
+            output
+                .lines()
+                .any(|line| line == "XDG_CONFIG_HOME=/etc/custom")
+        );
+    }
+
+    #[tokio::test]
+    async fn limits_apply_to_the_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["cat", "/proc/self/limits"], "limits": { "nofile": 64 } },
+            }),
+        )
+        .await;
+
+        let output = output(&manager).await;
+        let open_files = output
+            .lines()
+            .find(|line| line.starts_with("Max open files"))
+            .unwrap();
+
+        assert_eq!(
+            open_files.split_whitespace().collect::<Vec<_>>()[3..5],
+            ["64", "64"]
+        );
+    }
+
+    #[tokio::test]
+    async fn on_failure_does_not_restart_after_a_clean_exit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let restart = json!({ "mode": "on-failure", "time": 10, "count": 0 });
+        let mut manager = manager(tmp.path(), counted_service(tmp.path(), 0, restart)).await;
+
+        manager.run().await.unwrap();
+
+        assert_eq!(runs(tmp.path()), 1);
+    }
+
+    #[tokio::test]
+    async fn on_failure_restarts_after_a_failed_exit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let restart = json!({
+            "mode": "on-failure",
+            "time": 10,
+            "count": 0,
+            "startLimit": { "interval": 60000, "burst": 3 },
+        });
+        let mut manager = manager(tmp.path(), counted_service(tmp.path(), 1, restart)).await;
+
+        let err = manager.run().await.unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ServiceError::ProcessExited { status }) if status.code() == Some(1)
+        ));
+        assert_eq!(runs(tmp.path()), 3);
+    }
+
+    #[tokio::test]
+    async fn always_restarts_after_a_clean_exit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let restart = json!({
+            "mode": "always",
+            "time": 10,
+            "count": 0,
+            "startLimit": { "interval": 60000, "burst": 2 },
+        });
+        let mut manager = manager(tmp.path(), counted_service(tmp.path(), 0, restart)).await;
+
+        let err = manager.run().await.unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ServiceError::StartLimitHit)
+        ));
+        assert_eq!(runs(tmp.path()), 2);
+    }
+
+    #[tokio::test]
+    async fn success_exit_codes_count_as_a_clean_exit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let restart = json!({ "mode": "on-failure", "time": 10, "count": 0 });
+        let mut service = counted_service(tmp.path(), 2, restart);
+        service["process"]["successExitCodes"] = json!([2]);
+        let mut manager = manager(tmp.path(), service).await;
+
+        manager.run().await.unwrap();
+
+        assert_eq!(runs(tmp.path()), 1);
+    }
+
+    #[tokio::test]
+    async fn a_service_stopped_by_nimi_exits_cleanly() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut manager = manager(
+            tmp.path(),
+            json!({ "configData": {}, "process": { "argv": ["sleep", "30"] } }),
+        )
+        .await;
+        let cancel_tok = manager.cancel_tok.clone();
+
+        let run = tokio::spawn(async move { manager.run().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        cancel_tok.cancel();
+
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn services_wait_for_their_dependencies_to_be_ready() {
+        let tmp = tempfile::tempdir().unwrap();
+        let restart = json!({ "mode": "never", "time": 10, "count": 0 });
+        let mut manager = manager(tmp.path(), counted_service(tmp.path(), 0, restart)).await;
+        let dependency = watch::Sender::new(false);
+        manager
+            .dependencies
+            .insert("db".to_owned(), dependency.subscribe());
+
+        let run = tokio::spawn(async move { manager.run().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(runs(tmp.path()), 0);
+
+        dependency.send_replace(true);
+        run.await.unwrap().unwrap();
+
+        assert_eq!(runs(tmp.path()), 1);
+    }
+
+    /// Long running service whose readiness check passes from its `ready_after`th poll on
+    fn service_ready_after(
+        tmp_dir: &Path,
+        ready_after: usize,
+        retries: usize,
+    ) -> serde_json::Value {
+        let polls = tmp_dir.join("polls");
+        let check = format!(
+            "echo poll >> {polls}; [ $(wc -l < {polls}) -ge {ready_after} ]",
+            polls = polls.display()
+        );
+
+        json!({
+            "configData": {},
+            "process": { "argv": ["sleep", "30"] },
+            "readiness": {
+                "command": ["sh", "-c", check],
+                "interval": 10,
+                "timeout": 1000,
+                "retries": retries,
+            },
+            "restart": { "mode": "never", "time": 10, "count": 0 },
+        })
+    }
+
+    #[tokio::test]
+    async fn services_become_ready_once_their_readiness_check_passes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut manager = manager(tmp.path(), service_ready_after(tmp.path(), 3, 10)).await;
+        let mut ready = manager.ready.subscribe();
+        let cancel_tok = manager.cancel_tok.clone();
+
+        let run = tokio::spawn(async move { manager.run().await });
+        timeout(Duration::from_secs(5), ready.wait_for(|ready| *ready))
+            .await
+            .unwrap()
+            .unwrap();
+        let polls = std::fs::read_to_string(tmp.path().join("polls")).unwrap();
+        cancel_tok.cancel();
+
+        assert_eq!(polls.lines().count(), 3);
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn services_fail_once_their_readiness_retries_are_used_up() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut manager = manager(tmp.path(), service_ready_after(tmp.path(), 10, 2)).await;
+
+        let err = manager.run().await.unwrap_err();
+
+        assert!(matches!(err.downcast_ref(), Some(ServiceError::NotReady)));
+        assert!(!*manager.ready.borrow());
+    }
+
+    #[tokio::test]
+    async fn shell_command_is_run_through_sh() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": [], "command": "echo piped | tr a-z A-Z" },
+            }),
+        )
+        .await;
+
+        assert_eq!(output(&manager).await, "PIPED\n");
+    }
+
+    #[tokio::test]
+    async fn relative_binaries_are_found_through_the_passed_on_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["env"], "passEnvironment": [] },
+            }),
+        )
+        .await;
+
+        let output = output(&manager).await;
+        let names: Vec<_> = output
+            .lines()
+            .filter_map(|line| line.split_once('=').map(|(name, _)| name))
+            .collect();
+
+        assert!(names.contains(&"PATH"));
+        assert!(env::vars().all(|(name, _)| name == "PATH" || !names.contains(&name.as_str())));
+    }
+
+    #[tokio::test]
+    async fn listed_variables_survive_the_cleared_environment() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (name, value) = env::vars().find(|(name, _)| name != "PATH").unwrap();
+        let settings = serde_json::from_value(json!({
+            "restart": { "mode": "never", "time": 0, "count": 0 },
+            "startup": { "commands": [], "environment": {}, "ignoreFailure": false },
+            "logging": {},
+            "shutdown": {},
+            "failurePolicy": "stop-all",
+            "forwardSignals": [],
+            "passEnvironment": [name],
+        }))
+        .unwrap();
+        let manager = manager_with(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["env"], "passEnvironment": [] },
+            }),
+            settings,
+        )
+        .await;
+
+        let output = output(&manager).await;
+
+        assert!(output.lines().any(|line| line == format!("{name}={value}")));
+    }
+
+    /// Shell command appending `line` to the `order` file in `tmp_dir`
+    fn record(tmp_dir: &Path, line: &str) -> serde_json::Value {
+        let order = tmp_dir.join("order");
+        json!(["sh", "-c", format!("echo {line} >> {}", order.display())])
+    }
+
+    /// Lines written by `record`
+    fn recorded(tmp_dir: &Path) -> Vec<String> {
+        std::fs::read_to_string(tmp_dir.join("order"))
+            .map(|order| order.lines().map(str::to_owned).collect())
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn a_failing_start_pre_hook_blocks_the_start() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut service = counted_service(
+            tmp.path(),
+            0,
+            json!({ "mode": "never", "time": 10, "count": 0 }),
+        );
+        service["execStartPre"] = json!([
+            record(tmp.path(), "pre"),
+            ["sh", "-c", "exit 3"],
+            record(tmp.path(), "unreachable")
+        ]);
+        let mut manager = manager(tmp.path(), service).await;
+
+        let err = manager.run().await.unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ServiceError::HookFailed { hook, status }) if hook == "sh" && status.code() == Some(3)
+        ));
+        assert_eq!(recorded(tmp.path()), ["pre"]);
+        assert_eq!(runs(tmp.path()), 0);
+    }
+
+    #[tokio::test]
+    async fn start_hooks_run_around_the_service_with_its_environment() {
+        let tmp = tempfile::tempdir().unwrap();
+        let order = tmp.path().join("order");
+        let mut manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": {
+                    "argv": ["sh", "-c", format!("echo main >> {}; sleep 0.5", order.display())],
+                    "environment": { "GREETING": "hello" },
+                },
+                "execStartPre": [["sh", "-c", format!("echo pre-$GREETING >> {}", order.display())]],
+                "execStartPost": [record(tmp.path(), "post")],
+                "restart": { "mode": "never", "time": 10, "count": 0 },
+            }),
+        )
+        .await;
+
+        manager.run().await.unwrap();
+
+        let mut recorded = recorded(tmp.path());
+        assert_eq!(recorded.remove(0), "pre-hello");
+        recorded.sort();
+        assert_eq!(recorded, ["main", "post"]);
+    }
+
+    #[tokio::test]
+    async fn stop_pre_hooks_run_before_the_service_is_stopped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let order = tmp.path().join("order");
+        let mut manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": {
+                    "argv": ["sh", "-c", format!("trap 'echo main-stopped >> {}; exit 0' TERM; sleep 30 & wait", order.display())],
+                },
+                "execStopPre": [record(tmp.path(), "stop-pre")],
+            }),
+        )
+        .await;
+        let cancel_tok = manager.cancel_tok.clone();
+
+        let run = tokio::spawn(async move { manager.run().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        cancel_tok.cancel();
+
+        run.await.unwrap().unwrap();
+        assert_eq!(recorded(tmp.path()), ["stop-pre", "main-stopped"]);
+    }
+
+    #[tokio::test]
+    async fn slow_stop_pre_hooks_are_cut_off_by_the_shutdown_timeout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let order = tmp.path().join("order");
+        let mut settings = Settings::default();
+        settings.shutdown.timeout = Duration::from_millis(300);
+        let mut manager = manager_with(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["sleep", "30"] },
+                "execStopPre": [["sh", "-c", format!("echo stop-pre >> {}; sleep 30", order.display())]],
+            }),
+            settings,
+        )
+        .await;
+        let cancel_tok = manager.cancel_tok.clone();
+
+        let run = tokio::spawn(async move { manager.run().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let stopped_at = Instant::now();
+        cancel_tok.cancel();
+
+        run.await.unwrap().unwrap();
+        assert!(stopped_at.elapsed() < Duration::from_secs(5));
+        assert_eq!(recorded(tmp.path()), ["stop-pre"]);
+    }
+
+    #[tokio::test]
+    async fn a_completed_oneshot_is_ready_and_never_restarted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut service = counted_service(
+            tmp.path(),
+            0,
+            json!({ "mode": "always", "time": 10, "count": 5 }),
+        );
+        service["type"] = json!("oneshot");
+        let mut manager = manager(tmp.path(), service).await;
+        let ready = manager.ready.subscribe();
+
+        manager.run().await.unwrap();
+
+        assert_eq!(runs(tmp.path()), 1);
+        assert!(*ready.borrow());
+    }
+
+    #[tokio::test]
+    async fn a_failed_oneshot_fails_without_becoming_ready() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut service = counted_service(
+            tmp.path(),
+            4,
+            json!({ "mode": "always", "time": 10, "count": 5 }),
+        );
+        service["type"] = json!("oneshot");
+        let mut manager = manager(tmp.path(), service).await;
+        let ready = manager.ready.subscribe();
+
+        let err = manager.run().await.unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ServiceError::ProcessExited { status }) if status.code() == Some(4)
+        ));
+        assert_eq!(runs(tmp.path()), 1);
+        assert!(!*ready.borrow());
+    }
+
+    #[tokio::test]
+    async fn restarts_after_a_crash_are_counted_on_the_status_board() {
+        let tmp = tempfile::tempdir().unwrap();
+        let restart = json!({ "mode": "up-to-count", "time": 10, "count": 2 });
+        let mut manager = manager(tmp.path(), counted_service(tmp.path(), 1, restart)).await;
+        let status = manager.status.clone();
+
+        assert!(manager.run().await.is_err());
+
+        let snapshot = status.snapshot();
+        assert_eq!(snapshot[0].restarts, 2);
+        assert_eq!(snapshot[0].last_exit_code, Some(1));
+        assert!(
+            status
+                .metrics()
+                .contains("nimi_service_restarts_total{service=\"test\"} 2\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_stop_command_stops_the_service_without_restarting_it() {
+        let tmp = tempfile::tempdir().unwrap();
+        let runs_file = tmp.path().join("runs");
+        let service = json!({
+            "configData": {},
+            "process": {
+                "argv": ["sh", "-c", format!("echo run >> {}; exec sleep 30", runs_file.display())],
+            },
+            "restart": { "mode": "always", "time": 10, "count": 0 },
+        });
+        let mut opts = opts(tmp.path(), service, Settings::default()).await;
+        let (commands, commands_rx) = mpsc::channel(1);
+        opts.commands = commands_rx;
+        let shutdown_tok = CancellationToken::new();
+        opts.cancel_tok = shutdown_tok.child_token();
+        let mut manager = ServiceManager::new(opts).await.unwrap();
+
+        let run = tokio::spawn(async move { manager.run().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        commands.send(ServiceCommand::Stop).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(runs(tmp.path()), 1);
+        // Only this service was stopped
+        assert!(!shutdown_tok.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn stdin_is_read_from_a_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("input.txt");
+        std::fs::write(&input, "line one\nline two\n").unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["cat"], "stdin": { "file": input } },
+            }),
+        )
+        .await;
+
+        assert_eq!(output(&manager).await, "line one\nline two\n");
+    }
+
+    #[tokio::test]
+    async fn stdin_defaults_to_dev_null() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({ "configData": {}, "process": { "argv": ["readlink", "/proc/self/fd/0"] } }),
+        )
+        .await;
+
+        assert_eq!(output(&manager).await, "/dev/null\n");
+    }
+
+    #[tokio::test]
+    async fn a_missing_stdin_file_fails_the_spawn() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["cat"], "stdin": { "file": tmp.path().join("missing") } },
+            }),
+        )
+        .await;
+
+        let err = manager.create_service_child().await.err().unwrap();
+
+        assert!(err.to_string().starts_with("Failed to open stdin file"));
+    }
+
+    #[tokio::test]
+    async fn start_limit_gives_up_on_a_failing_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let restart = json!({
+            "mode": "on-failure",
+            "time": 10,
+            "startLimit": { "interval": 60000, "burst": 3 },
+        });
+        let mut manager = manager(tmp.path(), counted_service(tmp.path(), 1, restart)).await;
+
+        let err = manager.run().await.unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ServiceError::ProcessExited { status }) if status.code() == Some(1)
+        ));
+        assert_eq!(runs(tmp.path()), 3);
+    }
+
+    #[tokio::test]
+    async fn starts_outside_of_the_start_limit_interval_are_not_counted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let restart = json!({
+            "mode": "up-to-count",
+            "time": 100,
+            "count": 4,
+            "startLimit": { "interval": 50, "burst": 2 },
+        });
+        let mut manager = manager(tmp.path(), counted_service(tmp.path(), 1, restart)).await;
+
+        assert!(manager.run().await.is_err());
+
+        assert_eq!(runs(tmp.path()), 5);
+    }
+
+    #[tokio::test]
+    async fn services_which_never_become_ready_fail_at_their_start_timeout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut service = service_ready_after(tmp.path(), 1000, 1000);
+        service["startTimeout"] = json!(200);
+        let mut manager = manager(tmp.path(), service).await;
+
+        let err = timeout(Duration::from_secs(5), manager.run())
+            .await
+            .unwrap()
+            .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ServiceError::StartTimedOut)
+        ));
+        assert!(!*manager.ready.borrow());
+    }
+
+    #[tokio::test]
+    async fn hanging_oneshot_services_fail_at_their_start_timeout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut manager = manager(
+            tmp.path(),
+            json!({
+                "type": "oneshot",
+                "configData": {},
+                "process": { "argv": ["sleep", "30"] },
+                "startTimeout": 200,
+            }),
+        )
+        .await;
+
+        let err = timeout(Duration::from_secs(5), manager.run())
+            .await
+            .unwrap()
+            .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ServiceError::StartTimedOut)
+        ));
+    }
+
+    #[tokio::test]
+    async fn the_start_timeout_stops_applying_once_the_service_is_ready() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut service = service_ready_after(tmp.path(), 1, 10);
+        service["startTimeout"] = json!(200);
+        let mut manager = manager(tmp.path(), service).await;
+        let mut ready = manager.ready.subscribe();
+        let cancel_tok = manager.cancel_tok.clone();
+
+        let run = tokio::spawn(async move { manager.run().await });
+        timeout(Duration::from_secs(5), ready.wait_for(|ready| *ready))
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+
+        assert!(!run.is_finished());
+        cancel_tok.cancel();
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn umask_is_set_for_the_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["sh", "-c", "umask"], "umask": "0027" },
+            }),
+        )
+        .await;
+
+        assert_eq!(output(&manager).await, "0027\n");
+    }
+
+    #[tokio::test]
+    async fn niceness_is_set_for_the_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["nice"], "nice": 7 },
+            }),
+        )
+        .await;
+
+        assert_eq!(output(&manager).await, "7\n");
+    }
+
+    #[tokio::test]
+    async fn inherited_stdio_is_not_logged() {
+        let tmp = tempfile::tempdir().unwrap();
+        let fds = tmp.path().join("fds");
+        let script = format!(
+            "fds=$(readlink /proc/$$/fd/1 /proc/$$/fd/2); echo \"$fds\" > {}",
+            fds.display()
+        );
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["sh", "-c", script], "stdio": "inherit" },
+            }),
+        )
+        .await;
+
+        let (mut process, _guard, _) = manager.create_service_child().await.unwrap();
+        let mut set = JoinSet::new();
+        manager
+            .start_loggers(&mut process, None, LogOutput::default(), &mut set)
+            .unwrap();
+        assert!(process.wait().await.unwrap().success());
+
+        assert!(set.is_empty());
+        assert!(process.stdout.is_none() && process.stderr.is_none());
+        let nimi_fds = [1, 2].map(|fd| {
+            let fd = std::fs::read_link(format!("/proc/self/fd/{fd}")).unwrap();
+            format!("{}\n", fd.display())
+        });
+        assert_eq!(std::fs::read_to_string(fds).unwrap(), nimi_fds.concat());
+    }
+
+    /// Controlling terminal and standard input of a `process.tty` service reading `stdin`
+    async fn tty_of(tmp_dir: &Path, stdin: serde_json::Value) -> (String, String) {
+        let found = tmp_dir.join("tty");
+        let script = format!(
+            "echo $(awk '{{print $7}}' /proc/self/stat) $(readlink /proc/$$/fd/0) > {}",
+            found.display()
+        );
+        let manager = manager(
+            tmp_dir,
+            json!({
+                "configData": {},
+                "process": { "argv": ["sh", "-c", script], "tty": true, "stdin": stdin },
+            }),
+        )
+        .await;
+
+        let (mut process, _guard, pty) = manager.create_service_child().await.unwrap();
+        assert!(pty.is_some());
+        assert!(process.wait().await.unwrap().success());
+
+        let found = std::fs::read_to_string(found).unwrap();
+        let (tty, stdin) = found.trim_end().split_once(' ').unwrap();
+        (tty.to_owned(), stdin.to_owned())
+    }
+
+    #[tokio::test]
+    async fn tty_services_run_on_a_pty() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let (tty, stdin) = tty_of(tmp.path(), json!("null")).await;
+
+        assert_ne!(tty, "0");
+        assert!(stdin.starts_with("/dev/pts/"), "{stdin}");
+    }
+
+    #[tokio::test]
+    async fn tty_services_keep_the_pty_with_another_stdin() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("input.txt");
+        std::fs::write(&input, "").unwrap();
+
+        let (tty, stdin) = tty_of(tmp.path(), json!({ "file": input })).await;
+
+        assert_ne!(tty, "0");
+        assert_eq!(Path::new(&stdin), input);
+    }
+
+    #[tokio::test]
+    async fn tty_services_can_not_inherit_stdio() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["true"], "tty": true, "stdio": "inherit" },
+            }),
+        )
+        .await;
+
+        let Err(e) = manager.create_service_child().await else {
+            panic!("service combining tty and inherited stdio was started");
+        };
+
+        assert_eq!(
+            e.to_string(),
+            "Service test can't set both `process.tty` and `process.stdio = \"inherit\"`"
+        );
+    }
+
+    /// Spawn `script` and read the pid of the background process it prints first
+    async fn spawn_with_background(tmp_dir: &Path, script: &str) -> (Child, ChildGuard, i32) {
+        let manager = manager(
+            tmp_dir,
+            json!({ "configData": {}, "process": { "argv": ["sh", "-c", script] } }),
+        )
+        .await;
+        let (mut process, guard, _) = manager.create_service_child().await.unwrap();
+        let mut line = String::new();
+        tokio::io::BufReader::new(process.stdout.take().unwrap())
+            .read_line(&mut line)
+            .await
+            .unwrap();
+
+        (process, guard, line.trim().parse().unwrap())
+    }
+
+    /// Wait until the process `pid` is gone or a zombie
+    async fn wait_gone(pid: i32) {
+        timeout(Duration::from_secs(5), async {
+            loop {
+                match std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+                    Ok(stat) if !stat.contains(") Z ") => {}
+                    _ => break,
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn services_lead_a_session_of_their_own() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": {
+                    "argv": [
+                        "sh",
+                        "-c",
+                        "read -r pid comm state ppid pgrp session rest < /proc/self/stat; echo $pid $pgrp $session",
+                    ],
+                },
+            }),
+        )
+        .await;
+
+        let output = output(&manager).await;
+        let ids: Vec<_> = output.split_whitespace().collect();
+
+        assert_eq!(ids.len(), 3);
+        assert!(ids.iter().all(|id| *id == ids[0]), "{output}");
+    }
+
+    #[tokio::test]
+    async fn shutdown_signals_the_whole_process_group() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (mut process, _guard, background) =
+            spawn_with_background(tmp.path(), "sleep 30 & echo $!; wait").await;
+
+        let status = ServiceManager::shutdown_process(
+            &mut process,
+            Signal::SIGTERM,
+            Duration::from_secs(5),
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(status.signal(), Some(Signal::SIGTERM as i32));
+        wait_gone(background).await;
+    }
+
+    #[tokio::test]
+    async fn shutdown_kills_a_group_ignoring_the_signal() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (mut process, _guard, background) =
+            spawn_with_background(tmp.path(), "trap '' TERM; sleep 30 & echo $!; wait").await;
+
+        let status = ServiceManager::shutdown_process(
+            &mut process,
+            Signal::SIGTERM,
+            Duration::from_millis(200),
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(status.signal(), Some(Signal::SIGKILL as i32));
+        wait_gone(background).await;
+    }
+
+    #[tokio::test]
+    async fn processes_surviving_sigkill_are_left_behind() {
+        let tmp = tempfile::tempdir().unwrap();
+        // Never sent SIGKILL, standing in for a process stuck in uninterruptible sleep
+        let (mut process, _guard, _) =
+            spawn_with_background(tmp.path(), "echo $$; exec sleep 30").await;
+
+        let status = ServiceManager::wait_killed(&mut process, Duration::from_millis(100))
+            .await
+            .unwrap();
+
+        assert!(status.is_none());
+        process.kill().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn forwarded_signals_reach_the_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let started = tmp.path().join("started");
+        let got = tmp.path().join("got");
+        let script = format!(
+            "trap 'echo USR1 > {got}; exit 0' USR1; touch {started}; while :; do sleep 0.05; done",
+            got = got.display(),
+            started = started.display(),
+        );
+        let mut manager = manager(
+            tmp.path(),
+            json!({ "configData": {}, "process": { "argv": ["sh", "-c", script] } }),
+        )
+        .await;
+        let forwarded_signals = manager.forwarded_signals.clone();
+
+        let run = tokio::spawn(async move { manager.run().await });
+        timeout(Duration::from_secs(5), async {
+            while !started.exists() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        forwarded_signals.send(Signal::SIGUSR1).unwrap();
+
+        timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(got).unwrap(), "USR1\n");
+    }
+
+    #[tokio::test]
+    async fn passed_environments_of_settings_and_service_are_combined() {
+        let tmp = tempfile::tempdir().unwrap();
+        let passed = async |global: serde_json::Value, service: serde_json::Value| {
+            let settings = serde_json::from_value(json!({ "passEnvironment": global })).unwrap();
+            let manager = manager_with(
+                tmp.path(),
+                json!({
+                    "configData": {},
+                    "process": { "argv": ["env"], "passEnvironment": service },
+                }),
+                settings,
+            )
+            .await;
+
+            manager
+                .passed_environment()
+                .map(|names| names.into_iter().map(str::to_owned).collect::<Vec<_>>())
+        };
+
+        assert_eq!(passed(json!(null), json!(null)).await, None);
+        assert_eq!(
+            passed(json!(["HOME"]), json!(null)).await,
+            Some(vec!["HOME".to_owned(), "PATH".to_owned()])
+        );
+        assert_eq!(
+            passed(json!(null), json!([])).await,
+            Some(vec!["PATH".to_owned()])
+        );
+        assert_eq!(
+            passed(json!(["HOME"]), json!(["LANG"])).await,
+            Some(vec![
+                "HOME".to_owned(),
+                "LANG".to_owned(),
+                "PATH".to_owned()
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn services_restarting_often_are_reported_as_crash_looping() {
+        let tmp = tempfile::tempdir().unwrap();
+        let restart = json!({
+            "mode": "on-failure",
+            "time": 10000,
+            "crashLoop": { "restarts": 1 },
+        });
+        let mut manager = manager(tmp.path(), counted_service(tmp.path(), 1, restart)).await;
+        let status = manager.status.clone();
+        let cancel_tok = manager.cancel_tok.clone();
+
+        let run = tokio::spawn(async move { manager.run().await });
+        timeout(Duration::from_secs(5), async {
+            while status.snapshot()[0].state != ServiceState::CrashLooping {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        cancel_tok.cancel();
+
+        run.await.unwrap().unwrap();
+        assert_eq!(runs(tmp.path()), 1);
+    }
+
+    #[tokio::test]
+    async fn services_stop_crash_looping_once_their_restarts_leave_the_window() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut service = counted_service(tmp.path(), 1, json!({}));
+        service["restart"]["crashLoop"] = json!({ "interval": 1000, "restarts": 2 });
+        let mut manager = manager(tmp.path(), service).await;
+        let now = Instant::now();
+
+        manager.recent_starts.push_back(now);
+        assert!(!manager.check_crash_loop());
+        manager.recent_starts.push_back(now);
+        assert!(manager.check_crash_loop());
+
+        manager.recent_starts = [now - Duration::from_secs(5), now].into();
+        assert!(!manager.check_crash_loop());
+        assert!(!manager.crash_looping);
+    }
+
+    #[tokio::test]
+    async fn draining_services_run_until_they_exit_and_are_not_restarted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut service = counted_service(tmp.path(), 0, json!({ "mode": "always", "time": 10 }));
+        service["process"]["argv"][2] = json!(format!(
+            "sleep 0.3; echo run >> {}",
+            tmp.path().join("runs").display()
+        ));
+        let mut manager = manager(tmp.path(), service).await;
+        let drain_tok = manager.drain_tok.clone();
+
+        let run = tokio::spawn(async move { manager.run().await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        drain_tok.cancel();
+
+        timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(runs(tmp.path()), 1);
+    }
+
+    #[test]
+    fn exits_are_described_by_code_or_signal() {
+        let describe = |raw| ExitDescription(ExitStatus::from_raw(raw)).to_string();
+
+        assert_eq!(describe(3 << 8), "exit code 3");
+        assert_eq!(describe(Signal::SIGTERM as i32), "signal SIGTERM");
+        assert_eq!(
+            describe(Signal::SIGSEGV as i32 | 0x80),
+            "signal SIGSEGV (core dumped)"
+        );
+        assert_eq!(describe(40), "signal 40");
+    }
+
+    #[test]
+    fn exit_codes_of_signalled_processes_follow_the_shell() {
+        let exit_code = |raw| ExitDescription(ExitStatus::from_raw(raw)).exit_code();
+
+        assert_eq!(exit_code(3 << 8), Some(3));
+        assert_eq!(exit_code(Signal::SIGKILL as i32), Some(137));
+    }
+
+    #[tokio::test]
+    async fn only_the_first_start_waits_for_the_start_delay() {
+        let tmp = tempfile::tempdir().unwrap();
+        let restart = json!({ "mode": "up-to-count", "time": 10, "count": 2 });
+        let mut service = counted_service(tmp.path(), 1, restart);
+        service["startDelay"] = json!(300);
+        let mut manager = manager(tmp.path(), service).await;
+
+        let started_at = Instant::now();
+        assert!(manager.run().await.is_err());
+        let elapsed = started_at.elapsed();
+
+        assert_eq!(runs(tmp.path()), 3);
+        assert!(elapsed >= Duration::from_millis(300), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(900), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn a_shutdown_during_the_start_delay_never_starts_the_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut service = counted_service(tmp.path(), 0, json!({}));
+        service["startDelay"] = json!(30000);
+        let mut manager = manager(tmp.path(), service).await;
+        let cancel_tok = manager.cancel_tok.clone();
+
+        let run = tokio::spawn(async move { manager.run().await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        cancel_tok.cancel();
+
+        timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(runs(tmp.path()), 0);
+    }
+
+    #[tokio::test]
+    async fn a_service_restart_block_overrides_the_global_one() {
+        let settings = || Settings {
+            restart: serde_json::from_value(
+                json!({ "mode": "up-to-count", "time": 10, "count": 2 }),
+            )
+            .unwrap(),
+            ..Settings::default()
+        };
+        let (app, migrate) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let mut managers = [
+            manager_with(
+                app.path(),
+                counted_service(app.path(), 1, json!(null)),
+                settings(),
+            )
+            .await,
+            manager_with(
+                migrate.path(),
+                counted_service(
+                    migrate.path(),
+                    1,
+                    json!({ "mode": "never", "time": 10, "count": 0 }),
+                ),
+                settings(),
+            )
+            .await,
+        ];
+
+        for manager in &mut managers {
+            assert!(
+                timeout(Duration::from_secs(5), manager.run())
+                    .await
+                    .unwrap()
+                    .is_err()
+            );
+        }
+
+        assert_eq!(runs(app.path()), 3);
+        assert_eq!(runs(migrate.path()), 1);
+    }
+
+    #[tokio::test]
+    async fn services_which_stop_writing_output_are_restarted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let runs = tmp.path().join("runs");
+        let mut manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": {
+                    "argv": ["sh", "-c", format!("echo run >> {}; echo started; exec sleep 30", runs.display())],
+                },
+                "outputWatchdog": 200,
+                "restart": { "mode": "up-to-count", "time": 10, "count": 1 },
+            }),
+        )
+        .await;
+
+        let res = timeout(Duration::from_secs(5), manager.run())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            res.unwrap_err().downcast_ref(),
+            Some(ServiceError::OutputStalled(_))
+        ));
+        assert_eq!(self::runs(tmp.path()), 2);
+    }
+
+    #[tokio::test]
+    async fn services_which_keep_writing_output_are_left_running() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["sh", "-c", "while true; do echo tick; sleep 0.05; done"] },
+                "outputWatchdog": 300,
+            }),
+        )
+        .await;
+
+        assert!(
+            timeout(Duration::from_secs(1), manager.run())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn the_config_dir_is_passed_in_the_configured_variable() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": {
+                    "argv": ["sh", "-c", "echo $APP_CONFIG_PATH"],
+                    "environment": { "APP_CONFIG_PATH": "/etc/app" },
+                },
+                "configEnvVar": "APP_CONFIG_PATH",
+                "configEnvMode": "prepend",
+            }),
+        )
+        .await;
+
+        assert_eq!(
+            output(&manager).await,
+            format!("{}:/etc/app\n", Path::new(&manager.config_dir).display())
+        );
+    }
+
+    #[tokio::test]
+    async fn services_see_their_config_dir_in_nimi_config_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": { "app": { "enable": true, "path": "app.conf", "text": "port = 80" } },
+                "process": {
+                    "argv": ["sh", "-c", "cat $NIMI_CONFIG_DIR/app.conf; echo \" $1\"", "sh", "${NIMI_CONFIG_DIR}/app.conf"],
+                },
+            }),
+        )
+        .await;
+
+        assert_eq!(
+            output(&manager).await,
+            format!(
+                "port = 80 {}\n",
+                Path::new(&manager.config_dir).join("app.conf").display()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_bare_binaries_name_the_path_they_were_looked_up_in() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": {
+                    "argv": ["nimi-test-missing"],
+                    "environment": { "PATH": "/nonexistent/bin" },
+                },
+                "restart": { "mode": "never" },
+            }),
+        )
+        .await;
+
+        let err = format!("{:#}", manager.run().await.unwrap_err());
+
+        assert!(
+            err.contains("Binary \"nimi-test-missing\" was not found in PATH \"/nonexistent/bin\""),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_absolute_binaries_are_named() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["/nonexistent/nimi-test"] },
+                "restart": { "mode": "never" },
+            }),
+        )
+        .await;
+
+        let err = format!("{:#}", manager.run().await.unwrap_err());
+
+        assert!(
+            err.contains("Binary \"/nonexistent/nimi-test\" doesn't exist"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn supplementary_groups_are_added_to_the_groups_of_the_service() {
+        if !Uid::effective().is_root() {
+            return;
+        }
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = manager(
+            tmp.path(),
+            json!({
+                "configData": {},
+                "process": { "argv": ["id", "-G"], "supplementaryGroups": [4242, 4243] },
+            }),
+        )
+        .await;
+
+        let output = output(&manager).await;
+        let groups: Vec<_> = output.split_whitespace().collect();
 
-        Ok((process, guard))
+        assert!(
+            groups.contains(&"4242") && groups.contains(&"4243"),
+            "{output}"
+        );
     }
 }
@@ -0,0 +1,316 @@
+//! Cgroup Module
+//!
+//! Places the processes of a service into a cgroup v2 of their own
+
+use std::{
+    ffi::CString,
+    io::ErrorKind,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+use eyre::{Context, OptionExt, Result};
+use log::{debug, info};
+use tokio::{fs, process::Command};
+
+use crate::process_manager::service::Cgroup;
+
+/// cgroup created for a single service
+///
+/// Created once and kept across restarts, the directories nimi created for it are
+/// removed again when dropped
+pub struct ServiceCgroup {
+    path: PathBuf,
+
+    /// Directories created by nimi, deepest first
+    created: Vec<PathBuf>,
+}
+
+impl ServiceCgroup {
+    /// Period the `cpuQuota` is spread over, in microseconds
+    const CPU_PERIOD: u64 = 100_000;
+
+    /// Leaf cgroup nimi moves itself into before enabling controllers in its own cgroup
+    const SUPERVISOR_LEAF: &str = "nimi-supervisor";
+
+    /// Create the cgroup of a service and apply its limits
+    ///
+    /// Controllers needed for the limits are enabled in every cgroup above it
+    pub async fn create(cgroup: &Cgroup, service: &str) -> Result<Self> {
+        let root = Self::mount_point()
+            .await?
+            .ok_or_eyre("cgroup v2 is not mounted")?;
+        let own = Self::own_cgroup().await?;
+
+        Self::create_in(&root, &root.join(own), cgroup, service).await
+    }
+
+    /// Create the cgroup below the hierarchy mounted at `root`, with nimi in `own`
+    async fn create_in(root: &Path, own: &Path, cgroup: &Cgroup, service: &str) -> Result<Self> {
+        let relative = cgroup
+            .path
+            .clone()
+            .unwrap_or_else(|| Path::new("nimi").join(service));
+        eyre::ensure!(
+            relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_))),
+            "cgroup path {relative:?} must be a relative path inside of the cgroup hierarchy"
+        );
+
+        let mut created = Self {
+            path: root.to_owned(),
+            created: Vec::new(),
+        };
+        for component in relative.components() {
+            created.path.push(component);
+            match fs::create_dir(&created.path).await {
+                Ok(()) => created.created.insert(0, created.path.clone()),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e)
+                        .wrap_err_with(|| format!("Failed to create cgroup {:?}", created.path));
+                }
+            }
+        }
+
+        let mut controllers = Vec::new();
+        if cgroup.memory_max.is_some() {
+            controllers.push("+memory");
+        }
+        if cgroup.cpu_quota.is_some() {
+            controllers.push("+cpu");
+        }
+        if !controllers.is_empty() {
+            let controllers = controllers.join(" ");
+            let mut dir = root.to_owned();
+            for component in relative.components() {
+                // cgroups with processes of their own can't pass controllers on
+                if dir == own {
+                    Self::leave(own).await?;
+                }
+                let subtree_control = dir.join("cgroup.subtree_control");
+                fs::write(&subtree_control, &controllers)
+                    .await
+                    .wrap_err_with(|| {
+                        format!("Failed to enable {controllers} in {subtree_control:?}")
+                    })?;
+                dir.push(component);
+            }
+        }
+
+        if let Some(memory_max) = cgroup.memory_max {
+            created.write("memory.max", &memory_max.to_string()).await?;
+        }
+        if let Some(cpu_quota) = cgroup.cpu_quota {
+            let quota = u64::from(cpu_quota) * Self::CPU_PERIOD / 100;
+            created
+                .write("cpu.max", &format!("{quota} {}", Self::CPU_PERIOD))
+                .await?;
+        }
+
+        Ok(created)
+    }
+
+    /// Move nimi and every other process out of `own` into a leaf cgroup below it
+    ///
+    /// Its children are moved along, they would keep `own` from being a parent as well
+    async fn leave(own: &Path) -> Result<()> {
+        let leaf = own.join(Self::SUPERVISOR_LEAF);
+        info!("Moving nimi into the cgroup {leaf:?} to enable controllers in {own:?}");
+        match fs::create_dir(&leaf).await {
+            Err(e) if e.kind() != ErrorKind::AlreadyExists => {
+                return Err(e).wrap_err_with(|| format!("Failed to create cgroup {leaf:?}"));
+            }
+            _ => {}
+        }
+
+        let procs = own.join("cgroup.procs");
+        let pids = fs::read_to_string(&procs)
+            .await
+            .wrap_err_with(|| format!("Failed to read {procs:?}"))?;
+        let leaf_procs = leaf.join("cgroup.procs");
+        for pid in pids.lines() {
+            match fs::write(&leaf_procs, pid).await {
+                // The process exited in the meantime
+                Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {}
+                res => res.wrap_err_with(|| format!("Failed to move {pid} into {leaf:?}"))?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Make the process spawned by `command` move itself into the cgroup before it execs
+    ///
+    /// Unlike `add` this can't miss processes it forks right away, but failures can't be
+    /// reported. Has to be registered before the privileges are dropped
+    pub fn enter_on_exec(&self, command: &mut Command) -> Result<()> {
+        let procs = CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes())
+            .wrap_err("cgroup path may not contain NUL bytes")?;
+
+        // SAFETY: `open(2)`, `write(2)` and `close(2)` are async-signal-safe, the path is
+        // converted before forking so the hook doesn't allocate
+        unsafe {
+            command.pre_exec(move || {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd != -1 {
+                    libc::write(fd, b"0".as_ptr().cast(), 1);
+                    libc::close(fd);
+                }
+                Ok(())
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Move the process with the given pid into the cgroup
+    pub async fn add(&self, pid: u32) -> Result<()> {
+        self.write("cgroup.procs", &pid.to_string()).await
+    }
+
+    async fn write(&self, file: &str, value: &str) -> Result<()> {
+        let path = self.path.join(file);
+        fs::write(&path, value)
+            .await
+            .wrap_err_with(|| format!("Failed to write {path:?}"))
+    }
+
+    /// Find where the cgroup v2 hierarchy is mounted
+    async fn mount_point() -> Result<Option<PathBuf>> {
+        let mounts = fs::read_to_string("/proc/self/mounts")
+            .await
+            .wrap_err("Failed to read mounts")?;
+
+        Ok(mounts.lines().find_map(|mount| {
+            let mut fields = mount.split_whitespace();
+            let mount_point = fields.nth(1)?;
+            (fields.next()? == "cgroup2").then(|| PathBuf::from(mount_point))
+        }))
+    }
+
+    /// Path of the cgroup v2 of nimi, relative to the root of the hierarchy
+    async fn own_cgroup() -> Result<PathBuf> {
+        let cgroups = fs::read_to_string("/proc/self/cgroup")
+            .await
+            .wrap_err("Failed to read the cgroup of nimi")?;
+
+        cgroups
+            .lines()
+            .find_map(|line| line.strip_prefix("0::/"))
+            .map(PathBuf::from)
+            .ok_or_eyre("nimi isn't in a cgroup v2")
+    }
+}
+
+impl Drop for ServiceCgroup {
+    /// Remove the directories nimi created, parents still used by others stay
+    fn drop(&mut self) {
+        for dir in &self.created {
+            match std::fs::remove_dir(dir) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    debug!("Failed to remove cgroup {dir:?}: {e}");
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn cgroup(cgroup: serde_json::Value) -> Cgroup {
+        serde_json::from_value(cgroup).unwrap()
+    }
+
+    fn read(path: PathBuf) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_directories_created_by_nimi_are_removed() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        std::fs::create_dir(root.join("services")).unwrap();
+
+        let created = ServiceCgroup::create_in(
+            root,
+            &root.join("init"),
+            &cgroup(json!({ "path": "services/web/main" })),
+            "web",
+        )
+        .await
+        .unwrap();
+        assert!(root.join("services/web/main").is_dir());
+
+        drop(created);
+        assert!(!root.join("services/web").exists());
+        assert!(root.join("services").is_dir());
+    }
+
+    #[tokio::test]
+    async fn limits_are_applied_after_leaving_the_own_cgroup() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        std::fs::write(root.join("cgroup.procs"), "42\n").unwrap();
+
+        let _created = ServiceCgroup::create_in(
+            root,
+            root,
+            &cgroup(json!({ "memoryMax": 1024, "cpuQuota": 50 })),
+            "web",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(read(root.join("nimi-supervisor/cgroup.procs")), "42");
+        assert_eq!(read(root.join("cgroup.subtree_control")), "+memory +cpu");
+        assert_eq!(
+            read(root.join("nimi/cgroup.subtree_control")),
+            "+memory +cpu"
+        );
+        assert_eq!(read(root.join("nimi/web/memory.max")), "1024");
+        assert_eq!(read(root.join("nimi/web/cpu.max")), "50000 100000");
+    }
+
+    #[tokio::test]
+    async fn nimi_stays_in_its_cgroup_when_it_does_not_pass_on_controllers() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+
+        let _created = ServiceCgroup::create_in(
+            root,
+            &root.join("init"),
+            &cgroup(json!({ "memoryMax": 1024 })),
+            "web",
+        )
+        .await
+        .unwrap();
+
+        assert!(!root.join("init/nimi-supervisor").exists());
+        assert_eq!(read(root.join("nimi/web/memory.max")), "1024");
+    }
+
+    #[tokio::test]
+    async fn paths_outside_of_the_hierarchy_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+
+        for path in ["../web", "/web", "nimi/../web"] {
+            let res = ServiceCgroup::create_in(
+                root.path(),
+                root.path(),
+                &cgroup(json!({ "path": path })),
+                "web",
+            )
+            .await;
+
+            assert!(res.is_err(), "{path} was accepted");
+        }
+    }
+}
@@ -3,37 +3,88 @@
 //! Handles creating the configuration directory
 
 use eyre::{Context, OptionExt, Result};
+use log::warn;
+use nix::unistd::mkdtemp;
 use sha2::{Digest, Sha256};
 use std::{
-    ffi::OsStr,
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
+    fs::Permissions,
     io::ErrorKind,
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
-use tokio::fs;
+use tokio::{fs, io::AsyncWriteExt};
 
-use crate::process_manager::service::ConfigDataMap;
+use crate::process_manager::{
+    Service,
+    service::{ConfigData, ConfigDataMap, FileMode, Materialize},
+};
+
+/// Name of the environment variable holding the config directory of a service
+///
+/// `${NIMI_CONFIG_DIR}` in the arguments of a service is replaced with the directory too
+pub const CONFIG_DIR_VAR: &str = "NIMI_CONFIG_DIR";
 
 /// Configuration directory struct
 ///
 /// Generates a reusable per service temp dir using a hash of the
 /// configuration data
+///
+/// Services with the same configuration data share a directory, it is removed
+/// once the last `ConfigDir` using it is dropped. Services with `uniqueConfigDir`
+/// get a freshly created directory of their own instead
 pub struct ConfigDir(PathBuf);
 
+/// Bookkeeping for a config directory in use
+struct Users {
+    /// Number of `ConfigDir`s using the directory
+    count: usize,
+
+    /// If the config files were written, locked while they are being written
+    populated: Arc<tokio::sync::Mutex<bool>>,
+}
+
 impl ConfigDir {
     /// Create a new configuration directory
     ///
     /// Writes the configuration to disk inside the passed tempdir with
     /// the configured files
-    pub async fn new(tmp_dir: &Path, config_data: &ConfigDataMap) -> Result<Self> {
-        let dir_name = Self::generate_config_directory_name(config_data)
-            .wrap_err("Failed to generate config directory name")?;
+    pub async fn new(tmp_dir: &Path, service: &Service) -> Result<Self> {
+        let config_data = &service.config_data;
+        let materialize = service.materialize;
+
+        let (cfg_dir, populated) = if service.unique_config_dir {
+            let template = tmp_dir.join("nimi-config-XXXXXX");
+            let path = tokio::task::spawn_blocking(move || mkdtemp(&template))
+                .await?
+                .wrap_err("Failed to create unique config directory")?;
+
+            Self::acquire(path)
+        } else {
+            let path = Self::shared_path(tmp_dir, service)?
+                .ok_or_eyre("Service has no shared config directory")?;
 
-        let cfg_dir_path = tmp_dir.join(&dir_name);
+            Self::acquire(path)
+        };
+        let cfg_dir_path = &cfg_dir.0;
+
+        // A service sharing the directory may already have written the files, rewriting
+        // them would briefly remove them from under it
+        let mut populated = populated.lock().await;
+        if *populated {
+            return Ok(cfg_dir);
+        }
 
         for cfg in config_data.values() {
             if !cfg.enable {
                 continue;
             }
+            cfg.check_path()?;
 
             let out_location = cfg_dir_path.join(&cfg.path);
 
@@ -47,37 +98,583 @@ impl ConfigDir {
                 Err(e) => return Err(e).wrap_err("Failed to create config file parent dir"),
             }
 
-            match fs::symlink(&cfg.source, out_location).await {
-                Ok(()) => {}
-                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
-                Err(e) => {
-                    return Err(e).wrap_err_with(|| {
-                        format!("Failed to create symlink for config file: {:?}", cfg.path)
-                    });
+            Self::write_entry(cfg, &out_location, materialize).await?;
+        }
+
+        *populated = true;
+
+        Ok(cfg_dir)
+    }
+
+    /// Path of the directory inside of `tmp_dir` which `new` creates for `service`
+    ///
+    /// `None` for services with `uniqueConfigDir`, whose directory name is only picked
+    /// when it is created
+    pub fn shared_path(tmp_dir: &Path, service: &Service) -> Result<Option<PathBuf>> {
+        if service.unique_config_dir {
+            return Ok(None);
+        }
+
+        let dir_name = match service.config_dir_name.get() {
+            Some(dir_name) => dir_name,
+            None => {
+                let dir_name =
+                    Self::generate_config_directory_name(&service.config_data, service.materialize)
+                        .wrap_err("Failed to generate config directory name")?;
+                service.config_dir_name.get_or_init(|| dir_name)
+            }
+        };
+
+        Ok(Some(tmp_dir.join(dir_name)))
+    }
+
+    /// Replace every `${NIMI_CONFIG_DIR}` in `arg` with `dir`
+    pub fn interpolate(dir: &Path, arg: &OsStr) -> OsString {
+        match (arg.to_str(), dir.to_str()) {
+            (Some(arg), Some(dir)) => arg.replace(&format!("${{{CONFIG_DIR_VAR}}}"), dir).into(),
+            _ => arg.to_owned(),
+        }
+    }
+
+    /// Place a single config data entry at `out_location`
+    ///
+    /// `text` is always written out as a regular file, a `source` with a `mode` is
+    /// always copied
+    async fn write_entry(
+        cfg: &ConfigData,
+        out_location: &Path,
+        materialize: Materialize,
+    ) -> Result<()> {
+        let mode = match (&cfg.source, &cfg.text, materialize, cfg.mode) {
+            (Some(source), _, Materialize::Symlink, None) => {
+                return Self::link(source, out_location).await.wrap_err_with(|| {
+                    format!("Failed to create symlink for config file: {:?}", cfg.path)
+                });
+            }
+            (Some(source), _, _, mode) => {
+                Self::copy(source, out_location)
+                    .await
+                    .wrap_err_with(|| format!("Failed to copy config file: {:?}", cfg.path))?;
+
+                match mode {
+                    Some(mode) => mode,
+                    None => return Ok(()),
+                }
+            }
+            (None, Some(text), _, mode) => {
+                let mode = mode.unwrap_or(FileMode::DEFAULT);
+                Self::write_text(text, out_location, mode)
+                    .await
+                    .wrap_err_with(|| {
+                        format!("Failed to write text for config file: {:?}", cfg.path)
+                    })?;
+
+                mode
+            }
+            (None, None, _, _) => eyre::bail!(
+                "Config file {:?} has neither a `source` nor `text` set",
+                cfg.path
+            ),
+        };
+
+        eyre::ensure!(
+            !fs::metadata(out_location).await?.is_dir(),
+            "Config data {:?} is a directory, a mode can only be set for files",
+            cfg.path
+        );
+        fs::set_permissions(out_location, Permissions::from_mode(mode.bits()))
+            .await
+            .wrap_err_with(|| format!("Failed to set mode of config file: {:?}", cfg.path))
+    }
+
+    /// Write `text` to a new file at `out_location`, replacing anything already there
+    ///
+    /// The file is created with `mode` (minus the umask), so its contents are never
+    /// readable with laxer permissions
+    async fn write_text(text: &str, out_location: &Path, mode: FileMode) -> std::io::Result<()> {
+        Self::remove_existing(out_location).await?;
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode.bits())
+            .open(out_location)
+            .await?;
+        file.write_all(text.as_bytes()).await?;
+        file.flush().await
+    }
+
+    /// Symlink `out_location` to `source`
+    ///
+    /// The directory may be left over from an earlier run, an existing entry is kept
+    /// only if it already links to `source`
+    async fn link(source: &Path, out_location: &Path) -> std::io::Result<()> {
+        match fs::symlink(source, out_location).await {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                if fs::read_link(out_location)
+                    .await
+                    .is_ok_and(|target| target == source)
+                {
+                    return Ok(());
                 }
+
+                Self::remove_existing(out_location).await?;
+                fs::symlink(source, out_location).await
             }
+            res => res,
+        }
+    }
+
+    /// Copy `source` to `out_location`, replacing anything already there
+    ///
+    /// Directories are copied recursively. File modes are preserved, copied directories
+    /// are made writable by their owner so the config directory can be removed again
+    async fn copy(source: &Path, out_location: &Path) -> std::io::Result<()> {
+        Self::remove_existing(out_location).await?;
+
+        let (source, out_location) = (source.to_owned(), out_location.to_owned());
+        tokio::task::spawn_blocking(move || copy_recursive(&source, &out_location)).await?
+    }
+
+    /// Remove whatever is at `location`, if anything
+    async fn remove_existing(location: &Path) -> std::io::Result<()> {
+        let res = match fs::symlink_metadata(location).await {
+            Ok(meta) if meta.is_dir() => fs::remove_dir_all(location).await,
+            Ok(_) => fs::remove_file(location).await,
+            Err(e) => Err(e),
+        };
+
+        match res {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            res => res,
         }
+    }
+
+    fn users() -> &'static Mutex<HashMap<PathBuf, Users>> {
+        static USERS: OnceLock<Mutex<HashMap<PathBuf, Users>>> = OnceLock::new();
+        USERS.get_or_init(|| Mutex::new(HashMap::new()))
+    }
+
+    /// Register another user of the directory at `path`
+    ///
+    /// Also returns the flag telling if the config files of the directory were written
+    fn acquire(path: PathBuf) -> (Self, Arc<tokio::sync::Mutex<bool>>) {
+        let populated = match Self::users().lock() {
+            Ok(mut users) => {
+                let users = users.entry(path.clone()).or_insert_with(|| Users {
+                    count: 0,
+                    populated: Arc::default(),
+                });
+                users.count += 1;
+
+                Arc::clone(&users.populated)
+            }
+            Err(_) => Arc::default(),
+        };
 
-        Ok(Self(cfg_dir_path))
+        (Self(path), populated)
     }
 
     /// Generate a name for the config dir by using an Sha256 hash of
     /// the contents
-    pub fn generate_config_directory_name(config_data: &ConfigDataMap) -> Result<String> {
-        let bytes = serde_json::to_vec(&config_data).wrap_err_with(|| {
+    ///
+    /// Disabled entries are left out of the hash since they are never materialized. Copied
+    /// config data gets a directory apart from symlinked config data. Entries are hashed
+    /// sorted by name, so identical config data always gets the same directory
+    fn generate_config_directory_name(
+        config_data: &ConfigDataMap,
+        materialize: Materialize,
+    ) -> Result<String> {
+        #[cfg(test)]
+        tests::HASHED.with(|hashed| hashed.set(hashed.get() + 1));
+
+        let enabled: BTreeMap<_, _> = config_data.iter().filter(|(_, cfg)| cfg.enable).collect();
+
+        let mut hasher = Sha256::new();
+        match materialize {
+            Materialize::Symlink => serde_json::to_writer(&mut hasher, &enabled),
+            Materialize::Copy => serde_json::to_writer(&mut hasher, &(&enabled, materialize)),
+        }
+        .wrap_err_with(|| {
             format!(
                 "Failed to serialize config data files to bytes: {:?}",
                 config_data
             )
         })?;
-        let digest = Sha256::digest(&bytes);
 
-        Ok(format!("nimi-config-{:x}", digest))
+        Ok(format!("nimi-config-{:x}", hasher.finalize()))
+    }
+}
+
+impl Drop for ConfigDir {
+    fn drop(&mut self) {
+        let Ok(mut users) = Self::users().lock() else {
+            return;
+        };
+        let Some(Users { count, .. }) = users.get_mut(&self.0) else {
+            return;
+        };
+
+        *count -= 1;
+        if *count != 0 {
+            return;
+        }
+        users.remove(&self.0);
+
+        // Only the rename happens under the lock, so a service creating the same
+        // directory concurrently gets a fresh one which the removal can't touch
+        static REMOVED: AtomicUsize = AtomicUsize::new(0);
+        let mut removed = self.0.clone().into_os_string();
+        removed.push(format!(
+            ".removed-{}-{}",
+            std::process::id(),
+            REMOVED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let removed = PathBuf::from(removed);
+        let res = std::fs::rename(&self.0, &removed);
+        drop(users);
+
+        match res.and_then(|()| std::fs::remove_dir_all(&removed)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                warn!("Failed to remove config directory {:?}: {e}", self.0);
+            }
+            _ => {}
+        }
     }
 }
 
+/// Copy `source` to `target`, following symlinks and recursing into directories
+fn copy_recursive(source: &Path, target: &Path) -> std::io::Result<()> {
+    let meta = std::fs::metadata(source)?;
+    if !meta.is_dir() {
+        return std::fs::copy(source, target).map(|_| ());
+    }
+
+    std::fs::create_dir(target)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        copy_recursive(&entry.path(), &target.join(entry.file_name()))?;
+    }
+    std::fs::set_permissions(
+        target,
+        Permissions::from_mode(meta.permissions().mode() | 0o700),
+    )
+}
+
 impl AsRef<OsStr> for ConfigDir {
     fn as_ref(&self) -> &OsStr {
         self.0.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    thread_local! {
+        /// Number of config directory names hashed on the current thread
+        pub static HASHED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    fn service(config_data: serde_json::Value) -> Service {
+        serde_json::from_value(json!({
+            "configData": config_data,
+            "process": { "argv": ["true"] },
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_enabled_config_data_is_written() {
+        let tmp = tempfile::tempdir().unwrap();
+        let service = service(json!({
+            "enabled": { "enable": true, "path": "enabled.conf", "text": "on" },
+            "disabled": { "enable": false, "path": "disabled.conf", "text": "off" },
+        }));
+
+        let dir = ConfigDir::new(tmp.path(), &service).await.unwrap();
+        let dir = Path::new(&dir);
+
+        assert_eq!(
+            std::fs::read_to_string(dir.join("enabled.conf")).unwrap(),
+            "on"
+        );
+        assert!(!dir.join("disabled.conf").exists());
+    }
+
+    #[test]
+    fn toggling_enable_changes_the_directory_name() {
+        let name = |enable: bool| {
+            let service = service(json!({
+                "a": { "enable": true, "path": "a.conf", "text": "a" },
+                "b": { "enable": enable, "path": "b.conf", "text": "b" },
+            }));
+            ConfigDir::generate_config_directory_name(&service.config_data, service.materialize)
+                .unwrap()
+        };
+
+        assert_ne!(name(true), name(false));
+    }
+
+    #[test]
+    fn disabled_entries_are_left_out_of_the_hash() {
+        let name = |config_data| {
+            let service = service(config_data);
+            ConfigDir::generate_config_directory_name(&service.config_data, service.materialize)
+                .unwrap()
+        };
+
+        assert_eq!(
+            name(json!({
+                "a": { "enable": true, "path": "a.conf", "text": "a" },
+            })),
+            name(json!({
+                "a": { "enable": true, "path": "a.conf", "text": "a" },
+                "b": { "enable": false, "path": "b.conf", "text": "b" },
+            })),
+        );
+    }
+
+    #[tokio::test]
+    async fn the_directory_is_removed_with_its_last_user() {
+        let tmp = tempfile::tempdir().unwrap();
+        let service = service(json!({
+            "a": { "enable": true, "path": "nested/a.conf", "text": "a" },
+        }));
+
+        let first = ConfigDir::new(tmp.path(), &service).await.unwrap();
+        let second = ConfigDir::new(tmp.path(), &service).await.unwrap();
+        let path = PathBuf::from(&first);
+        assert_eq!(path, PathBuf::from(&second));
+
+        drop(first);
+        assert!(path.join("nested/a.conf").exists());
+
+        drop(second);
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_directory_created_again_after_removal_is_written_anew() {
+        let tmp = tempfile::tempdir().unwrap();
+        let service = service(json!({
+            "a": { "enable": true, "path": "a.conf", "text": "a" },
+        }));
+
+        drop(ConfigDir::new(tmp.path(), &service).await.unwrap());
+        let dir = ConfigDir::new(tmp.path(), &service).await.unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(Path::new(&dir).join("a.conf")).unwrap(),
+            "a"
+        );
+    }
+
+    #[tokio::test]
+    async fn drifted_symlinks_left_from_an_earlier_run_are_pointed_at_the_source() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source.conf");
+        std::fs::write(&source, "new").unwrap();
+        let service = service(json!({
+            "a": { "enable": true, "path": "a.conf", "text": null, "source": source },
+        }));
+        let path = ConfigDir::shared_path(tmp.path(), &service)
+            .unwrap()
+            .unwrap();
+        std::fs::create_dir(&path).unwrap();
+        std::os::unix::fs::symlink(tmp.path().join("old.conf"), path.join("a.conf")).unwrap();
+
+        let dir = ConfigDir::new(tmp.path(), &service).await.unwrap();
+
+        assert_eq!(PathBuf::from(&dir), path);
+        assert_eq!(std::fs::read_link(path.join("a.conf")).unwrap(), source);
+    }
+
+    #[tokio::test]
+    async fn copies_left_from_an_earlier_run_get_the_current_contents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source.conf");
+        std::fs::write(&source, "new").unwrap();
+        let mut service = service(json!({
+            "a": { "enable": true, "path": "a.conf", "text": null, "source": source },
+        }));
+        service.materialize = Materialize::Copy;
+        let path = ConfigDir::shared_path(tmp.path(), &service)
+            .unwrap()
+            .unwrap();
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("a.conf"), "old").unwrap();
+
+        let dir = ConfigDir::new(tmp.path(), &service).await.unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(Path::new(&dir).join("a.conf")).unwrap(),
+            "new"
+        );
+    }
+
+    #[tokio::test]
+    async fn copied_sources_are_regular_files_keeping_their_mode() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+        std::fs::create_dir_all(source.join("sub")).unwrap();
+        std::fs::write(source.join("sub/run.sh"), "echo hi").unwrap();
+        std::fs::set_permissions(source.join("sub/run.sh"), Permissions::from_mode(0o750)).unwrap();
+        std::fs::set_permissions(&source, Permissions::from_mode(0o555)).unwrap();
+        let mut service = service(json!({
+            "a": { "enable": true, "path": "scripts", "text": null, "source": source },
+        }));
+        service.materialize = Materialize::Copy;
+
+        let dir = ConfigDir::new(tmp.path(), &service).await.unwrap();
+        let copied = Path::new(&dir).join("scripts");
+
+        assert!(!std::fs::symlink_metadata(&copied).unwrap().is_symlink());
+        let script = std::fs::metadata(copied.join("sub/run.sh")).unwrap();
+        assert_eq!(script.permissions().mode() & 0o777, 0o750);
+        assert_eq!(
+            std::fs::read_to_string(copied.join("sub/run.sh")).unwrap(),
+            "echo hi"
+        );
+
+        let path = PathBuf::from(&dir);
+        drop(dir);
+        assert!(!path.exists());
+        std::fs::set_permissions(&source, Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn materialize_changes_the_directory_name() {
+        let service = service(json!({
+            "a": { "enable": true, "path": "a.conf", "text": null, "source": "/etc/hostname" },
+        }));
+        let name = |materialize| {
+            ConfigDir::generate_config_directory_name(&service.config_data, materialize).unwrap()
+        };
+
+        assert_ne!(name(Materialize::Symlink), name(Materialize::Copy));
+    }
+
+    #[tokio::test]
+    async fn config_files_get_their_mode() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source.conf");
+        std::fs::write(&source, "secret").unwrap();
+        let service = service(json!({
+            "plain": { "enable": true, "path": "plain.conf", "text": "plain" },
+            "private": { "enable": true, "path": "private.conf", "text": "private", "mode": "0600" },
+            "source": { "enable": true, "path": "source.conf", "text": null, "source": source, "mode": "0640" },
+        }));
+
+        let dir = ConfigDir::new(tmp.path(), &service).await.unwrap();
+        let mode = |name: &str| {
+            let meta = std::fs::symlink_metadata(Path::new(&dir).join(name)).unwrap();
+            assert!(!meta.is_symlink(), "{name} is a symlink");
+            meta.permissions().mode() & 0o777
+        };
+
+        assert_eq!(mode("plain.conf"), 0o644);
+        assert_eq!(mode("private.conf"), 0o600);
+        assert_eq!(mode("source.conf"), 0o640);
+    }
+
+    #[tokio::test]
+    async fn unique_config_dirs_are_not_shared() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut service = service(json!({
+            "a": { "enable": true, "path": "a.conf", "text": "a" },
+        }));
+        service.unique_config_dir = true;
+        assert!(
+            ConfigDir::shared_path(tmp.path(), &service)
+                .unwrap()
+                .is_none()
+        );
+
+        let first = ConfigDir::new(tmp.path(), &service).await.unwrap();
+        let second = ConfigDir::new(tmp.path(), &service).await.unwrap();
+        let (first_path, second_path) = (PathBuf::from(&first), PathBuf::from(&second));
+
+        assert_ne!(first_path, second_path);
+        for path in [&first_path, &second_path] {
+            assert_eq!(std::fs::read_to_string(path.join("a.conf")).unwrap(), "a");
+        }
+
+        drop(first);
+        assert!(!first_path.exists());
+        assert!(second_path.join("a.conf").exists());
+        drop(second);
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn the_directory_name_does_not_depend_on_the_map_order() {
+        let entry = |i: usize| {
+            let cfg: ConfigData = serde_json::from_value(json!({
+                "enable": true, "path": format!("{i}.conf"), "text": format!("{i}"),
+            }))
+            .unwrap();
+            (format!("entry-{i}"), cfg)
+        };
+        let forward: ConfigDataMap = (0..32).map(entry).collect();
+        let backward: ConfigDataMap = (0..32).rev().map(entry).collect();
+
+        assert_eq!(
+            ConfigDir::generate_config_directory_name(&forward, Materialize::Symlink).unwrap(),
+            ConfigDir::generate_config_directory_name(&backward, Materialize::Symlink).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn the_directory_name_is_hashed_once_per_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let service = service(json!({
+            "a": { "enable": true, "path": "a.conf", "text": "a" },
+        }));
+        let before = HASHED.with(|hashed| hashed.get());
+
+        let path = ConfigDir::shared_path(tmp.path(), &service).unwrap();
+        let first = ConfigDir::new(tmp.path(), &service).await.unwrap();
+        drop(first);
+        let second = ConfigDir::new(tmp.path(), &service).await.unwrap();
+
+        assert_eq!(HASHED.with(|hashed| hashed.get()) - before, 1);
+        assert_eq!(Some(Path::new(&second)), path.as_deref());
+    }
+
+    #[tokio::test]
+    async fn a_shared_directory_is_only_written_by_its_first_user() {
+        let tmp = tempfile::tempdir().unwrap();
+        let service = service(json!({
+            "a": { "enable": true, "path": "a.conf", "text": "a" },
+        }));
+
+        let first = ConfigDir::new(tmp.path(), &service).await.unwrap();
+        let file = Path::new(&first).join("a.conf");
+        std::fs::write(&file, "edited while in use").unwrap();
+        let _second = ConfigDir::new(tmp.path(), &service).await.unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(file).unwrap(),
+            "edited while in use"
+        );
+    }
+
+    #[test]
+    fn the_directory_name_is_stable_across_builds() {
+        // Directories left from an earlier run of another nimi build are only reused
+        // when it derives the same name
+        let service = service(json!({
+            "a": { "enable": true, "path": "a.conf", "text": "a" },
+        }));
+
+        assert_eq!(
+            ConfigDir::generate_config_directory_name(&service.config_data, service.materialize)
+                .unwrap(),
+            "nimi-config-9d563ae222a7c4014a0467aa2be2a457ff57b101895b45068e7f9d86b0c7a1bd"
+        );
+    }
+}
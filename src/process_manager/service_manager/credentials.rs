@@ -0,0 +1,201 @@
+//! Credentials Module
+//!
+//! Resolves the user and groups a service should run as
+
+use std::ffi::CString;
+
+use eyre::{Context, OptionExt, Result, eyre};
+use nix::unistd::{
+    Gid, Group, Uid, User, getgid, getgrouplist, getgroups, setgid, setgroups, setuid,
+};
+
+use crate::process_manager::service::Identity;
+
+/// Resolved credentials for a service process
+///
+/// Built before spawning so that the `pre_exec` hook only has to perform
+/// async-signal-safe syscalls
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    uid: Option<Uid>,
+    gid: Gid,
+    groups: Vec<Gid>,
+}
+
+impl Credentials {
+    /// Resolve the configured user and groups to numeric ids
+    ///
+    /// Returns `None` if neither a user, a group nor supplementary groups are
+    /// configured. `supplementary_groups` are added to the groups of the user, or to
+    /// those of nimi when no user is set
+    pub fn resolve(
+        user: Option<&Identity>,
+        group: Option<&Identity>,
+        supplementary_groups: &[Identity],
+    ) -> Result<Option<Self>> {
+        let extra_groups = supplementary_groups
+            .iter()
+            .map(Self::resolve_group)
+            .collect::<Result<Vec<_>>>()?;
+        if extra_groups.is_empty() {
+            return Self::resolve_identity(user, group);
+        }
+        eyre::ensure!(
+            Uid::effective().is_root(),
+            "Setting `process.supplementaryGroups` requires nimi to run as root"
+        );
+
+        let mut credentials = match Self::resolve_identity(user, group)? {
+            Some(credentials) => credentials,
+            None => Self {
+                uid: None,
+                gid: getgid(),
+                groups: getgroups().wrap_err("Failed to get the groups of nimi")?,
+            },
+        };
+        for gid in extra_groups {
+            if !credentials.groups.contains(&gid) {
+                credentials.groups.push(gid);
+            }
+        }
+
+        Ok(Some(credentials))
+    }
+
+    /// Resolve the user and primary group, with the supplementary groups of the user
+    fn resolve_identity(user: Option<&Identity>, group: Option<&Identity>) -> Result<Option<Self>> {
+        let group = group.map(Self::resolve_group).transpose()?;
+
+        let Some(user) = user else {
+            return Ok(group.map(|gid| Self {
+                uid: None,
+                gid,
+                groups: vec![gid],
+            }));
+        };
+
+        let (uid, entry) = Self::resolve_user(user)?;
+        let gid = match (group, &entry) {
+            (Some(gid), _) => gid,
+            (None, Some(entry)) => entry.gid,
+            (None, None) => {
+                return Err(eyre!(
+                    "User {uid} has no passwd entry, set `process.group` explicitly"
+                ));
+            }
+        };
+
+        let groups = match entry {
+            Some(entry) => {
+                let name = CString::new(entry.name.as_str())
+                    .wrap_err_with(|| format!("Invalid user name: {:?}", entry.name))?;
+                getgrouplist(&name, gid).wrap_err_with(|| {
+                    format!(
+                        "Failed to get supplementary groups for user {:?}",
+                        entry.name
+                    )
+                })?
+            }
+            None => vec![gid],
+        };
+
+        Ok(Some(Self {
+            uid: Some(uid),
+            gid,
+            groups,
+        }))
+    }
+
+    /// Resolve the configured user to its uid
+    pub fn resolve_uid(user: &Identity) -> Result<Uid> {
+        Ok(Self::resolve_user(user)?.0)
+    }
+
+    fn resolve_user(user: &Identity) -> Result<(Uid, Option<User>)> {
+        match user {
+            Identity::Id(id) => {
+                let uid = Uid::from_raw(*id);
+                let entry =
+                    User::from_uid(uid).wrap_err_with(|| format!("Failed to look up user {id}"))?;
+
+                Ok((uid, entry))
+            }
+            Identity::Name(name) => {
+                let entry = User::from_name(name)
+                    .wrap_err_with(|| format!("Failed to look up user {name:?}"))?
+                    .ok_or_eyre(format!("No such user: {name:?}"))?;
+
+                Ok((entry.uid, Some(entry)))
+            }
+        }
+    }
+
+    fn resolve_group(group: &Identity) -> Result<Gid> {
+        match group {
+            Identity::Id(id) => Ok(Gid::from_raw(*id)),
+            Identity::Name(name) => Group::from_name(name)
+                .wrap_err_with(|| format!("Failed to look up group {name:?}"))?
+                .map(|entry| entry.gid)
+                .ok_or_eyre(format!("No such group: {name:?}")),
+        }
+    }
+
+    /// Switch the current process over to these credentials
+    ///
+    /// Intended to be called from a `pre_exec` hook, groups are dropped before
+    /// the user so that the process still has permission to change them
+    pub fn apply(&self) -> std::io::Result<()> {
+        setgroups(&self.groups)?;
+        setgid(self.gid)?;
+        if let Some(uid) = self.uid {
+            setuid(uid)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_to_resolve_keeps_the_credentials_of_nimi() {
+        assert!(Credentials::resolve(None, None, &[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn supplementary_groups_extend_the_groups_of_nimi() {
+        if !Uid::effective().is_root() {
+            let err = Credentials::resolve(None, None, &[Identity::Id(4242)]).unwrap_err();
+            assert!(err.to_string().contains("requires nimi to run as root"));
+            return;
+        }
+
+        let credentials = Credentials::resolve(None, None, &[Identity::Id(4242)])
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(credentials.uid, None);
+        assert_eq!(credentials.gid, getgid());
+        assert!(credentials.groups.contains(&Gid::from_raw(4242)));
+        for gid in getgroups().unwrap() {
+            assert!(credentials.groups.contains(&gid));
+        }
+    }
+
+    #[test]
+    fn unknown_supplementary_groups_fail() {
+        let err = Credentials::resolve(
+            None,
+            None,
+            &[Identity::Name("nimi-test-no-such-group".into())],
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "No such group: \"nimi-test-no-such-group\""
+        );
+    }
+}
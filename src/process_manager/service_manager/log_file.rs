@@ -0,0 +1,215 @@
+//! Log File Module
+//!
+//! Handles appending the output of a service to its log file and rotating it
+
+use std::{
+    ffi::OsString,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use eyre::{Context, Result};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncWriteExt, BufWriter},
+    sync::Mutex,
+};
+
+use crate::process_manager::settings::LogRotation;
+
+/// Log file shared between the loggers of a single service
+///
+/// None once writing to the file failed
+pub type SharedLogFile = Arc<Mutex<Option<LogFile>>>;
+
+/// Log file struct
+///
+/// Appends lines to a file, rotating it to `<path>.1`, `<path>.2`, ... once it
+/// grows past the configured size
+pub struct LogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+    rotation: LogRotation,
+}
+
+impl LogFile {
+    /// Open a log file for appending
+    ///
+    /// Creates the parent directories of the file when they are missing
+    pub async fn open(path: &Path, rotation: LogRotation) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .wrap_err_with(|| format!("Failed to create the directory for {path:?}"))?;
+        }
+
+        let (writer, size) = Self::open_writer(path).await?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            writer,
+            size,
+            rotation,
+        })
+    }
+
+    /// Append a line to the log file
+    ///
+    /// Every line is flushed right away so the file stays current if nimi gets killed
+    pub async fn write_line(&mut self, line: &[u8]) -> Result<()> {
+        if self
+            .rotation
+            .max_size
+            .is_some_and(|max_size| self.size >= max_size)
+        {
+            self.rotate().await?;
+        }
+
+        self.writer.write_all(line).await?;
+        self.writer.write_all(b"\n").await?;
+        self.writer.flush().await?;
+        self.size += line.len() as u64 + 1;
+
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        let max_files = self.rotation.max_files;
+
+        if max_files == 0 {
+            remove_if_exists(&self.path).await?;
+        } else {
+            remove_if_exists(&self.rotated_path(max_files)).await?;
+            for no in (1..max_files).rev() {
+                rename_if_exists(&self.rotated_path(no), &self.rotated_path(no + 1)).await?;
+            }
+            rename_if_exists(&self.path, &self.rotated_path(1)).await?;
+        }
+
+        (self.writer, self.size) = Self::open_writer(&self.path).await?;
+
+        Ok(())
+    }
+
+    fn rotated_path(&self, no: usize) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{no}"));
+
+        PathBuf::from(path)
+    }
+
+    async fn open_writer(path: &Path) -> Result<(BufWriter<File>, u64)> {
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .await
+            .wrap_err_with(|| format!("Failed to open log file {path:?}"))?;
+        let size = file
+            .metadata()
+            .await
+            .wrap_err_with(|| format!("Failed to read metadata of log file {path:?}"))?
+            .len();
+
+        Ok((BufWriter::new(file), size))
+    }
+}
+
+async fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).wrap_err_with(|| format!("Failed to remove log file {path:?}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn rename_if_exists(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).wrap_err_with(|| format!("Failed to rotate log file {from:?}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotation(max_size: u64, max_files: usize) -> LogRotation {
+        LogRotation {
+            max_size: Some(max_size),
+            max_files,
+        }
+    }
+
+    /// Paths in `dir` starting with `service.txt`, sorted
+    fn log_files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("service.txt"))
+            .collect();
+        files.sort();
+
+        files
+    }
+
+    #[tokio::test]
+    async fn log_file_is_rotated_past_max_size() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("service.txt");
+        let mut log_file = LogFile::open(&path, rotation(10, 2)).await.unwrap();
+
+        for line in ["line 1 ...", "line 2 ...", "line 3 ...", "line 4 ..."] {
+            log_file.write_line(line.as_bytes()).await.unwrap();
+        }
+
+        assert_eq!(
+            log_files(tmp.path()),
+            ["service.txt", "service.txt.1", "service.txt.2"]
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "line 4 ...\n");
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("service.txt.1")).unwrap(),
+            "line 3 ...\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("service.txt.2")).unwrap(),
+            "line 2 ...\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn log_file_is_truncated_without_rotated_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("service.txt");
+        let mut log_file = LogFile::open(&path, rotation(10, 0)).await.unwrap();
+
+        for line in ["line 1 ...", "line 2 ..."] {
+            log_file.write_line(line.as_bytes()).await.unwrap();
+        }
+
+        assert_eq!(log_files(tmp.path()), ["service.txt"]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "line 2 ...\n");
+    }
+
+    #[tokio::test]
+    async fn log_file_size_counts_what_is_already_there() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("service.txt");
+        std::fs::write(&path, "earlier run\n").unwrap();
+        let mut log_file = LogFile::open(&path, rotation(10, 1)).await.unwrap();
+
+        log_file.write_line(b"this run").await.unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("service.txt.1")).unwrap(),
+            "earlier run\n"
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "this run\n");
+    }
+}
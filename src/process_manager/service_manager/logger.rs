@@ -4,51 +4,282 @@
 
 use std::{
     fmt::Debug,
-    path::{Path, PathBuf},
-    sync::Arc,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
 use eyre::{Context, ContextCompat, Result};
-use log::{debug, error};
+use jiff::Timestamp;
+use log::{Level, LevelFilter, debug, error, log, warn};
 use tokio::{
-    fs::{self, File},
-    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, BufWriter, Lines},
-    task::JoinSet,
+    io::{self, AsyncBufReadExt, AsyncRead, BufReader},
+    sync::{Notify, mpsc},
+    task::{JoinHandle, JoinSet},
 };
 
+use crate::process_manager::{
+    service::LogLevelRegex,
+    service_manager::{
+        log_file::SharedLogFile,
+        syslog::{Severity, Syslog},
+    },
+};
+
+/// Where the loggers print the lines of services to
+#[derive(Clone, Default)]
+pub enum LogSink {
+    /// Print through the global logger
+    #[default]
+    Console,
+
+    /// Send to the local syslog socket
+    Syslog(Arc<Syslog>),
+}
+
+impl LogSink {
+    /// Connect to the local syslog socket
+    ///
+    /// Falls back to the console if the socket is unavailable
+    pub fn syslog() -> Self {
+        match Syslog::connect(Path::new(Syslog::SOCKET)) {
+            Ok(syslog) => Self::Syslog(Arc::new(syslog)),
+            Err(e) => {
+                warn!(
+                    "Failed to connect to syslog at {}, printing logs to the console: {e}",
+                    Syslog::SOCKET
+                );
+                Self::Console
+            }
+        }
+    }
+}
+
+/// How the loggers print the lines of services
+#[derive(Clone, Default)]
+pub struct LogOutput {
+    /// If every line is prepended with a timestamp and the target
+    pub prefix: bool,
+
+    /// Where the lines are printed to
+    pub sink: LogSink,
+
+    /// Lines longer than this many bytes are truncated, see `LinesReader`
+    pub max_line_length: usize,
+
+    /// Queue of the log writer task, lines are printed directly while it isn't started
+    queue: Option<LogQueue>,
+
+    /// Notified for every line read, see `with_activity`
+    activity: Option<Arc<Notify>>,
+
+    /// Classifies the lines read, see `with_level_regex`
+    level_regex: Option<LogLevelRegex>,
+}
+
+/// Sending half of the queue of the log writer task
+#[derive(Clone)]
+struct LogQueue {
+    lines: mpsc::Sender<QueuedLine>,
+
+    /// Counters of the lines every logger dropped because the queue was full
+    suppressed: Arc<Mutex<Vec<Arc<Suppressed>>>>,
+}
+
+/// Line waiting in the queue of the log writer task
+struct QueuedLine {
+    logger: Logger,
+    target: Arc<String>,
+    line: String,
+}
+
+/// Number of lines a single logger dropped since they were last reported
+struct Suppressed {
+    target: Arc<String>,
+    count: AtomicUsize,
+}
+
+impl LogOutput {
+    /// Number of lines the log writer task queues before loggers start dropping lines
+    const QUEUE_CAPACITY: usize = 1024;
+
+    /// How often dropped lines are reported while the queue stays full
+    const SUPPRESSED_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// How long `stop_writer` waits for the queued lines to be printed
+    const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Print the lines of every logger cloned from this output through a single task
+    ///
+    /// Lines of a service writing faster than they can be printed are dropped while the
+    /// queue is full, and counted in a message once the queue has been worked off or
+    /// every `SUPPRESSED_INTERVAL` while it stays full. Logs files still get every line
+    pub fn start_writer(&mut self) -> JoinHandle<()> {
+        let (lines, mut queued) = mpsc::channel(Self::QUEUE_CAPACITY);
+        let suppressed = Arc::<Mutex<Vec<Arc<Suppressed>>>>::default();
+        self.queue = Some(LogQueue {
+            lines,
+            suppressed: Arc::clone(&suppressed),
+        });
+
+        let sink = self.sink.clone();
+        tokio::spawn(async move {
+            let mut reported_at = Instant::now();
+            loop {
+                // Also wakes up without new lines, so the last dropped lines of a logger
+                // which went quiet are still reported
+                match tokio::time::timeout(Self::SUPPRESSED_INTERVAL, queued.recv()).await {
+                    Ok(Some(QueuedLine {
+                        logger,
+                        target,
+                        line,
+                    })) => logger.print(&sink, &target, &line).await,
+                    Ok(None) => {
+                        Self::report_suppressed(&suppressed);
+                        break;
+                    }
+                    Err(_) => {}
+                }
+
+                if queued.is_empty() || reported_at.elapsed() >= Self::SUPPRESSED_INTERVAL {
+                    Self::report_suppressed(&suppressed);
+                    reported_at = Instant::now();
+                }
+            }
+        })
+    }
+
+    /// Stop queueing lines and wait for the log writer task to print the queued ones
+    ///
+    /// Loggers still holding a clone of the queue keep the task alive, so this gives up
+    /// after `FLUSH_TIMEOUT`
+    pub async fn stop_writer(&mut self, writer: JoinHandle<()>) {
+        self.queue = None;
+
+        if tokio::time::timeout(Self::FLUSH_TIMEOUT, writer)
+            .await
+            .is_err()
+        {
+            debug!("Gave up on printing the remaining queued log lines");
+        }
+    }
+
+    /// Log how many lines every logger dropped, forgetting about loggers which finished
+    fn report_suppressed(suppressed: &Mutex<Vec<Arc<Suppressed>>>) {
+        let Ok(mut suppressed) = suppressed.lock() else {
+            return;
+        };
+
+        suppressed.retain(|logger| {
+            let count = logger.count.swap(0, Ordering::Relaxed);
+            if count > 0 {
+                warn!(
+                    "Suppressed {count} lines of {}, the service logs faster than they can be printed",
+                    logger.target
+                );
+            }
+
+            Arc::strong_count(logger) > 1
+        });
+    }
+
+    /// Notify `activity` about every line the loggers using this output read
+    ///
+    /// Lets the output watchdog of a service tell a quiet process from a busy one
+    pub fn with_activity(self, activity: Arc<Notify>) -> Self {
+        Self {
+            activity: Some(activity),
+            ..self
+        }
+    }
+
+    /// Print every line at the level `level_regex` finds in it
+    ///
+    /// Lines without a level are printed at the level of their logger
+    pub fn with_level_regex(self, level_regex: Option<LogLevelRegex>) -> Self {
+        Self {
+            level_regex,
+            ..self
+        }
+    }
+
+    /// Create the counter of dropped lines for a new logger
+    fn track_suppressed(&self, target: &Arc<String>) -> Arc<Suppressed> {
+        let suppressed = Arc::new(Suppressed {
+            target: Arc::clone(target),
+            count: AtomicUsize::new(0),
+        });
+        if let Some(queue) = &self.queue
+            && let Ok(mut loggers) = queue.suppressed.lock()
+        {
+            loggers.push(Arc::clone(&suppressed));
+        }
+
+        suppressed
+    }
+
+    /// Hand a line to the log writer task, or print it right away if there is none
+    async fn emit(
+        &self,
+        logger: Logger,
+        target: &Arc<String>,
+        line: String,
+        suppressed: &Suppressed,
+    ) {
+        let Some(queue) = &self.queue else {
+            logger.print(&self.sink, target, &line).await;
+            return;
+        };
+
+        let queued = QueuedLine {
+            logger,
+            target: Arc::clone(target),
+            line,
+        };
+        if queue.lines.try_send(queued).is_err() {
+            suppressed.count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
 /// Logger type
 ///
-/// Formats the logs differently based on if they are intended for stdout or stderr
+/// Prints the lines of stdout or stderr at the level it holds
+#[derive(Clone, Copy)]
 pub enum Logger {
-    /// Regular process logs
-    Stdout,
+    /// Regular process logs, usually printed at `debug`
+    Stdout(Level),
 
-    /// Process error logs
-    Stderr,
+    /// Process error logs, usually printed at `error`
+    Stderr(Level),
 }
 
 impl Logger {
     /// Start a logger for a given file descriptor
+    ///
+    /// Lines are only printed to the `output` if `level` lets the level they are
+    /// logged at through, the logs file keeps every line
     pub fn start<D>(
         self,
         fd: &mut Option<D>,
         target: Arc<String>,
-        logs_dir: Arc<Option<PathBuf>>,
+        logs_file: Option<SharedLogFile>,
+        output: LogOutput,
+        level: LevelFilter,
         set: &mut JoinSet<Result<()>>,
     ) -> Result<()>
     where
         D: AsyncRead + Unpin + Send + 'static + Debug,
     {
-        let reader = Self::get_lines_reader(fd)
+        let reader = Self::get_lines_reader(fd, output.max_line_length)
             .wrap_err("Failed to acquire lines reader for stdout logger")?;
 
         set.spawn(async move {
-            if let Some(ref logs_dir) = *logs_dir {
-                self.write_logs_console_and_file(reader, &target, logs_dir)
-                    .await?;
-            } else {
-                self.write_logs_console_only(reader, &target).await
-            }
+            self.write_logs(reader, &target, logs_file, &output, level)
+                .await;
 
             Ok::<_, eyre::Report>(())
         });
@@ -56,91 +287,488 @@ impl Logger {
         Ok(())
     }
 
-    async fn write_logs_console_only<D>(&self, mut reader: Lines<BufReader<D>>, target: &str)
-    where
+    async fn write_logs<D>(
+        &self,
+        mut reader: LinesReader<D>,
+        target: &Arc<String>,
+        logs_file: Option<SharedLogFile>,
+        output: &LogOutput,
+        level: LevelFilter,
+    ) where
         D: AsyncRead + Unpin + Send + 'static,
     {
+        let suppressed = output.track_suppressed(target);
+
         loop {
             match reader.next_line().await {
                 Ok(Some(line)) => {
-                    self.log_line(target, &line);
+                    if let Some(activity) = &output.activity {
+                        activity.notify_one();
+                    }
+                    self.log_line(
+                        target,
+                        &String::from_utf8_lossy(line),
+                        output,
+                        level,
+                        &suppressed,
+                    )
+                    .await;
+                    Self::write_log_file_line(logs_file.as_ref(), target, line).await;
                 }
                 Ok(None) => break,
                 Err(e) => {
-                    error!(target: &target, "{}", e);
+                    error!("Failed to read output of {target}: {e}");
+                    Self::write_log_file_line(logs_file.as_ref(), target, e.to_string().as_bytes())
+                        .await;
                     break;
                 }
             }
         }
     }
 
-    async fn write_logs_console_and_file<D>(
+    /// Append a line to the logs file
+    ///
+    /// After the first failed write the file is dropped and only the console gets logs
+    async fn write_log_file_line(logs_file: Option<&SharedLogFile>, target: &str, line: &[u8]) {
+        let Some(logs_file) = logs_file else {
+            return;
+        };
+        let mut logs_file = logs_file.lock().await;
+        let Some(writer) = logs_file.as_mut() else {
+            return;
+        };
+
+        if let Err(e) = writer.write_line(line).await {
+            error!("Failed to write logs file of {target}, only logging to the console: {e:#}");
+            *logs_file = None;
+        }
+    }
+
+    async fn log_line(
         &self,
-        mut reader: Lines<BufReader<D>>,
-        target: &str,
-        logs_dir: &Path,
-    ) -> Result<()>
+        target: &Arc<String>,
+        line: &str,
+        output: &LogOutput,
+        level: LevelFilter,
+        suppressed: &Suppressed,
+    ) {
+        let logger = match output
+            .level_regex
+            .as_ref()
+            .and_then(|regex| regex.level_of(line))
+        {
+            Some(found) => self.at_level(found),
+            None => *self,
+        };
+        let (Logger::Stdout(line_level) | Logger::Stderr(line_level)) = logger;
+        if line_level > level {
+            return;
+        }
+
+        let line = if output.prefix {
+            format!("{} [{target}] {line}", Timestamp::now())
+        } else {
+            line.to_owned()
+        };
+
+        output.emit(logger, target, line, suppressed).await;
+    }
+
+    /// The same logger printing at `level`
+    fn at_level(self, level: Level) -> Self {
+        match self {
+            Self::Stdout(_) => Self::Stdout(level),
+            Self::Stderr(_) => Self::Stderr(level),
+        }
+    }
+
+    /// Print a line to `sink`, falling back to the console if syslog is unavailable
+    async fn print(self, sink: &LogSink, target: &str, line: &str) {
+        let (Self::Stdout(level) | Self::Stderr(level)) = self;
+
+        if let LogSink::Syslog(syslog) = sink {
+            let severity = match level {
+                Level::Error => Severity::Err,
+                Level::Warn => Severity::Warning,
+                Level::Info | Level::Debug | Level::Trace => Severity::Info,
+            };
+            if syslog.send(severity, target, line).await {
+                return;
+            }
+        }
+
+        log!(target: target, level, "{}", line);
+    }
+
+    fn get_lines_reader<D>(fd: &mut Option<D>, max_len: usize) -> Result<LinesReader<D>>
     where
-        D: AsyncRead + Unpin + Send + 'static,
+        D: AsyncRead + Debug,
     {
-        let mut logs_file = Self::create_logs_file(logs_dir, target).await?;
+        let taken = fd
+            .take()
+            .wrap_err_with(|| format!("Service was missing field for {:?}", fd))?;
+
+        Ok(LinesReader {
+            reader: BufReader::new(taken),
+            buf: Vec::new(),
+            max_len,
+        })
+    }
+}
+
+/// Reads raw lines from a process output
+///
+/// Unlike [`tokio::io::Lines`] this doesn't require the output to be valid UTF-8,
+/// so a single stray byte can't end the log capture for a service
+///
+/// Lines are cut off after `max_len` bytes and the rest of them is skipped, so output
+/// without any newlines can't grow the buffer without bound
+struct LinesReader<D> {
+    reader: BufReader<D>,
+    buf: Vec<u8>,
+    max_len: usize,
+}
+
+impl<D> LinesReader<D>
+where
+    D: AsyncRead + Unpin,
+{
+    /// Read the next line without its `\n` or `\r\n` terminator
+    ///
+    /// A truncated line ends in a marker telling how many bytes were dropped
+    async fn next_line(&mut self) -> io::Result<Option<&[u8]>> {
+        self.buf.clear();
+        let mut line_len = 0;
+        let mut read_any = false;
 
         loop {
-            match reader.next_line().await {
-                Ok(Some(line)) => {
-                    self.log_line(target, &line);
-                    Self::write_log_file_line(&mut logs_file, &line).await?;
-                }
-                Ok(None) => break,
-                Err(e) => {
-                    error!(target: &target, "{}", e);
-                    Self::write_log_file_line(&mut logs_file, e.to_string().as_str()).await?;
-                    break;
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                if !read_any {
+                    return Ok(None);
                 }
+                break;
+            }
+            read_any = true;
+
+            let newline = available.iter().position(|byte| *byte == b'\n');
+            let content = &available[..newline.unwrap_or(available.len())];
+            let room = self.max_len.saturating_sub(self.buf.len());
+            self.buf
+                .extend_from_slice(&content[..content.len().min(room)]);
+            line_len += content.len();
+
+            let consumed = newline.map_or(available.len(), |newline| newline + 1);
+            self.reader.consume(consumed);
+            if newline.is_some() {
+                break;
             }
         }
 
-        logs_file.flush().await?;
+        let dropped = line_len - self.buf.len();
+        if dropped > 0 {
+            self.buf
+                .extend_from_slice(format!(" [{dropped} bytes truncated]").as_bytes());
+        } else if self.buf.ends_with(b"\r") {
+            self.buf.pop();
+        }
 
-        Ok(())
+        Ok(Some(&self.buf))
     }
+}
 
-    async fn create_logs_file(logs_dir: &Path, target: &str) -> Result<BufWriter<File>> {
-        let logs_path = logs_dir.join(format!("{}.txt", &target));
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
 
-        let file = fs::OpenOptions::new()
-            .write(true)
-            .append(true)
-            .create(true)
-            .open(logs_path)
-            .await
-            .wrap_err_with(|| format!("Failed to create logs file for {}", &target))?;
+    use super::*;
 
-        Ok(BufWriter::new(file))
+    /// Output queueing its lines into the returned receiver instead of printing them
+    fn captured_output() -> (LogOutput, mpsc::Receiver<QueuedLine>) {
+        let (lines, queued) = mpsc::channel(LogOutput::QUEUE_CAPACITY);
+        let output = LogOutput {
+            max_line_length: 64 * 1024,
+            queue: Some(LogQueue {
+                lines,
+                suppressed: Arc::default(),
+            }),
+            ..LogOutput::default()
+        };
+
+        (output, queued)
     }
 
-    async fn write_log_file_line(writer: &mut BufWriter<File>, line: &str) -> Result<()> {
-        writer.write_all(line.as_bytes()).await?;
-        writer.write_all(b"\n").await?;
+    /// Run `logger` over `input` and collect the level and text of every line it printed
+    async fn logged(
+        logger: Logger,
+        output: LogOutput,
+        mut queued: mpsc::Receiver<QueuedLine>,
+        input: &[u8],
+        level: LevelFilter,
+    ) -> Vec<(Level, String)> {
+        let mut set = JoinSet::new();
+        logger
+            .start(
+                &mut Some(Cursor::new(input.to_vec())),
+                Arc::new("test".to_owned()),
+                None,
+                output,
+                level,
+                &mut set,
+            )
+            .unwrap();
+        set.join_all().await;
 
-        Ok(())
+        let mut lines = Vec::new();
+        while let Ok(QueuedLine { logger, line, .. }) = queued.try_recv() {
+            let (Logger::Stdout(level) | Logger::Stderr(level)) = logger;
+            lines.push((level, line));
+        }
+
+        lines
     }
 
-    fn log_line(&self, target: &str, line: &str) {
-        match self {
-            Self::Stdout => debug!(target: target, "{}", line),
-            Self::Stderr => error!(target: target, "{}", line),
+    #[tokio::test]
+    async fn lines_after_invalid_utf8_are_still_logged() {
+        let (output, queued) = captured_output();
+
+        let lines = logged(
+            Logger::Stdout(Level::Info),
+            output,
+            queued,
+            b"before\n\xff\xfe latin-1\nafter\n",
+            LevelFilter::Trace,
+        )
+        .await;
+
+        assert_eq!(
+            lines,
+            [
+                (Level::Info, "before".to_owned()),
+                (Level::Info, "\u{fffd}\u{fffd} latin-1".to_owned()),
+                (Level::Info, "after".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn last_line_without_newline_is_logged() {
+        let mut reader = LinesReader {
+            reader: BufReader::new(&b"first\r\nlast"[..]),
+            buf: Vec::new(),
+            max_len: 1024,
+        };
+
+        assert_eq!(reader.next_line().await.unwrap(), Some(&b"first"[..]));
+        assert_eq!(reader.next_line().await.unwrap(), Some(&b"last"[..]));
+        assert_eq!(reader.next_line().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn stdout_goes_to_syslog_as_info_and_stderr_as_err() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("log");
+        let socket = tokio::net::UnixDatagram::bind(&path).unwrap();
+        let sink = LogSink::Syslog(Arc::new(Syslog::connect(&path).unwrap()));
+
+        Logger::Stdout(Level::Debug)
+            .print(&sink, "web", "out")
+            .await;
+        Logger::Stderr(Level::Error)
+            .print(&sink, "web", "err")
+            .await;
+
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"<30>web: out");
+        let len = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"<27>web: err");
+    }
+
+    #[tokio::test]
+    async fn long_lines_are_truncated_across_reads() {
+        let mut reader = LinesReader {
+            reader: BufReader::with_capacity(3, &b"abcdefghij\nabcd\nok\n"[..]),
+            buf: Vec::new(),
+            max_len: 4,
+        };
+
+        assert_eq!(
+            reader.next_line().await.unwrap(),
+            Some(&b"abcd [6 bytes truncated]"[..])
+        );
+        assert_eq!(reader.next_line().await.unwrap(), Some(&b"abcd"[..]));
+        assert_eq!(reader.next_line().await.unwrap(), Some(&b"ok"[..]));
+        assert_eq!(reader.next_line().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn output_without_newlines_is_bounded() {
+        let input = vec![b'x'; 1024 * 1024];
+        let mut reader = LinesReader {
+            reader: BufReader::new(&input[..]),
+            buf: Vec::new(),
+            max_len: 16,
+        };
+
+        let line = reader.next_line().await.unwrap().unwrap().to_vec();
+
+        assert_eq!(
+            line,
+            format!("{} [{} bytes truncated]", "x".repeat(16), 1024 * 1024 - 16).into_bytes()
+        );
+        assert!(reader.buf.capacity() < 1024);
+        assert_eq!(reader.next_line().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn lines_are_dropped_and_counted_while_the_queue_is_full() {
+        let (lines, queued) = mpsc::channel(2);
+        let suppressed = Arc::<Mutex<Vec<Arc<Suppressed>>>>::default();
+        let output = LogOutput {
+            max_line_length: 1024,
+            queue: Some(LogQueue {
+                lines,
+                suppressed: Arc::clone(&suppressed),
+            }),
+            ..LogOutput::default()
+        };
+
+        let lines = logged(
+            Logger::Stdout(Level::Info),
+            output,
+            queued,
+            b"1\n2\n3\n4\n5\n",
+            LevelFilter::Trace,
+        )
+        .await;
+
+        assert_eq!(
+            lines,
+            [(Level::Info, "1".to_owned()), (Level::Info, "2".to_owned())]
+        );
+        let suppressed = suppressed.lock().unwrap();
+        assert_eq!(suppressed.len(), 1);
+        assert_eq!(suppressed[0].count.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn the_writer_prints_the_queued_lines_before_stopping() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("log");
+        let socket = tokio::net::UnixDatagram::bind(&path).unwrap();
+        let mut output = LogOutput {
+            max_line_length: 1024,
+            sink: LogSink::Syslog(Arc::new(Syslog::connect(&path).unwrap())),
+            ..LogOutput::default()
+        };
+        let writer = output.start_writer();
+
+        let mut set = JoinSet::new();
+        Logger::Stdout(Level::Info)
+            .start(
+                &mut Some(Cursor::new(b"one\ntwo\n".to_vec())),
+                Arc::new("web".to_owned()),
+                None,
+                output.clone(),
+                LevelFilter::Trace,
+                &mut set,
+            )
+            .unwrap();
+        set.join_all().await;
+        output.stop_writer(writer).await;
+
+        let mut buf = [0; 64];
+        for expected in [&b"<30>web: one"[..], b"<30>web: two"] {
+            let len = socket.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], expected);
         }
     }
 
-    fn get_lines_reader<D>(fd: &mut Option<D>) -> Result<Lines<BufReader<D>>>
-    where
-        D: AsyncRead + Debug,
-    {
-        let taken = fd
-            .take()
-            .wrap_err_with(|| format!("Service was missing field for {:?}", fd))?;
+    #[tokio::test]
+    async fn lines_are_logged_at_the_level_of_their_stream() {
+        let (output, queued) = captured_output();
+        let stdout = logged(
+            Logger::Stdout(Level::Info),
+            output,
+            queued,
+            b"out\n",
+            LevelFilter::Trace,
+        )
+        .await;
+        let (output, queued) = captured_output();
+        let stderr = logged(
+            Logger::Stderr(Level::Warn),
+            output,
+            queued,
+            b"err\n",
+            LevelFilter::Trace,
+        )
+        .await;
+
+        assert_eq!(stdout, [(Level::Info, "out".to_owned())]);
+        assert_eq!(stderr, [(Level::Warn, "err".to_owned())]);
+    }
+
+    #[tokio::test]
+    async fn the_level_regex_classifies_mixed_lines() {
+        let (output, queued) = captured_output();
+        let regex =
+            serde_json::from_value(serde_json::json!(r"^\[(\w+)\]|level=(?P<level>\w+)")).unwrap();
+
+        let lines = logged(
+            Logger::Stdout(Level::Debug),
+            output.with_level_regex(Some(regex)),
+            queued,
+            b"[ERROR] failed\nlevel=warn slow\n[NOTICE] up\n[LOUD] unknown\nplain\n",
+            LevelFilter::Trace,
+        )
+        .await;
+
+        assert_eq!(
+            lines,
+            [
+                (Level::Error, "[ERROR] failed".to_owned()),
+                (Level::Warn, "level=warn slow".to_owned()),
+                (Level::Info, "[NOTICE] up".to_owned()),
+                (Level::Debug, "[LOUD] unknown".to_owned()),
+                (Level::Debug, "plain".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn the_level_filter_applies_to_both_streams() {
+        let regex: LogLevelRegex =
+            serde_json::from_value(serde_json::json!(r"^\[(\w+)\]")).unwrap();
+        let input = b"[DEBUG] noise\n[WARN] kept\nplain\n";
+
+        let (output, queued) = captured_output();
+        let stdout = logged(
+            Logger::Stdout(Level::Info),
+            output.with_level_regex(Some(regex.clone())),
+            queued,
+            input,
+            LevelFilter::Warn,
+        )
+        .await;
+        let (output, queued) = captured_output();
+        let stderr = logged(
+            Logger::Stderr(Level::Error),
+            output.with_level_regex(Some(regex)),
+            queued,
+            input,
+            LevelFilter::Warn,
+        )
+        .await;
 
-        Ok(BufReader::new(taken).lines())
+        assert_eq!(stdout, [(Level::Warn, "[WARN] kept".to_owned())]);
+        assert_eq!(
+            stderr,
+            [
+                (Level::Warn, "[WARN] kept".to_owned()),
+                (Level::Error, "plain".to_owned()),
+            ]
+        );
     }
 }
@@ -0,0 +1,152 @@
+//! Pty Module
+//!
+//! Runs a service process on a pseudo terminal, for programs which behave differently or
+//! refuse to run without one
+
+use std::{
+    io::{self, ErrorKind},
+    os::fd::{AsRawFd, OwnedFd},
+    pin::Pin,
+    process::Stdio,
+    task::{Context as TaskContext, Poll, ready},
+};
+
+use eyre::{Context, Result};
+use nix::{
+    fcntl::{FcntlArg, FdFlag, OFlag, fcntl},
+    pty::{Winsize, openpty},
+};
+use tokio::{
+    io::{AsyncRead, ReadBuf, unix::AsyncFd},
+    process::Command,
+};
+
+/// Pseudo terminal allocated for a single service process
+pub struct Pty {
+    master: OwnedFd,
+    slave: OwnedFd,
+}
+
+impl Pty {
+    /// Size of the terminal when nimi itself doesn't run on one
+    const DEFAULT_SIZE: Winsize = Winsize {
+        ws_row: 24,
+        ws_col: 80,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    /// Allocate a pty, sized like the terminal nimi runs on if there is one
+    pub fn open() -> Result<Self> {
+        let size = Self::terminal_size().unwrap_or(Self::DEFAULT_SIZE);
+        let pty = openpty(&size, None).wrap_err("Failed to allocate a pty")?;
+
+        for fd in [&pty.master, &pty.slave] {
+            fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+                .wrap_err("Failed to set close-on-exec on the pty")?;
+        }
+        fcntl(pty.master.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .wrap_err("Failed to make the pty master non-blocking")?;
+
+        Ok(Self {
+            master: pty.master,
+            slave: pty.slave,
+        })
+    }
+
+    /// Size of the terminal on the standard output of nimi
+    fn terminal_size() -> Option<Winsize> {
+        let mut size = Self::DEFAULT_SIZE;
+        // SAFETY: `TIOCGWINSZ` only writes a `winsize` to the given pointer
+        let res = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+
+        (res == 0 && size.ws_row > 0 && size.ws_col > 0).then_some(size)
+    }
+
+    /// Connect the standard input, output and error of `command` to the pty
+    ///
+    /// The pty also becomes the controlling terminal of the process, even if its standard
+    /// input is replaced afterwards. Has to be called after `ServiceManager::new_session`
+    /// registered its hook
+    pub fn attach(&self, command: &mut Command) -> Result<()> {
+        let stdio = || -> Result<Stdio> {
+            Ok(self
+                .slave
+                .try_clone()
+                .wrap_err("Failed to duplicate the pty")?
+                .into())
+        };
+        command.stdin(stdio()?).stdout(stdio()?).stderr(stdio()?);
+
+        // The slave kept by nimi is still open in the forked child, only closed on exec
+        let slave = self.slave.as_raw_fd();
+        // SAFETY: `ioctl(2)` is async-signal-safe, the hook only copies the fd number and
+        // a failure only reads `errno`
+        unsafe {
+            command.pre_exec(move || {
+                if libc::ioctl(slave, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Reader of everything the process writes to the pty
+    ///
+    /// Drops the slave side kept by nimi, so the reader ends once the process and
+    /// everything it forked closed the pty
+    pub fn into_reader(self) -> Result<PtyReader> {
+        let Self { master, slave } = self;
+        drop(slave);
+
+        Ok(PtyReader(
+            AsyncFd::new(master).wrap_err("Failed to register the pty with the runtime")?,
+        ))
+    }
+}
+
+/// Master side of a `Pty`, read by the stdout `Logger` of a service
+#[derive(Debug)]
+pub struct PtyReader(AsyncFd<OwnedFd>);
+
+impl AsyncRead for PtyReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.0.poll_read_ready(cx))?;
+
+            let unfilled = buf.initialize_unfilled();
+            let res = guard.try_io(|fd| {
+                // SAFETY: reads into the initialized, unfilled part of the buffer
+                let read = unsafe {
+                    libc::read(fd.as_raw_fd(), unfilled.as_mut_ptr().cast(), unfilled.len())
+                };
+                if read == -1 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(read as usize)
+                }
+            });
+
+            match res {
+                Ok(Ok(read)) => {
+                    buf.advance(read);
+                    return Poll::Ready(Ok(()));
+                }
+                // Linux reports a closed slave side as `EIO` rather than end of file
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => {
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(e)) if e.kind() == ErrorKind::Interrupted => {}
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
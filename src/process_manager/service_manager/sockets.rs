@@ -0,0 +1,420 @@
+//! Sockets Module
+//!
+//! Binds the listening sockets of a service and passes them to its process using the
+//! systemd socket activation protocol
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{CString, OsStr, OsString},
+    io::{self, ErrorKind},
+    net::{SocketAddr, TcpListener, UdpSocket},
+    os::{
+        fd::{AsRawFd, OwnedFd, RawFd},
+        unix::{
+            ffi::{OsStrExt, OsStringExt},
+            fs::FileTypeExt,
+            net::{UnixDatagram, UnixListener},
+        },
+    },
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context, Result, eyre};
+use tokio::process::Command;
+
+use crate::{
+    config::resolve_binary,
+    process_manager::service::{Socket, SocketType},
+};
+
+/// First file descriptor a passed socket is placed at
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Placeholder for the value of `LISTEN_PID`, which is only known after forking
+///
+/// Long enough to hold any pid
+const PID_PLACEHOLDER: &str = "XXXXXXXXXXXXXXXXXXXX";
+
+/// Sockets bound for a single service
+///
+/// They are bound once and kept open across restarts, so connections arriving while the
+/// service restarts are queued instead of refused
+pub struct ListenSockets {
+    fds: Vec<OwnedFd>,
+    names: Vec<String>,
+}
+
+impl ListenSockets {
+    /// Bind every socket of a service
+    pub fn bind(sockets: &[Socket], service: &str) -> Result<Self> {
+        let mut fds = Vec::with_capacity(sockets.len());
+        let mut names = Vec::with_capacity(sockets.len());
+
+        for socket in sockets {
+            let fd = Self::bind_one(socket).wrap_err_with(|| {
+                format!(
+                    "Failed to bind socket {} of service {service}",
+                    socket.listen
+                )
+            })?;
+            let name = socket.name.as_deref().unwrap_or(service);
+            eyre::ensure!(
+                !name.contains(':'),
+                "Socket name {name:?} of service {service} may not contain `:`"
+            );
+
+            fds.push(fd);
+            names.push(name.to_owned());
+        }
+
+        Ok(Self { fds, names })
+    }
+
+    fn bind_one(socket: &Socket) -> Result<OwnedFd> {
+        if socket.listen.contains('/') {
+            let path = Path::new(&socket.listen);
+            remove_stale_socket(path)?;
+
+            return Ok(match socket.kind {
+                SocketType::Stream => UnixListener::bind(path)?.into(),
+                SocketType::Datagram => UnixDatagram::bind(path)?.into(),
+            });
+        }
+
+        let addr: SocketAddr = socket
+            .listen
+            .parse()
+            .wrap_err("Expected `host:port` or the path of a unix socket")?;
+
+        Ok(match socket.kind {
+            SocketType::Stream => TcpListener::bind(addr)?.into(),
+            SocketType::Datagram => UdpSocket::bind(addr)?.into(),
+        })
+    }
+
+    /// Check if the service has no sockets to pass on
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// Pass the sockets to the process spawned by `command`
+    ///
+    /// `LISTEN_PID` can only be filled in after forking, which the environment handling of
+    /// `Command` doesn't allow, so this registers a `pre_exec` hook which execs the program
+    /// itself. The program is looked up in the `PATH` of the service here, before forking,
+    /// so the hook only has to call `execve`. It has to be registered after every other
+    /// hook, `cleared_env` tells if `env_clear` was called on the command
+    pub fn pass_to(&self, command: &mut Command, cleared_env: bool) -> Result<()> {
+        let mut exec = Exec::new(command, cleared_env, self)?;
+
+        // SAFETY: the hook only calls `fcntl`, `dup2`, `getpid` and `execve`, which are
+        // async-signal-safe, and writes the pid into the environment buffer `Exec` allocated
+        // before forking, so nothing is allocated or locked in the child
+        unsafe {
+            command.pre_exec(move || exec.run());
+        }
+
+        Ok(())
+    }
+}
+
+/// Pre-built arguments for the `execve` performed by the `pre_exec` hook
+struct Exec {
+    path: CString,
+    _argv: Vec<CString>,
+    argv_ptrs: Vec<*const libc::c_char>,
+    _envp: Vec<Vec<u8>>,
+    envp_ptrs: Vec<*const libc::c_char>,
+    pid: *mut u8,
+    fds: Vec<RawFd>,
+    dup_fds: Vec<RawFd>,
+}
+
+// SAFETY: the raw pointers point into the heap buffers of `path`, `_argv` and `_envp`,
+// which `Exec` owns and never reallocates, so they stay valid wherever `Exec` is moved.
+// They are only dereferenced by `run` in the forked child, after `Command` was moved to
+// the thread spawning it
+unsafe impl Send for Exec {}
+// SAFETY: nothing is reachable through a shared `&Exec`, `run` needs `&mut self`
+unsafe impl Sync for Exec {}
+
+impl Exec {
+    fn new(command: &Command, cleared_env: bool, sockets: &ListenSockets) -> Result<Self> {
+        let command = command.as_std();
+
+        let mut vars: BTreeMap<OsString, OsString> = if cleared_env {
+            BTreeMap::new()
+        } else {
+            env::vars_os().collect()
+        };
+        for (name, value) in command.get_envs() {
+            match value {
+                Some(value) => vars.insert(name.to_owned(), value.to_owned()),
+                None => vars.remove(name),
+            };
+        }
+        vars.insert("LISTEN_FDS".into(), sockets.fds.len().to_string().into());
+        vars.insert("LISTEN_FDNAMES".into(), sockets.names.join(":").into());
+        vars.insert("LISTEN_PID".into(), PID_PLACEHOLDER.into());
+
+        let program = command.get_program();
+        let path = resolve_program(program, vars.get(OsStr::new("PATH")))?;
+
+        let argv = std::iter::once(program)
+            .chain(command.get_args())
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err("Arguments may not contain NUL bytes")?;
+        let mut envp = vars
+            .into_iter()
+            .map(|(name, value)| {
+                let mut var = name.into_vec();
+                var.push(b'=');
+                var.extend(value.as_bytes());
+                eyre::ensure!(
+                    !var.contains(&0),
+                    "Environment variables may not contain NUL bytes"
+                );
+                var.push(0);
+
+                Ok(var)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut pid = std::ptr::null_mut();
+        let mut envp_ptrs = Vec::with_capacity(envp.len() + 1);
+        for var in &mut envp {
+            let ptr = var.as_mut_ptr();
+            if var.starts_with(b"LISTEN_PID=") {
+                // SAFETY: the offset stays inside of the variable
+                pid = unsafe { ptr.add(b"LISTEN_PID=".len()) };
+            }
+            envp_ptrs.push(ptr.cast_const().cast());
+        }
+        envp_ptrs.push(std::ptr::null());
+        eyre::ensure!(!pid.is_null(), "LISTEN_PID is missing from the environment");
+
+        let argv_ptrs = argv
+            .iter()
+            .map(|arg| arg.as_ptr())
+            .chain([std::ptr::null()])
+            .collect();
+        let fds: Vec<_> = sockets.fds.iter().map(AsRawFd::as_raw_fd).collect();
+
+        Ok(Self {
+            path: CString::new(path.into_os_string().into_vec())
+                .wrap_err("Program path may not contain NUL bytes")?,
+            _argv: argv,
+            argv_ptrs,
+            _envp: envp,
+            envp_ptrs,
+            pid,
+            dup_fds: vec![0; fds.len()],
+            fds,
+        })
+    }
+
+    /// Runs in the forked child, must not allocate
+    fn run(&mut self) -> io::Result<()> {
+        let count = self.fds.len() as RawFd;
+
+        // Move the sockets out of the way first, so placing them can't overwrite another
+        for (fd, dup_fd) in self.fds.iter().zip(&mut self.dup_fds) {
+            // SAFETY: plain syscall on a file descriptor we own
+            *dup_fd = check(unsafe {
+                libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, LISTEN_FDS_START + count)
+            })?;
+        }
+        for (target, dup_fd) in (LISTEN_FDS_START..).zip(&self.dup_fds) {
+            // SAFETY: plain syscall, the new descriptor doesn't inherit `FD_CLOEXEC`
+            check(unsafe { libc::dup2(*dup_fd, target) })?;
+        }
+
+        // SAFETY: `getpid` can't fail
+        let mut pid = unsafe { libc::getpid() }.unsigned_abs();
+        let mut digits = [0; 10];
+        let mut len = 0;
+        loop {
+            digits[len] = b'0' + (pid % 10) as u8;
+            len += 1;
+            pid /= 10;
+            if pid == 0 {
+                break;
+            }
+        }
+        // SAFETY: the placeholder is longer than any pid plus the terminating NUL
+        unsafe {
+            for (i, digit) in digits[..len].iter().rev().enumerate() {
+                *self.pid.add(i) = *digit;
+            }
+            *self.pid.add(len) = 0;
+        }
+
+        // SAFETY: both arrays are NUL terminated and point into buffers owned by `self`
+        unsafe {
+            libc::execve(
+                self.path.as_ptr(),
+                self.argv_ptrs.as_ptr(),
+                self.envp_ptrs.as_ptr(),
+            );
+        }
+
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn check(res: libc::c_int) -> io::Result<libc::c_int> {
+    if res == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+/// Look up `program` the same way `execvp` would, but in the `PATH` of the service
+///
+/// Falls back to the `PATH` of nimi when the service has none, like spawning without
+/// sockets does
+fn resolve_program(program: &OsStr, path: Option<&OsString>) -> Result<PathBuf> {
+    if program.as_bytes().contains(&b'/') {
+        return Ok(PathBuf::from(program));
+    }
+
+    let program = program
+        .to_str()
+        .ok_or_else(|| eyre!("Binary {program:?} is not valid UTF-8"))?;
+    let path = path
+        .map(|path| {
+            path.to_str()
+                .ok_or_else(|| eyre!("PATH {path:?} of the service is not valid UTF-8"))
+        })
+        .transpose()?;
+
+    resolve_binary(program, path).ok_or_else(|| {
+        eyre!(
+            "Binary {program:?} was not found in PATH {:?}, give an absolute path or add its directory to `process.environment.PATH`",
+            path.map_or_else(|| env::var_os("PATH").unwrap_or_default(), OsString::from)
+        )
+    })
+}
+
+/// Remove a unix socket left behind by a previous run, so it can be bound again
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)
+            .wrap_err_with(|| format!("Failed to remove stale socket {path:?}")),
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).wrap_err_with(|| format!("Failed to read metadata of {path:?}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Stdio;
+
+    use super::*;
+
+    fn socket(listen: &str, name: Option<&str>) -> Socket {
+        Socket {
+            listen: listen.to_owned(),
+            kind: SocketType::Stream,
+            name: name.map(str::to_owned),
+        }
+    }
+
+    /// Run `script` through `sh` with `sockets` passed to it and return its stdout
+    async fn run_with(sockets: &ListenSockets, script: &str) -> String {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]).stdout(Stdio::piped());
+        sockets.pass_to(&mut command, false).unwrap();
+
+        let output = command.output().await.unwrap();
+        assert!(output.status.success());
+
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_service_inherits_a_bound_tcp_socket_at_fd_3() {
+        let sockets = ListenSockets::bind(&[socket("127.0.0.1:0", None)], "web").unwrap();
+        let addr = TcpListener::from(sockets.fds[0].try_clone().unwrap())
+            .local_addr()
+            .unwrap();
+
+        let output = run_with(
+            &sockets,
+            "echo $LISTEN_FDS $LISTEN_FDNAMES; [ \"$LISTEN_PID\" = \"$$\" ] && echo pid; readlink /proc/self/fd/3",
+        )
+        .await;
+        let lines: Vec<_> = output.lines().collect();
+
+        assert_eq!(lines[..2], ["1 web", "pid"]);
+        assert!(lines[2].starts_with("socket:"), "{output}");
+        // The socket stays bound in nimi once the service is gone
+        std::net::TcpStream::connect(addr).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sockets_are_passed_in_order_with_their_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("app.sock");
+        let sockets = ListenSockets::bind(
+            &[
+                socket("127.0.0.1:0", Some("http")),
+                socket(path.to_str().unwrap(), Some("admin")),
+            ],
+            "web",
+        )
+        .unwrap();
+
+        let output = run_with(
+            &sockets,
+            "echo $LISTEN_FDS $LISTEN_FDNAMES; [ -S /proc/self/fd/4 ] && echo unix",
+        )
+        .await;
+
+        assert_eq!(output, "2 http:admin\nunix\n");
+    }
+
+    #[test]
+    fn stale_unix_sockets_are_replaced() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("app.sock");
+        drop(UnixListener::bind(&path).unwrap());
+
+        ListenSockets::bind(&[socket(path.to_str().unwrap(), None)], "web").unwrap();
+    }
+
+    #[test]
+    fn socket_names_may_not_contain_colons() {
+        let err = ListenSockets::bind(&[socket("127.0.0.1:0", Some("a:b"))], "web")
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "Socket name \"a:b\" of service web may not contain `:`"
+        );
+    }
+
+    #[test]
+    fn programs_are_looked_up_in_the_path_of_the_service() {
+        assert_eq!(
+            resolve_program(OsStr::new("sh"), Some(&"/nonexistent:/bin".into())).unwrap(),
+            Path::new("/bin/sh")
+        );
+        assert_eq!(
+            resolve_program(OsStr::new("./run"), None).unwrap(),
+            Path::new("./run")
+        );
+
+        let err = resolve_program(OsStr::new("sh"), Some(&"/nonexistent".into())).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Binary \"sh\" was not found in PATH \"/nonexistent\"")
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! Syslog Module
+//!
+//! Sends service logs to the local syslog socket
+
+use std::{
+    io,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use log::warn;
+use tokio::net::UnixDatagram;
+
+/// Syslog severity of a log line
+#[derive(Debug, Clone, Copy)]
+pub enum Severity {
+    /// Error conditions, used for stderr
+    Err = 3,
+
+    /// Warning conditions
+    Warning = 4,
+
+    /// Informational messages, used for stdout
+    Info = 6,
+}
+
+/// Syslog connection struct
+///
+/// Writes messages in the BSD syslog format with the `daemon` facility
+pub struct Syslog {
+    socket: UnixDatagram,
+    warned: AtomicBool,
+}
+
+impl Syslog {
+    /// Default location of the local syslog socket
+    pub const SOCKET: &str = "/dev/log";
+
+    const FACILITY_DAEMON: u8 = 3;
+
+    /// Connect to the syslog socket at `path`
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+
+        Ok(Self {
+            socket,
+            warned: AtomicBool::new(false),
+        })
+    }
+
+    /// Send a single line tagged with the name of the service
+    ///
+    /// Returns `false` if the line couldn't be sent, warning about it only once
+    pub async fn send(&self, severity: Severity, tag: &str, line: &str) -> bool {
+        let priority = Self::FACILITY_DAEMON * 8 + severity as u8;
+        let message = format!("<{priority}>{tag}: {line}");
+
+        match self.socket.send(message.as_bytes()).await {
+            Ok(_) => true,
+            Err(e) => {
+                if !self.warned.swap(true, Ordering::Relaxed) {
+                    warn!("Failed to send logs to syslog, printing them to the console: {e}");
+                }
+
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn received(socket: &UnixDatagram) -> String {
+        let mut buf = [0; 256];
+        let len = socket.recv(&mut buf).await.unwrap();
+
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[tokio::test]
+    async fn lines_are_tagged_with_the_service_and_severity() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("log");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        let syslog = Syslog::connect(&path).unwrap();
+
+        assert!(syslog.send(Severity::Info, "web", "listening").await);
+        assert!(syslog.send(Severity::Err, "web", "crashed").await);
+
+        assert_eq!(received(&socket).await, "<30>web: listening");
+        assert_eq!(received(&socket).await, "<27>web: crashed");
+    }
+
+    #[tokio::test]
+    async fn sending_fails_once_the_socket_is_gone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("log");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        let syslog = Syslog::connect(&path).unwrap();
+        drop(socket);
+
+        assert!(!syslog.send(Severity::Info, "web", "listening").await);
+        assert!(syslog.warned.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn missing_socket_fails_to_connect() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        assert!(Syslog::connect(&tmp.path().join("log")).is_err());
+    }
+}
@@ -0,0 +1,267 @@
+//! Watchdog Module
+//!
+//! Implements the service manager side of the `sd_notify` watchdog, so a service can
+//! prove it is alive by sending `WATCHDOG=1` pings
+
+use std::{
+    fs::Permissions,
+    io::{self, IoSliceMut},
+    os::{fd::AsRawFd, unix::fs::PermissionsExt},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use eyre::{Context, Result};
+use log::{debug, warn};
+use nix::{
+    cmsg_space,
+    sys::socket::{ControlMessageOwned, MsgFlags, recvmsg, setsockopt, sockopt::PassCred},
+    unistd::{Pid, Uid, chown, getpgid, mkdtemp},
+};
+use tokio::{
+    fs,
+    io::Interest,
+    net::UnixDatagram,
+    process::Command,
+    task,
+    time::{Instant, timeout_at},
+};
+
+use crate::process_manager::notify::NOTIFY_SOCKET;
+
+/// Notification socket a single service sends its watchdog pings to
+///
+/// Bound once and kept across restarts, its directory is removed again when dropped
+pub struct WatchdogSocket {
+    socket: UnixDatagram,
+    dir: PathBuf,
+    interval: Duration,
+}
+
+impl WatchdogSocket {
+    /// Largest notification read, longer ones are cut off
+    const MAX_MESSAGE: usize = 4096;
+
+    /// Bind the notification socket of a service inside of `tmp_dir`
+    ///
+    /// Only `owner` may send to it, or nimi itself if the service runs as nimi's user
+    pub async fn bind(tmp_dir: &Path, interval: Duration, owner: Option<Uid>) -> Result<Self> {
+        let template = tmp_dir.join("nimi-notify-XXXXXX");
+        let dir = task::spawn_blocking(move || mkdtemp(&template))
+            .await
+            .wrap_err("Failed to create notify socket directory")?
+            .wrap_err("Failed to create notify socket directory")?;
+        let path = dir.join("notify.sock");
+
+        let socket = match Self::bind_in(&dir, &path, owner).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir).await;
+                return Err(e).wrap_err_with(|| format!("Failed to bind notify socket {path:?}"));
+            }
+        };
+
+        Ok(Self {
+            socket,
+            dir,
+            interval,
+        })
+    }
+
+    async fn bind_in(dir: &Path, path: &Path, owner: Option<Uid>) -> io::Result<UnixDatagram> {
+        fs::set_permissions(dir, Permissions::from_mode(0o700)).await?;
+        let socket = UnixDatagram::bind(path)?;
+        fs::set_permissions(path, Permissions::from_mode(0o700)).await?;
+        if let Some(owner) = owner {
+            let (dir, path) = (dir.to_owned(), path.to_owned());
+            task::spawn_blocking(move || {
+                chown(&dir, Some(owner), None)?;
+                chown(&path, Some(owner), None)
+            })
+            .await??;
+        }
+        setsockopt(&socket, PassCred, &true)?;
+
+        Ok(socket)
+    }
+
+    /// Point the process spawned by `command` to the socket
+    ///
+    /// Also throws away pings a previous process of the service left unread
+    pub fn pass_to(&self, command: &mut Command) {
+        let mut buf = [0; Self::MAX_MESSAGE];
+        while self.socket.try_recv(&mut buf).is_ok() {}
+
+        command
+            .env(NOTIFY_SOCKET, self.dir.join("notify.sock"))
+            .env("WATCHDOG_USEC", self.interval.as_micros().to_string());
+    }
+
+    /// Resolve once the service goes `interval` without a ping or asks to be restarted
+    /// with `WATCHDOG=trigger`
+    ///
+    /// Only notifications sent from the process group `group` of the service count, and
+    /// only a `WATCHDOG=1` in them postpones the deadline
+    pub async fn missed_ping(&self, service: &str, group: Pid) {
+        let mut buf = [0; Self::MAX_MESSAGE];
+        let mut deadline = Instant::now() + self.interval;
+
+        loop {
+            let (read, sender) = match timeout_at(deadline, self.recv(&mut buf)).await {
+                Ok(Ok(received)) => received,
+                Ok(Err(e)) => {
+                    warn!(
+                        "Failed to read the notify socket of {service}, no longer watching it: {e}"
+                    );
+                    return std::future::pending().await;
+                }
+                Err(_) => return,
+            };
+            if !sender.is_some_and(|sender| getpgid(Some(sender)) == Ok(group)) {
+                debug!("Ignoring notification from {sender:?}, it isn't a process of {service}");
+                continue;
+            }
+
+            let message = String::from_utf8_lossy(&buf[..read]);
+            for line in message.lines() {
+                match line {
+                    "WATCHDOG=1" => deadline = Instant::now() + self.interval,
+                    "WATCHDOG=trigger" => {
+                        debug!("Service {service} triggered its watchdog");
+                        return;
+                    }
+                    _ => debug!("Ignoring notification {line:?} of {service}"),
+                }
+            }
+        }
+    }
+
+    /// Receive a notification into `buf`, along with the pid of its sender
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, Option<Pid>)> {
+        self.socket
+            .async_io(Interest::READABLE, || {
+                let mut iov = [IoSliceMut::new(buf)];
+                let mut cmsg = cmsg_space!(libc::ucred);
+                let msg = recvmsg::<()>(
+                    self.socket.as_raw_fd(),
+                    &mut iov,
+                    Some(&mut cmsg),
+                    MsgFlags::MSG_DONTWAIT,
+                )?;
+                let sender = msg.cmsgs().find_map(|cmsg| match cmsg {
+                    ControlMessageOwned::ScmCredentials(credentials) => {
+                        Some(Pid::from_raw(credentials.pid()))
+                    }
+                    _ => None,
+                });
+
+                Ok((msg.bytes, sender))
+            })
+            .await
+    }
+}
+
+impl Drop for WatchdogSocket {
+    /// Remove the socket along with its directory, nobody reads it once the service is gone
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            warn!(
+                "Failed to remove notify socket directory {:?}: {e}",
+                self.dir
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use nix::unistd::getpgrp;
+
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(200);
+
+    /// Send `message` to the socket every 50ms for a second
+    async fn ping(watchdog: &WatchdogSocket, message: &'static str) {
+        let path = watchdog.dir.join("notify.sock");
+        let sender = UnixDatagram::unbound().unwrap();
+        for _ in 0..20 {
+            sender.send_to(message.as_bytes(), &path).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn pings_of_the_service_keep_it_alive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let watchdog = WatchdogSocket::bind(tmp.path(), INTERVAL, None)
+            .await
+            .unwrap();
+
+        tokio::select! {
+            () = watchdog.missed_ping("test", getpgrp()) => panic!("watchdog fired"),
+            () = ping(&watchdog, "WATCHDOG=1") => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn pings_from_other_process_groups_are_ignored() {
+        let tmp = tempfile::tempdir().unwrap();
+        let watchdog = WatchdogSocket::bind(tmp.path(), INTERVAL, None)
+            .await
+            .unwrap();
+        let other = Pid::from_raw(getpgrp().as_raw() + 1);
+
+        tokio::select! {
+            () = watchdog.missed_ping("test", other) => {}
+            () = ping(&watchdog, "WATCHDOG=1") => panic!("foreign pings kept the watchdog alive"),
+        }
+    }
+
+    #[tokio::test]
+    async fn other_notifications_are_no_pings() {
+        let tmp = tempfile::tempdir().unwrap();
+        let watchdog = WatchdogSocket::bind(tmp.path(), INTERVAL, None)
+            .await
+            .unwrap();
+
+        tokio::select! {
+            () = watchdog.missed_ping("test", getpgrp()) => {}
+            () = ping(&watchdog, "STATUS=x") => panic!("other notifications kept the watchdog alive"),
+        }
+    }
+
+    #[tokio::test]
+    async fn the_service_can_trigger_its_watchdog() {
+        let tmp = tempfile::tempdir().unwrap();
+        let watchdog = WatchdogSocket::bind(tmp.path(), Duration::from_secs(30), None)
+            .await
+            .unwrap();
+
+        tokio::select! {
+            () = watchdog.missed_ping("test", getpgrp()) => {}
+            () = ping(&watchdog, "WATCHDOG=trigger") => panic!("trigger was ignored"),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_owner_can_reach_the_socket() {
+        let tmp = tempfile::tempdir().unwrap();
+        let owner = Uid::current();
+        let watchdog = WatchdogSocket::bind(tmp.path(), INTERVAL, Some(owner))
+            .await
+            .unwrap();
+        let dir = watchdog.dir.clone();
+
+        for path in [dir.clone(), dir.join("notify.sock")] {
+            let metadata = std::fs::metadata(path).unwrap();
+            assert_eq!(metadata.mode() & 0o777, 0o700);
+            assert_eq!(metadata.uid(), owner.as_raw());
+        }
+
+        drop(watchdog);
+        assert!(!dir.exists());
+    }
+}
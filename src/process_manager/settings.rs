@@ -3,15 +3,20 @@
 //! Holds data about the nix configurable settings for Nimi
 
 use serde_with::DurationMilliSeconds;
-use std::time::Duration;
+use std::{borrow::Cow, collections::HashMap, time::Duration};
 
+use nix::sys::signal::Signal;
+use schemars::{JsonSchema, Schema, SchemaGenerator};
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_with::serde_as;
 
+use crate::process_manager::service::{ArgV, Process};
+
 /// Settings Struct
 ///
 /// Process manager runtime settings for configuring things like restart behaviour
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
 pub struct Settings {
     /// The restart specific settings
     pub restart: Restart,
@@ -21,27 +26,229 @@ pub struct Settings {
 
     /// The logging specific settings
     pub logging: Logging,
+
+    /// The shutdown specific settings
+    pub shutdown: Shutdown,
+
+    /// How the remaining services are treated when one of them fails
+    #[serde(rename = "failurePolicy")]
+    pub failure_policy: FailurePolicy,
+
+    /// Variables of the environment of nimi passed on to every service
+    ///
+    /// Merged with the `passEnvironment` of each service. Services inherit the whole
+    /// environment when neither is set
+    #[serde(rename = "passEnvironment")]
+    pub pass_environment: Option<Vec<String>>,
+
+    /// Signals received by nimi which are relayed to every running service
+    #[serde(rename = "forwardSignals", with = "forward_signals")]
+    #[schemars(with = "Vec<String>")]
+    pub forward_signals: Vec<Signal>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            restart: Restart::default(),
+            startup: Startup::default(),
+            logging: Logging::default(),
+            shutdown: Shutdown::default(),
+            failure_policy: FailurePolicy::default(),
+            pass_environment: None,
+            forward_signals: vec![Signal::SIGUSR1, Signal::SIGUSR2],
+        }
+    }
+}
+
+impl Settings {
+    /// Variables of the environment of nimi passed on to `process`
+    ///
+    /// Combines `pass_environment` with the `passEnvironment` of the process, plus
+    /// `PATH` which is always passed on. `None` if the whole environment is inherited
+    pub fn passed_environment<'a>(&'a self, process: &'a Process) -> Option<Vec<&'a str>> {
+        let global = self.pass_environment.as_deref();
+        let service = process.pass_environment.as_deref();
+        if global.is_none() && service.is_none() {
+            return None;
+        }
+
+        Some(
+            global
+                .into_iter()
+                .chain(service)
+                .flatten()
+                .map(String::as_str)
+                .chain(["PATH"])
+                .collect(),
+        )
+    }
+}
+
+/// (De)serialization of `Settings::forward_signals` as a list of signal names
+mod forward_signals {
+    use std::str::FromStr;
+
+    use nix::sys::signal::Signal;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    /// Signals nimi handles itself or which can't be caught at all
+    const RESERVED: [Signal; 5] = [
+        Signal::SIGTERM,
+        Signal::SIGINT,
+        Signal::SIGCHLD,
+        Signal::SIGKILL,
+        Signal::SIGSTOP,
+    ];
+
+    pub fn serialize<S>(signals: &[Signal], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(signals.iter().map(|signal| signal.as_str()))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Signal>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|name| {
+                let signal = Signal::from_str(&name)
+                    .map_err(|_| D::Error::custom(format!("Unknown signal {name:?}")))?;
+                if RESERVED.contains(&signal) {
+                    return Err(D::Error::custom(format!("{name} can't be forwarded")));
+                }
+
+                Ok(signal)
+            })
+            .collect()
+    }
+}
+
+/// Failure Policy
+///
+/// Selects what happens to the other services when a service fails
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum FailurePolicy {
+    /// Stop every service as soon as one of them fails
+    #[default]
+    #[serde(rename = "stop-all")]
+    StopAll,
+
+    /// Log the failure and keep the other services running
+    #[serde(rename = "ignore")]
+    Ignore,
+
+    /// Feed any error into the restart policy of the failing service
+    /// instead of tearing down the other services
+    #[serde(rename = "restart-only")]
+    RestartOnly,
+}
+
+impl std::fmt::Display for FailurePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::StopAll => "stop-all",
+            Self::Ignore => "ignore",
+            Self::RestartOnly => "restart-only",
+        })
+    }
+}
+
+/// Shutdown Settings Struct
+///
+/// Configuration for how nimi stops services
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct Shutdown {
+    /// The amount of time (in milliseconds) to wait for a service
+    /// to exit gracefully before sending `SIGKILL`
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub timeout: Duration,
+
+    /// The amount of time (in milliseconds) to wait for a service to exit after
+    /// `SIGKILL`, before giving up on it and carrying on without it
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "killTimeout")]
+    pub kill_timeout: Duration,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            kill_timeout: Duration::from_secs(5),
+        }
+    }
 }
 
 /// Startup Settings Struct
 ///
 /// Configuration for how nimi gets started
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde_as]
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
 pub struct Startup {
     /// Binary to run on startup before starting services
     #[serde(rename = "runOnStartup")]
     pub run_on_startup: Option<String>,
+
+    /// Commands to run one after another on startup, after `run_on_startup`
+    pub commands: Vec<ArgV>,
+
+    /// Environment variables to set for the startup commands
+    pub environment: HashMap<String, String>,
+
+    /// If a non-zero exit of a startup command is logged instead of aborting nimi
+    #[serde(rename = "ignoreFailure")]
+    pub ignore_failure: bool,
+
+    /// The amount of time (in milliseconds) every service gets to be running and
+    /// ready, counted from when the services are started
+    ///
+    /// nimi shuts down with an error once it passes, no limit applies when unset
+    #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+    pub timeout: Option<Duration>,
 }
 
 /// Logging Settings Struct
 ///
 /// Configuration for how nimi prints logs
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Serialize)]
 pub struct Logging {
     /// The stringified path to the logs directory to use
     ///
     /// None if logs are disabled
     pub logs_dir: Option<String>,
+
+    /// If the stderr of services is merged into their stdout
+    pub combine_output: bool,
+
+    /// Lines of service output longer than this many bytes are truncated
+    pub max_line_length: usize,
+
+    /// Size based rotation of the log files
+    pub rotation: LogRotation,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        LoggingRaw::default().into()
+    }
+}
+
+impl From<LoggingRaw> for Logging {
+    fn from(raw: LoggingRaw) -> Self {
+        Self {
+            logs_dir: raw.enable.then_some(raw.logs_dir),
+            combine_output: raw.combine_output,
+            max_line_length: raw.max_line_length,
+            rotation: raw.rotation,
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for Logging {
@@ -49,18 +256,25 @@ impl<'de> Deserialize<'de> for Logging {
     where
         D: Deserializer<'de>,
     {
-        let raw = LoggingRaw::deserialize(deserializer)?;
+        LoggingRaw::deserialize(deserializer).map(Into::into)
+    }
+}
 
-        Ok(Logging {
-            logs_dir: raw.enable.then_some(raw.logs_dir),
-        })
+impl JsonSchema for Logging {
+    fn schema_name() -> Cow<'static, str> {
+        "Logging".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        LoggingRaw::json_schema(generator)
     }
 }
 
 /// Logging raw struct matching nix representation
 ///
 /// Configuration for how nimi prints logs
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
 struct LoggingRaw {
     /// If log files should be generated for the service
     pub enable: bool,
@@ -68,13 +282,63 @@ struct LoggingRaw {
     /// The stringified path to the logs directory to use
     #[serde(rename = "logsDir")]
     pub logs_dir: String,
+
+    /// If the stderr of services is merged into their stdout
+    #[serde(rename = "combineOutput")]
+    pub combine_output: bool,
+
+    /// Lines of service output longer than this many bytes are truncated
+    #[serde(rename = "maxLineLength")]
+    pub max_line_length: usize,
+
+    /// Size based rotation of the log files
+    pub rotation: LogRotation,
+}
+
+impl Default for LoggingRaw {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            logs_dir: "nimi_logs".to_owned(),
+            combine_output: false,
+            max_line_length: 64 * 1024,
+            rotation: LogRotation::default(),
+        }
+    }
+}
+
+/// Log Rotation Settings Struct
+///
+/// Configuration for when log files get rotated
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct LogRotation {
+    /// Size in bytes after which a log file is rotated
+    ///
+    /// None if log files are never rotated
+    #[serde(rename = "maxSize")]
+    pub max_size: Option<u64>,
+
+    /// Number of rotated log files to keep
+    #[serde(rename = "maxFiles")]
+    pub max_files: usize,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self {
+            max_size: None,
+            max_files: 5,
+        }
+    }
 }
 
 /// Restart Settings Struct
 ///
 /// Configuration for how nimi gets restarted
 #[serde_as]
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
 pub struct Restart {
     /// The mode to use for restarts
     pub mode: RestartMode,
@@ -86,15 +350,169 @@ pub struct Restart {
 
     /// The maximum amount of restarts in `RestartMode::UpToCount`
     pub count: usize,
+
+    /// Exponential backoff applied to the restart delay
+    ///
+    /// When unset the delay is always `time`
+    pub backoff: Option<Backoff>,
+
+    /// How long (in milliseconds) a service has to stay up before its
+    /// restart count and backoff are reset
+    ///
+    /// When unset the restart count is never reset
+    #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+    #[serde(rename = "successThreshold")]
+    pub success_threshold: Option<Duration>,
+
+    /// Limit on how often a service may be started within a time window
+    ///
+    /// When unset services are restarted regardless of how often they were started
+    #[serde(rename = "startLimit")]
+    pub start_limit: Option<StartLimit>,
+
+    /// When a restarting service is reported as crash-looping
+    ///
+    /// When unset services are never reported as crash-looping
+    #[serde(rename = "crashLoop")]
+    pub crash_loop: Option<CrashLoop>,
+}
+
+impl Default for Restart {
+    fn default() -> Self {
+        Self {
+            mode: RestartMode::default(),
+            time: Duration::from_secs(1),
+            count: 5,
+            backoff: None,
+            success_threshold: None,
+            start_limit: None,
+            crash_loop: None,
+        }
+    }
+}
+
+impl Restart {
+    /// Delay to wait before the restart with the given (zero based) attempt number
+    pub fn delay(&self, attempt: u32) -> Duration {
+        match &self.backoff {
+            Some(backoff) => backoff.delay(attempt),
+            None => self.time,
+        }
+    }
+}
+
+/// Start Limit Struct
+///
+/// Gives up on a service which was started more than `burst` times within `interval`
+#[serde_as]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct StartLimit {
+    /// The window (in milliseconds) in which starts are counted
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub interval: Duration,
+
+    /// The maximum amount of starts within `interval`
+    pub burst: usize,
+}
+
+impl Default for StartLimit {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            burst: 5,
+        }
+    }
+}
+
+/// Crash Loop Struct
+///
+/// Reports a service as crash-looping once it was restarted `restarts` times within
+/// `interval`
+#[serde_as]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CrashLoop {
+    /// The window (in milliseconds) in which restarts are counted
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub interval: Duration,
+
+    /// The amount of restarts within `interval` which count as a crash loop
+    pub restarts: usize,
+}
+
+impl Default for CrashLoop {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            restarts: 5,
+        }
+    }
+}
+
+/// Restart Backoff Struct
+///
+/// Grows the restart delay as `min(initial * multiplier^attempt, max)`
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct Backoff {
+    /// The delay (in milliseconds) before the first restart
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub initial: Duration,
+
+    /// The upper bound (in milliseconds) for the delay
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub max: Duration,
+
+    /// Factor the delay is multiplied by after every restart
+    pub multiplier: f64,
+
+    /// Fraction (between 0 and 1) of the delay to randomly subtract
+    ///
+    /// Spreads out restarts of services which crashed at the same time
+    pub jitter: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay to wait before the restart with the given (zero based) attempt number
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt).unwrap_or(i32::MAX);
+        let secs = (self.initial.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0) * Self::random_fraction();
+
+        Duration::try_from_secs_f64(secs * (1.0 - jitter)).unwrap_or(self.max)
+    }
+
+    /// Cheap source of randomness in the range `[0, 1)`, good enough for jitter
+    fn random_fraction() -> f64 {
+        use std::hash::{BuildHasher, RandomState};
+
+        let bits = RandomState::new().hash_one(std::time::SystemTime::now());
+
+        (bits >> 11) as f64 / (1u64 << 53) as f64
+    }
 }
 
 /// Restart Mode
 ///
 /// Selects how the processes get restarted on failure
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
 pub enum RestartMode {
     /// Don't restart, ever
-    #[default]
     #[serde(rename = "never")]
     Never,
 
@@ -102,7 +520,189 @@ pub enum RestartMode {
     #[serde(rename = "up-to-count")]
     UpToCount,
 
-    /// Restart every single time
+    /// Restart every time the process fails
+    #[default]
+    #[serde(rename = "on-failure")]
+    OnFailure,
+
+    /// Restart every single time, including after a successful exit
     #[serde(rename = "always")]
     Always,
 }
+
+impl std::fmt::Display for RestartMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Never => "never",
+            Self::UpToCount => "up-to-count",
+            Self::OnFailure => "on-failure",
+            Self::Always => "always",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_default_missing_fields() {
+        let settings: Settings = serde_json::from_str(r#"{"failurePolicy": "ignore"}"#).unwrap();
+
+        assert_eq!(settings.failure_policy, FailurePolicy::Ignore);
+        assert_eq!(settings.forward_signals, [Signal::SIGUSR1, Signal::SIGUSR2]);
+        assert!(settings.pass_environment.is_none());
+        assert!(matches!(settings.restart.mode, RestartMode::OnFailure));
+        assert_eq!(settings.shutdown.timeout, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn forward_signals_are_read_by_name() {
+        let settings: Settings =
+            serde_json::from_str(r#"{"forwardSignals": ["SIGHUP", "SIGWINCH"]}"#).unwrap();
+
+        assert_eq!(settings.forward_signals, [Signal::SIGHUP, Signal::SIGWINCH]);
+    }
+
+    #[test]
+    fn unknown_and_reserved_signals_are_not_forwarded() {
+        let err = |signals: &str| {
+            serde_json::from_str::<Settings>(&format!(r#"{{"forwardSignals": {signals}}}"#))
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert!(err(r#"["SIGNOPE"]"#).starts_with("Unknown signal \"SIGNOPE\""));
+        assert!(err(r#"["SIGTERM"]"#).starts_with("SIGTERM can't be forwarded"));
+        assert!(err(r#"["SIGKILL"]"#).starts_with("SIGKILL can't be forwarded"));
+    }
+
+    #[test]
+    fn shutdown_defaults_missing_fields() {
+        let shutdown: Shutdown = serde_json::from_str("{}").unwrap();
+
+        assert_eq!(shutdown.timeout, Duration::from_secs(10));
+        assert_eq!(shutdown.kill_timeout, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn shutdown_reads_timeout_in_milliseconds() {
+        let shutdown: Shutdown = serde_json::from_str(r#"{"timeout": 1500}"#).unwrap();
+
+        assert_eq!(shutdown.timeout, Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn startup_defaults_missing_fields() {
+        let startup: Startup = serde_json::from_str(r#"{"ignoreFailure": true}"#).unwrap();
+
+        assert!(startup.ignore_failure);
+        assert!(startup.run_on_startup.is_none());
+        assert!(startup.commands.is_empty());
+        assert!(startup.environment.is_empty());
+        assert_eq!(startup.timeout, None);
+    }
+
+    #[test]
+    fn logging_defaults_missing_fields() {
+        let logging: Logging = serde_json::from_str(r#"{"combineOutput": true}"#).unwrap();
+
+        assert!(logging.combine_output);
+        assert_eq!(logging.logs_dir, None);
+        assert_eq!(logging.max_line_length, 64 * 1024);
+    }
+
+    #[test]
+    fn logging_only_has_a_logs_dir_when_enabled() {
+        let logging: Logging = serde_json::from_str(r#"{"enable": true}"#).unwrap();
+
+        assert_eq!(logging.logs_dir.as_deref(), Some("nimi_logs"));
+    }
+
+    #[test]
+    fn log_rotation_defaults_missing_fields() {
+        let rotation: LogRotation = serde_json::from_str(r#"{"maxSize": 1024}"#).unwrap();
+
+        assert_eq!(rotation.max_size, Some(1024));
+        assert_eq!(rotation.max_files, 5);
+    }
+
+    #[test]
+    fn backoff_delays_grow_up_to_max() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.0,
+        };
+
+        let delays: Vec<_> = (0..6).map(|attempt| backoff.delay(attempt)).collect();
+
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis)
+        );
+    }
+
+    #[test]
+    fn backoff_jitter_only_shortens_the_delay() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(1),
+            multiplier: 1.0,
+            jitter: 0.5,
+        };
+
+        for attempt in 0..100 {
+            let delay = backoff.delay(attempt);
+            assert!(delay <= Duration::from_secs(1), "{delay:?}");
+            assert!(delay >= Duration::from_millis(500), "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn restart_delay_is_fixed_without_backoff() {
+        let restart: Restart =
+            serde_json::from_str(r#"{"mode": "always", "time": 250, "count": 5}"#).unwrap();
+
+        assert_eq!(restart.delay(0), Duration::from_millis(250));
+        assert_eq!(restart.delay(10), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn restart_defaults_missing_fields() {
+        let restart: Restart = serde_json::from_str(r#"{"startLimit": {}}"#).unwrap();
+
+        assert!(matches!(restart.mode, RestartMode::OnFailure));
+        assert_eq!(restart.time, Duration::from_secs(1));
+        assert_eq!(restart.count, 5);
+        assert!(restart.backoff.is_none());
+        assert!(restart.crash_loop.is_none());
+
+        let start_limit = restart.start_limit.unwrap();
+        assert_eq!(start_limit.interval, Duration::from_secs(10));
+        assert_eq!(start_limit.burst, 5);
+    }
+
+    #[test]
+    fn the_default_restart_mode_is_on_failure() {
+        assert!(matches!(RestartMode::default(), RestartMode::OnFailure));
+    }
+
+    #[test]
+    fn crash_loop_defaults_missing_fields() {
+        let crash_loop: CrashLoop = serde_json::from_str(r#"{"restarts": 3}"#).unwrap();
+
+        assert_eq!(crash_loop.interval, Duration::from_secs(60));
+        assert_eq!(crash_loop.restarts, 3);
+    }
+
+    #[test]
+    fn backoff_defaults_missing_fields() {
+        let backoff: Backoff = serde_json::from_str(r#"{"max": 5000}"#).unwrap();
+
+        assert_eq!(backoff.initial, Duration::from_millis(100));
+        assert_eq!(backoff.max, Duration::from_secs(5));
+        assert_eq!(backoff.multiplier, 2.0);
+    }
+}
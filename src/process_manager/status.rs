@@ -0,0 +1,563 @@
+//! Status Module
+//!
+//! Tracks the state of every service and serves it over HTTP for liveness and
+//! readiness probes and as Prometheus metrics
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    io,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use eyre::{Context, Report, Result};
+use jiff::Timestamp;
+use log::{debug, info};
+use serde::Serialize;
+use serde_with::{DisplayFromStr, serde_as};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::process_manager::supervisor::ServiceFailed;
+
+/// Lifecycle state of a service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceState {
+    /// Waiting on dependencies or running its `execStartPre` hooks
+    Starting,
+
+    /// The service process is running
+    Running,
+
+    /// Waiting for the restart delay to pass
+    Restarting,
+
+    /// Waiting for the restart delay to pass after restarting too often, see
+    /// `settings.restart.crashLoop`
+    #[serde(rename = "crash-looping")]
+    CrashLooping,
+
+    /// Stopped because of a failure
+    Failed,
+
+    /// Stopped without a failure
+    Stopped,
+}
+
+/// Status of a single service
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceStatus {
+    /// Name of the service
+    pub name: String,
+
+    /// Current state
+    pub state: ServiceState,
+
+    /// Number of times the service was restarted
+    pub restarts: usize,
+
+    /// When the service process was last spawned
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(rename = "startedAt")]
+    pub started_at: Option<Timestamp>,
+
+    /// Exit code of the last service process to exit, `128 + signal` if it was killed
+    #[serde(rename = "lastExitCode")]
+    pub last_exit_code: Option<i32>,
+
+    /// When the last service process exited
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(rename = "lastExitAt")]
+    pub last_exit_at: Option<Timestamp>,
+
+    /// Seconds the service processes ran for in total, as of the snapshot
+    #[serde(rename = "uptimeSeconds")]
+    pub uptime_seconds: f64,
+
+    /// When the service process was last spawned, while it is running
+    #[serde(skip)]
+    running_since: Option<Instant>,
+
+    /// How long the service processes ran before exiting
+    #[serde(skip)]
+    uptimes: Histogram,
+}
+
+impl ServiceStatus {
+    /// Add the current run to the uptimes, if the process is running
+    fn stop_running(&mut self) {
+        if let Some(since) = self.running_since.take() {
+            self.uptimes.observe(since.elapsed());
+        }
+    }
+}
+
+/// Histogram of durations in seconds, in the shape Prometheus expects
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Number of observations per entry of `BUCKETS`, not cumulative
+    buckets: [u64; Histogram::BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    /// Upper bounds of the buckets in seconds, from a second up to a day
+    const BUCKETS: [f64; 8] = [1.0, 10.0, 60.0, 300.0, 1800.0, 3600.0, 21600.0, 86400.0];
+
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(bucket) = Self::BUCKETS.iter().position(|bound| secs <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+/// Status of every service, shared between the service managers and the status server
+#[derive(Debug, Clone, Default)]
+pub struct StatusBoard(Arc<RwLock<BTreeMap<String, ServiceStatus>>>);
+
+impl StatusBoard {
+    /// Set the state of a service, adding it if it isn't known yet
+    pub fn set_state(&self, name: &str, state: ServiceState) {
+        self.update(name, |status| {
+            status.state = state;
+            if state == ServiceState::Running && status.running_since.is_none() {
+                status.running_since = Some(Instant::now());
+                status.started_at = Some(Timestamp::now());
+            }
+        });
+    }
+
+    /// Count a restart of a service and mark it as restarting or crash-looping
+    pub fn record_restart(&self, name: &str, crash_looping: bool) {
+        self.update(name, |status| {
+            status.state = if crash_looping {
+                ServiceState::CrashLooping
+            } else {
+                ServiceState::Restarting
+            };
+            status.restarts += 1;
+        });
+    }
+
+    /// Record that the process of a service exited with `exit_code`
+    ///
+    /// Ends the current run, even before the state moves on from running
+    pub fn record_exit(&self, name: &str, exit_code: Option<i32>) {
+        self.update(name, |status| {
+            status.stop_running();
+            status.last_exit_code = exit_code;
+            status.last_exit_at = Some(Timestamp::now());
+        });
+    }
+
+    /// Current state of a service
+    pub fn state(&self, name: &str) -> Option<ServiceState> {
+        Some(self.0.read().ok()?.get(name)?.state)
+    }
+
+    /// Status of every service, sorted by name
+    pub fn snapshot(&self) -> Vec<ServiceStatus> {
+        self.0
+            .read()
+            .map(|board| {
+                board
+                    .values()
+                    .map(|status| {
+                        let running = status.running_since.map(|since| since.elapsed());
+                        ServiceStatus {
+                            uptime_seconds: status.uptimes.sum
+                                + running.unwrap_or_default().as_secs_f64(),
+                            ..status.clone()
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Render the status of every service in the Prometheus text format
+    pub fn metrics(&self) -> String {
+        let statuses = self.snapshot();
+        let mut out = String::new();
+
+        out.push_str(
+            "# HELP nimi_service_restarts_total Number of times the service was restarted\n",
+        );
+        out.push_str("# TYPE nimi_service_restarts_total counter\n");
+        for status in &statuses {
+            let _ = writeln!(
+                out,
+                "nimi_service_restarts_total{{service=\"{}\"}} {}",
+                escape_label(&status.name),
+                status.restarts
+            );
+        }
+
+        out.push_str("# HELP nimi_service_up Whether the service process is running\n");
+        out.push_str("# TYPE nimi_service_up gauge\n");
+        for status in &statuses {
+            let _ = writeln!(
+                out,
+                "nimi_service_up{{service=\"{}\"}} {}",
+                escape_label(&status.name),
+                u8::from(status.state == ServiceState::Running)
+            );
+        }
+
+        out.push_str(
+            "# HELP nimi_service_uptime_seconds How long service processes ran before exiting\n",
+        );
+        out.push_str("# TYPE nimi_service_uptime_seconds histogram\n");
+        for status in &statuses {
+            let service = escape_label(&status.name);
+            let uptimes = &status.uptimes;
+
+            let mut cumulative = 0;
+            for (bound, count) in Histogram::BUCKETS.iter().zip(uptimes.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "nimi_service_uptime_seconds_bucket{{service=\"{service}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "nimi_service_uptime_seconds_bucket{{service=\"{service}\",le=\"+Inf\"}} {}",
+                uptimes.count
+            );
+            let _ = writeln!(
+                out,
+                "nimi_service_uptime_seconds_sum{{service=\"{service}\"}} {}",
+                uptimes.sum
+            );
+            let _ = writeln!(
+                out,
+                "nimi_service_uptime_seconds_count{{service=\"{service}\"}} {}",
+                uptimes.count
+            );
+        }
+
+        out
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut ServiceStatus)) {
+        if let Ok(mut board) = self.0.write() {
+            let status = board
+                .entry(name.to_owned())
+                .or_insert_with(|| ServiceStatus {
+                    name: name.to_owned(),
+                    state: ServiceState::Starting,
+                    restarts: 0,
+                    started_at: None,
+                    last_exit_code: None,
+                    last_exit_at: None,
+                    uptime_seconds: 0.0,
+                    running_since: None,
+                    uptimes: Histogram::default(),
+                });
+            f(status);
+
+            if status.state != ServiceState::Running {
+                status.stop_running();
+            }
+        }
+    }
+}
+
+/// How every service ended, printed as a single JSON record when nimi exits
+#[derive(Debug, Serialize)]
+pub struct ShutdownSummary {
+    /// Final status of every service, sorted by name
+    pub services: Vec<ServiceStatus>,
+
+    /// Service whose failure made nimi exit with an error
+    #[serde(rename = "failedService")]
+    pub failed_service: Option<String>,
+
+    /// Error nimi exited with
+    pub error: Option<String>,
+}
+
+impl ShutdownSummary {
+    /// Summarize the services on `board`, and `error` if nimi is exiting because of one
+    pub fn new(board: &StatusBoard, error: Option<&Report>) -> Self {
+        Self {
+            services: board.snapshot(),
+            failed_service: error
+                .and_then(|e| e.downcast_ref::<ServiceFailed>())
+                .map(|failed| failed.0.to_string()),
+            error: error.map(|e| format!("{e:#}")),
+        }
+    }
+
+    /// Write the summary to `out` as a single line of JSON
+    pub fn write_to(&self, mut out: impl io::Write) -> Result<()> {
+        serde_json::to_writer(&mut out, self).wrap_err("Failed to serialize shutdown summary")?;
+        writeln!(out).wrap_err("Failed to write shutdown summary")
+    }
+}
+
+/// Minimal HTTP server exposing the `StatusBoard`
+///
+/// - `GET /healthz` answers `200 OK` for as long as nimi is running
+/// - `GET /status` answers with a JSON list of the services and their state
+/// - `GET /metrics` answers with the Prometheus metrics of the services
+pub struct StatusServer {
+    listener: TcpListener,
+    board: StatusBoard,
+}
+
+impl StatusServer {
+    /// Maximum size of a request head, anything longer is rejected
+    const MAX_REQUEST: usize = 8 * 1024;
+
+    /// Time a client gets to send its request
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Bind the server to `addr`
+    pub async fn bind(addr: SocketAddr, board: StatusBoard) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .wrap_err_with(|| format!("Failed to bind status server to {addr}"))?;
+        info!("Serving status on http://{addr}");
+
+        Ok(Self { listener, board })
+    }
+
+    /// Accept connections until `cancel_tok` is cancelled
+    pub async fn serve(self, cancel_tok: CancellationToken) {
+        loop {
+            let stream = tokio::select! {
+                res = self.listener.accept() => match res {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        debug!("Failed to accept status connection: {e}");
+                        continue;
+                    }
+                },
+                _ = cancel_tok.cancelled() => break,
+            };
+
+            let board = self.board.clone();
+            tokio::spawn(async move {
+                if let Err(e) = Self::handle(stream, &board).await {
+                    debug!("Failed to answer status request: {e:#}");
+                }
+            });
+        }
+    }
+
+    async fn handle(mut stream: TcpStream, board: &StatusBoard) -> Result<()> {
+        let head = tokio::time::timeout(Self::REQUEST_TIMEOUT, Self::read_head(&mut stream))
+            .await
+            .wrap_err("Timed out reading request")??;
+
+        let mut parts = head.lines().next().unwrap_or_default().split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/healthz")) => Self::response("200 OK", "text/plain", "ok\n"),
+            (Some("GET"), Some("/status")) => Self::response(
+                "200 OK",
+                "application/json",
+                &serde_json::to_string(&board.snapshot())?,
+            ),
+            (Some("GET"), Some("/metrics")) => {
+                Self::response("200 OK", "text/plain; version=0.0.4", &board.metrics())
+            }
+            (Some("GET"), _) => Self::response("404 Not Found", "text/plain", "not found\n"),
+            _ => Self::response(
+                "405 Method Not Allowed",
+                "text/plain",
+                "method not allowed\n",
+            ),
+        };
+
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+
+        Ok(())
+    }
+
+    /// Read the request line and headers, the body of a request is never needed
+    async fn read_head(stream: &mut TcpStream) -> Result<String> {
+        let mut head = Vec::new();
+        let mut buf = [0; 1024];
+
+        while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = stream.read(&mut buf).await?;
+            eyre::ensure!(
+                read != 0,
+                "Connection closed before the request was complete"
+            );
+            head.extend_from_slice(&buf[..read]);
+            eyre::ensure!(head.len() <= Self::MAX_REQUEST, "Request is too large");
+        }
+
+        Ok(String::from_utf8_lossy(&head).into_owned())
+    }
+
+    fn response(status: &str, content_type: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+}
+
+/// Escape a label value for the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serve `board` on a free port and return its address
+    async fn serve(board: &StatusBoard) -> (SocketAddr, CancellationToken) {
+        let server = StatusServer::bind("127.0.0.1:0".parse().unwrap(), board.clone())
+            .await
+            .unwrap();
+        let addr = server.listener.local_addr().unwrap();
+        let cancel_tok = CancellationToken::new();
+        tokio::spawn(server.serve(cancel_tok.clone()));
+
+        (addr, cancel_tok)
+    }
+
+    /// Send `request` to the server at `addr` and return the whole response
+    async fn request(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        response
+    }
+
+    fn body(response: &str) -> &str {
+        response.split_once("\r\n\r\n").unwrap().1
+    }
+
+    #[tokio::test]
+    async fn healthz_answers_while_nimi_runs() {
+        let (addr, _cancel_tok) = serve(&StatusBoard::default()).await;
+
+        let response = request(addr, "GET /healthz HTTP/1.1\r\nHost: nimi\r\n\r\n").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(body(&response), "ok\n");
+    }
+
+    #[tokio::test]
+    async fn status_lists_the_services_with_their_state() {
+        let board = StatusBoard::default();
+        board.set_state("web", ServiceState::Running);
+        board.set_state("db", ServiceState::Starting);
+        board.record_restart("db", false);
+        let (addr, _cancel_tok) = serve(&board).await;
+
+        let response = request(addr, "GET /status HTTP/1.1\r\n\r\n").await;
+        let statuses: serde_json::Value = serde_json::from_str(body(&response)).unwrap();
+
+        assert_eq!(statuses[0]["name"], "db");
+        assert_eq!(statuses[0]["state"], "restarting");
+        assert_eq!(statuses[0]["restarts"], 1);
+        assert_eq!(statuses[1]["name"], "web");
+        assert_eq!(statuses[1]["state"], "running");
+        assert!(statuses[1]["startedAt"].is_string());
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_and_methods_are_rejected() {
+        let (addr, _cancel_tok) = serve(&StatusBoard::default()).await;
+
+        assert!(
+            request(addr, "GET /nope HTTP/1.1\r\n\r\n")
+                .await
+                .starts_with("HTTP/1.1 404 Not Found\r\n")
+        );
+        assert!(
+            request(addr, "POST /status HTTP/1.1\r\n\r\n")
+                .await
+                .starts_with("HTTP/1.1 405 Method Not Allowed\r\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_count_restarts_and_running_services() {
+        let board = StatusBoard::default();
+        board.set_state("web", ServiceState::Running);
+        board.record_exit("web", Some(1));
+        board.record_restart("web", false);
+        let (addr, _cancel_tok) = serve(&board).await;
+
+        let scrape = async || request(addr, "GET /metrics HTTP/1.1\r\n\r\n").await;
+        let metrics = scrape().await;
+        assert!(metrics.contains("nimi_service_restarts_total{service=\"web\"} 1\n"));
+        assert!(metrics.contains("nimi_service_up{service=\"web\"} 0\n"));
+        assert!(metrics.contains("nimi_service_uptime_seconds_count{service=\"web\"} 1\n"));
+
+        board.set_state("web", ServiceState::Running);
+        board.record_restart("web", true);
+        let metrics = scrape().await;
+        assert!(metrics.contains("nimi_service_restarts_total{service=\"web\"} 2\n"));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn exits_end_the_current_run() {
+        let board = StatusBoard::default();
+        assert!(board.snapshot().is_empty());
+
+        board.set_state("web", ServiceState::Running);
+        std::thread::sleep(Duration::from_millis(50));
+        let running = board.snapshot().remove(0);
+        assert!(running.started_at.is_some());
+        assert!(running.uptime_seconds >= 0.05);
+        assert!(running.last_exit_at.is_none());
+
+        board.record_exit("web", Some(137));
+        let exited = board.snapshot().remove(0);
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(exited.last_exit_code, Some(137));
+        assert!(exited.last_exit_at.is_some());
+        assert_eq!(
+            board.snapshot()[0].uptime_seconds,
+            exited.uptime_seconds,
+            "uptime kept growing after the exit"
+        );
+    }
+
+    #[test]
+    fn status_fields_are_serialized_in_camel_case() {
+        let board = StatusBoard::default();
+        board.set_state("web", ServiceState::Running);
+        board.record_exit("web", Some(1));
+
+        let status = serde_json::to_value(&board.snapshot()[0]).unwrap();
+
+        for field in ["startedAt", "lastExitCode", "lastExitAt", "uptimeSeconds"] {
+            assert!(status.get(field).is_some(), "{field} missing from {status}");
+        }
+        assert!(status["startedAt"].as_str().unwrap().ends_with('Z'));
+    }
+}
@@ -0,0 +1,683 @@
+//! Supervisor Module
+//!
+//! Keeps track of the running `ServiceManager` tasks so services can be added,
+//! removed or replaced while nimi is running
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use eyre::{Context, OptionExt, Result};
+use log::info;
+use nix::sys::signal::Signal;
+use thiserror::Error;
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    task::JoinSet,
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::process_manager::{
+    DependencyGraph, Service, ServiceManager, Settings,
+    control::ControlRequest,
+    service_manager::{ConfigDir, LogOutput, ServiceCommand, ServiceManagerOpts},
+    status::{ServiceState, StatusBoard},
+    watcher::PathWatcher,
+};
+
+/// Context of the error a service task ended with, naming the service
+#[derive(Debug, Error)]
+#[error("Failed to run service {0}")]
+pub struct ServiceFailed(pub Arc<String>);
+
+/// Used to initialize the Supervisor in a structured manner
+pub struct SupervisorOpts {
+    /// Directory to store logs in
+    pub logs_dir: Arc<Option<PathBuf>>,
+    /// How log lines get printed
+    pub log_output: LogOutput,
+    /// Temporary directory
+    pub tmp_dir: Arc<PathBuf>,
+
+    /// Process manager settings
+    pub settings: Arc<Settings>,
+
+    /// Signal which triggered the shutdown, forwarded to the services
+    pub shutdown_signal: Arc<OnceLock<Signal>>,
+
+    /// Signals received by nimi which are relayed to the services
+    pub forwarded_signals: broadcast::Sender<Signal>,
+
+    /// State of the services
+    pub status: StatusBoard,
+
+    /// Cancellation token for shutting down every service
+    pub cancel_tok: CancellationToken,
+
+    /// Cancelled when services should stop restarting and exit on their own
+    pub drain_tok: CancellationToken,
+
+    /// Where the watchers of `watchPaths` send their restart requests
+    pub requests: mpsc::Sender<ControlRequest>,
+}
+
+/// Handle to the task running a single `ServiceManager`
+struct ServiceHandle {
+    /// Serialized service definition, used to detect changes on reload
+    definition: serde_json::Value,
+
+    /// Stops only this service
+    cancel_tok: CancellationToken,
+
+    /// Cancelled once the task running the service has finished
+    finished: CancellationToken,
+
+    /// Readiness of the service
+    ready: watch::Receiver<bool>,
+
+    /// Commands for the `ServiceManager` of the service
+    commands: mpsc::Sender<ServiceCommand>,
+}
+
+/// Tokens for stopping the task of every service, by name
+///
+/// Shared with the service managers, so a service can stop the services it conflicts with
+#[derive(Debug, Clone, Default)]
+pub struct StopHandles(Arc<RwLock<HashMap<String, StopHandle>>>);
+
+/// Tokens for stopping the task of a single service
+#[derive(Debug, Clone)]
+pub struct StopHandle {
+    /// Stops only this service
+    pub cancel_tok: CancellationToken,
+
+    /// Cancelled once the task running the service has finished
+    pub finished: CancellationToken,
+
+    /// Services the service is ordered after
+    pub after: Arc<[String]>,
+}
+
+impl StopHandles {
+    /// Tokens of the current task of a service
+    pub fn get(&self, name: &str) -> Option<StopHandle> {
+        self.0.read().ok()?.get(name).cloned()
+    }
+
+    fn insert(&self, name: &str, handle: &ServiceHandle, after: Arc<[String]>) {
+        if let Ok(mut handles) = self.0.write() {
+            handles.insert(
+                name.to_owned(),
+                StopHandle {
+                    cancel_tok: handle.cancel_tok.clone(),
+                    finished: handle.finished.clone(),
+                    after,
+                },
+            );
+        }
+    }
+
+    fn remove(&self, name: &str) {
+        if let Ok(mut handles) = self.0.write() {
+            handles.remove(name);
+        }
+    }
+}
+
+/// Responsible for spawning and tracking the tasks running each service
+pub struct Supervisor {
+    opts: SupervisorOpts,
+
+    handles: HashMap<String, ServiceHandle>,
+    stop_handles: StopHandles,
+    join_set: JoinSet<Result<()>>,
+
+    /// Watchers of the `watchPaths` of services, with the paths they watch
+    ///
+    /// Kept apart from the handles as they outlive the tasks of their service
+    watchers: HashMap<String, (Vec<PathBuf>, DropGuard)>,
+}
+
+impl Supervisor {
+    /// Create a new supervisor without any running services
+    pub fn new(opts: SupervisorOpts) -> Self {
+        Self {
+            opts,
+            handles: HashMap::new(),
+            stop_handles: StopHandles::default(),
+            join_set: JoinSet::new(),
+            watchers: HashMap::new(),
+        }
+    }
+
+    /// Spawn a `ServiceManager` task for each of the given services
+    ///
+    /// A service already running under the same name is stopped first, its
+    /// replacement only starts once the old task has finished. The services
+    /// launch one after another in `DependencyGraph::start_order`
+    ///
+    /// The config directories of all services are created before any task is
+    /// spawned, so a broken config data entry leaves every service unstarted
+    pub async fn spawn(&mut self, services: HashMap<String, Service>) -> Result<()> {
+        let prepared = self.prepare(services).await?;
+
+        self.launch(prepared)
+    }
+
+    /// Create the config directories of `services`, in `DependencyGraph::start_order`
+    async fn prepare(
+        &self,
+        mut services: HashMap<String, Service>,
+    ) -> Result<Vec<(String, Service, ConfigDir)>> {
+        let mut prepared = Vec::with_capacity(services.len());
+        for name in DependencyGraph::start_order(&services) {
+            let service = &services[name];
+            let config_dir = ConfigDir::new(&self.opts.tmp_dir, service)
+                .await
+                .wrap_err_with(|| format!("Failed to create config directory of service {name}"))?;
+
+            prepared.push((name.to_owned(), config_dir));
+        }
+
+        Ok(prepared
+            .into_iter()
+            .filter_map(|(name, config_dir)| {
+                let service = services.remove(&name)?;
+                Some((name, service, config_dir))
+            })
+            .collect())
+    }
+
+    /// Spawn the tasks of services `prepare`d before, replacing those of the same name
+    fn launch(&mut self, prepared: Vec<(String, Service, ConfigDir)>) -> Result<()> {
+        let ready: HashMap<_, _> = prepared
+            .iter()
+            .map(|(name, _, _)| (name.clone(), watch::Sender::new(false)))
+            .collect();
+        let mut previous_launched = None;
+
+        for (name, service, config_dir) in prepared {
+            let dependencies = service
+                .after
+                .iter()
+                .filter_map(|dep| {
+                    let ready = match ready.get(dep) {
+                        Some(tx) => tx.subscribe(),
+                        None => self.handles.get(dep)?.ready.clone(),
+                    };
+
+                    Some((dep.clone(), ready))
+                })
+                .collect();
+            let ready = ready[&name].clone();
+            let (commands, commands_rx) = mpsc::channel(1);
+            let after = Arc::from(service.after.as_slice());
+
+            let handle = ServiceHandle {
+                definition: serde_json::to_value(&service)
+                    .wrap_err_with(|| format!("Failed to serialize service {name}"))?,
+                cancel_tok: self.opts.cancel_tok.child_token(),
+                finished: CancellationToken::new(),
+                ready: ready.subscribe(),
+                commands,
+            };
+
+            let previous = self.handles.remove(&name).map(|previous| {
+                previous.cancel_tok.cancel();
+                previous.finished
+            });
+
+            let opts = ServiceManagerOpts {
+                logs_dir: Arc::clone(&self.opts.logs_dir),
+                log_output: self.opts.log_output.clone(),
+                tmp_dir: Arc::clone(&self.opts.tmp_dir),
+
+                settings: Arc::clone(&self.opts.settings),
+                shutdown_signal: Arc::clone(&self.opts.shutdown_signal),
+                forwarded_signals: self.opts.forwarded_signals.clone(),
+                status: self.opts.status.clone(),
+
+                name: Arc::new(name.clone()),
+                service,
+                config_dir,
+                cancel_tok: handle.cancel_tok.clone(),
+                drain_tok: self.opts.drain_tok.clone(),
+                stop_handles: self.stop_handles.clone(),
+
+                launched: CancellationToken::new(),
+                previous_launched: previous_launched.take(),
+
+                ready,
+                dependencies,
+                commands: commands_rx,
+            };
+            previous_launched = Some(opts.launched.clone());
+            self.watch(&name, &opts.service.watch_paths);
+            let finished = handle.finished.clone();
+            let launched = opts.launched.clone();
+
+            self.join_set.spawn(async move {
+                let _finished = finished.drop_guard();
+                let _launched = launched.drop_guard();
+                if let Some(previous) = previous {
+                    previous.cancelled().await;
+                }
+
+                let name = Arc::clone(&opts.name);
+                let status = opts.status.clone();
+
+                let res = async move { ServiceManager::new(opts).await?.run().await }.await;
+                status.set_state(
+                    &name,
+                    match res {
+                        Ok(()) => ServiceState::Stopped,
+                        Err(_) => ServiceState::Failed,
+                    },
+                );
+
+                res.wrap_err(ServiceFailed(name))
+            });
+
+            self.stop_handles.insert(&name, &handle, after);
+            self.handles.insert(name, handle);
+        }
+
+        Ok(())
+    }
+
+    /// Watch the `paths` of service `name`, replacing its watcher if they changed
+    ///
+    /// The watcher keeps running while the service stays in the config, so it also
+    /// restarts a service which was stopped on request or failed to restart before
+    fn watch(&mut self, name: &str, paths: &[PathBuf]) {
+        if self
+            .watchers
+            .get(name)
+            .is_some_and(|(watched, _)| watched == paths)
+        {
+            return;
+        }
+        self.watchers.remove(name);
+        if paths.is_empty() {
+            return;
+        }
+
+        let cancel_tok = self.opts.cancel_tok.child_token();
+        let watcher = PathWatcher::new(paths.to_vec()).restart_on_change(
+            name.to_owned(),
+            self.opts.requests.clone(),
+            cancel_tok.clone(),
+        );
+        tokio::spawn(watcher);
+        self.watchers
+            .insert(name.to_owned(), (paths.to_vec(), cancel_tok.drop_guard()));
+    }
+
+    /// Reconcile the running services with a new set of service definitions
+    ///
+    /// - Services which are no longer defined are stopped
+    /// - Services which are newly defined are started
+    /// - Services whose definition changed are stopped and started again
+    /// - Services whose definition is unchanged are left alone
+    ///
+    /// Nothing is stopped if the config directory of a changed service can't be created
+    pub async fn reload(&mut self, services: HashMap<String, Service>) -> Result<()> {
+        DependencyGraph::new(&services).wrap_err("Invalid service dependencies")?;
+
+        let names: HashSet<_> = services.keys().cloned().collect();
+        let mut changed = HashMap::new();
+        for (name, service) in services {
+            let definition = serde_json::to_value(&service)
+                .wrap_err_with(|| format!("Failed to serialize service {name}"))?;
+
+            match self.handles.get(&name) {
+                Some(handle) if handle.definition == definition => continue,
+                Some(_) => info!("Restarting changed service {name}"),
+                None => info!("Starting added service {name}"),
+            }
+
+            changed.insert(name, service);
+        }
+        let prepared = self.prepare(changed).await?;
+
+        self.handles.retain(|name, handle| {
+            let keep = names.contains(name);
+            if !keep {
+                info!("Stopping removed service {name}");
+                handle.cancel_tok.cancel();
+                self.stop_handles.remove(name);
+                self.watchers.remove(name);
+            }
+
+            keep
+        });
+
+        self.launch(prepared)
+    }
+
+    /// Stop a single service and start it again with its current definition
+    pub async fn restart(&mut self, name: &str) -> Result<()> {
+        let handle = self
+            .handles
+            .get(name)
+            .ok_or_eyre(format!("Unknown service {name}"))?;
+        let service = serde_json::from_value(handle.definition.clone())
+            .wrap_err_with(|| format!("Failed to deserialize service {name}"))?;
+
+        info!("Restarting service {name} on request");
+        self.spawn(HashMap::from([(name.to_owned(), service)]))
+            .await
+    }
+
+    /// Stop a single service
+    ///
+    /// It stays known to the supervisor, so it can be started again with `restart`
+    pub fn stop(&self, name: &str) -> Result<()> {
+        let handle = self
+            .handles
+            .get(name)
+            .ok_or_eyre(format!("Unknown service {name}"))?;
+
+        info!("Stopping service {name} on request");
+        // A full channel already holds a `Stop`, a closed one means the task is done
+        let _ = handle.commands.try_send(ServiceCommand::Stop);
+
+        Ok(())
+    }
+
+    /// Future which resolves once every currently running service is ready
+    ///
+    /// Services which stop before becoming ready are skipped
+    pub fn wait_all_ready(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut ready: Vec<_> = self
+            .handles
+            .values()
+            .map(|handle| handle.ready.clone())
+            .collect();
+
+        async move {
+            for ready in &mut ready {
+                let _ = ready.wait_for(|ready| *ready).await;
+            }
+        }
+    }
+
+    /// Wait for the next service task to finish
+    ///
+    /// Returns `None` once no service tasks are left
+    pub async fn join_next(&mut self) -> Option<Result<()>> {
+        let res = self.join_set.join_next().await?;
+
+        Some(res.map_err(Into::into).and_then(std::convert::identity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::process_manager::control::ControlAction;
+
+    fn supervisor(tmp_dir: &Path) -> Supervisor {
+        Supervisor::new(SupervisorOpts {
+            logs_dir: Arc::new(None),
+            log_output: LogOutput::default(),
+            tmp_dir: Arc::new(tmp_dir.to_owned()),
+            settings: Arc::new(Settings::default()),
+            shutdown_signal: Arc::new(OnceLock::new()),
+            forwarded_signals: broadcast::Sender::new(4),
+            status: StatusBoard::default(),
+            cancel_tok: CancellationToken::new(),
+            drain_tok: CancellationToken::new(),
+            requests: mpsc::channel(1).0,
+        })
+    }
+
+    /// Service appending `line` to the `order` file in `tmp_dir`, after running `script`
+    fn recording_service(tmp_dir: &Path, line: &str, script: &str) -> serde_json::Value {
+        let order = tmp_dir.join("order");
+        json!({
+            "configData": {},
+            "process": {
+                "argv": ["sh", "-c", format!("{script}; echo {line} >> {}", order.display())],
+            },
+            "restart": { "mode": "never", "time": 10, "count": 0 },
+        })
+    }
+
+    fn recorded(tmp_dir: &Path) -> Vec<String> {
+        std::fs::read_to_string(tmp_dir.join("order"))
+            .map(|order| order.lines().map(str::to_owned).collect())
+            .unwrap_or_default()
+    }
+
+    fn services(services: serde_json::Value) -> HashMap<String, Service> {
+        serde_json::from_value(services).unwrap()
+    }
+
+    async fn join_all(supervisor: &mut Supervisor) {
+        timeout_secs(5, async {
+            while let Some(res) = supervisor.join_next().await {
+                res.unwrap();
+            }
+        })
+        .await;
+    }
+
+    async fn timeout_secs<F: Future>(secs: u64, future: F) -> F::Output {
+        tokio::time::timeout(Duration::from_secs(secs), future)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn spawned_services_run_through_their_service_manager() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut supervisor = supervisor(tmp.path());
+        let status = supervisor.opts.status.clone();
+        let mut web = recording_service(tmp.path(), "web", "true");
+        web["process"]["argv"][2] = json!(format!(
+            "ls ${{NIMI_CONFIG_DIR}} >> {}",
+            tmp.path().join("order").display()
+        ));
+        web["configData"] = json!({
+            "app": { "enable": true, "path": "app.conf", "text": "listen 80" },
+            "off": { "enable": false, "path": "off.conf", "text": "unused" },
+        });
+
+        supervisor
+            .spawn(services(json!({ "web": web })))
+            .await
+            .unwrap();
+        join_all(&mut supervisor).await;
+
+        assert_eq!(recorded(tmp.path()), ["app.conf"]);
+        assert_eq!(status.state("web"), Some(ServiceState::Stopped));
+    }
+
+    #[tokio::test]
+    async fn conflicts_waiting_to_restart_are_stopped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut supervisor = supervisor(tmp.path());
+        let status = supervisor.opts.status.clone();
+        let mut crashing = recording_service(tmp.path(), "crashed", "true");
+        crashing["process"]["argv"][2] = json!(format!(
+            "echo crashed >> {}; exit 1",
+            tmp.path().join("order").display()
+        ));
+        crashing["restart"] = json!({ "mode": "on-failure", "time": 30000 });
+
+        supervisor
+            .spawn(services(json!({ "crashing": crashing })))
+            .await
+            .unwrap();
+        timeout_secs(5, async {
+            while status.state("crashing") != Some(ServiceState::Restarting) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        let mut server = recording_service(tmp.path(), "server", "true");
+        server["conflicts"] = json!(["crashing"]);
+        supervisor
+            .spawn(services(json!({ "server": server })))
+            .await
+            .unwrap();
+
+        join_all(&mut supervisor).await;
+        assert_eq!(recorded(tmp.path()), ["crashed", "server"]);
+        assert_eq!(status.state("crashing"), Some(ServiceState::Stopped));
+    }
+
+    #[tokio::test]
+    async fn conflicts_ordered_after_the_service_are_left_to_start() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut supervisor = supervisor(tmp.path());
+        let mut migrate = recording_service(tmp.path(), "migrate", "sleep 0.2");
+        migrate["type"] = json!("oneshot");
+        migrate["conflicts"] = json!(["server"]);
+        let mut server = recording_service(tmp.path(), "server", "true");
+        server["after"] = json!(["migrate"]);
+
+        supervisor
+            .spawn(services(json!({ "migrate": migrate, "server": server })))
+            .await
+            .unwrap();
+
+        join_all(&mut supervisor).await;
+        assert_eq!(recorded(tmp.path()), ["migrate", "server"]);
+    }
+
+    #[tokio::test]
+    async fn services_launch_in_start_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut supervisor = supervisor(tmp.path());
+        let status = supervisor.opts.status.clone();
+        let services = services(json!({
+            "web": recording_service(tmp.path(), "web", "true"),
+            "db": recording_service(tmp.path(), "db", "true"),
+            "api": recording_service(tmp.path(), "api", "true"),
+            "cache": recording_service(tmp.path(), "cache", "true"),
+        }));
+
+        supervisor.spawn(services).await.unwrap();
+        join_all(&mut supervisor).await;
+
+        let mut started: Vec<_> = status
+            .snapshot()
+            .into_iter()
+            .map(|service| (service.started_at.unwrap(), service.name))
+            .collect();
+        started.sort();
+        let order: Vec<_> = started.into_iter().map(|(_, name)| name).collect();
+        assert_eq!(order, ["api", "cache", "db", "web"]);
+    }
+
+    #[tokio::test]
+    async fn a_broken_service_starts_nothing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut supervisor = supervisor(tmp.path());
+        let mut broken = recording_service(tmp.path(), "broken", "true");
+        broken["configData"] = json!({
+            "escape": { "enable": true, "path": "../escape", "text": "" },
+        });
+
+        let res = supervisor
+            .spawn(services(json!({
+                "web": recording_service(tmp.path(), "web", "true"),
+                "broken": broken,
+            })))
+            .await;
+
+        assert!(res.is_err());
+        assert!(supervisor.join_next().await.is_none());
+        assert_eq!(recorded(tmp.path()), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn a_broken_reload_leaves_the_running_services_alone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut supervisor = supervisor(tmp.path());
+        let status = supervisor.opts.status.clone();
+        let web = || recording_service(tmp.path(), "web", "sleep 30");
+        supervisor
+            .spawn(services(json!({ "web": web(), "old": web() })))
+            .await
+            .unwrap();
+        timeout_secs(5, async {
+            while [status.state("web"), status.state("old")] != [Some(ServiceState::Running); 2] {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        let mut changed = web();
+        changed["configData"] = json!({
+            "escape": { "enable": true, "path": "../escape", "text": "" },
+        });
+
+        let res = supervisor.reload(services(json!({ "web": changed }))).await;
+
+        assert!(res.is_err());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(300), supervisor.join_next())
+                .await
+                .is_err()
+        );
+        assert_eq!(status.state("web"), Some(ServiceState::Running));
+        assert_eq!(status.state("old"), Some(ServiceState::Running));
+        supervisor.opts.cancel_tok.cancel();
+        join_all(&mut supervisor).await;
+    }
+
+    #[tokio::test]
+    async fn watched_paths_keep_restarting_the_service_while_it_is_configured() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (requests, mut received) = mpsc::channel(1);
+        let mut supervisor = supervisor(tmp.path());
+        supervisor.opts.requests = requests;
+        let watched = tmp.path().join("watched");
+        std::fs::write(&watched, "1").unwrap();
+        let mut web = recording_service(tmp.path(), "web", "true");
+        web["watchPaths"] = json!([watched]);
+        supervisor
+            .spawn(services(json!({ "web": web })))
+            .await
+            .unwrap();
+        join_all(&mut supervisor).await;
+        let mut touch = {
+            let mut contents = String::from("1");
+            move || {
+                contents.push('1');
+                std::fs::write(&watched, &contents).unwrap();
+            }
+        };
+
+        // The service already completed, its watcher still starts it again
+        touch();
+        let request = timeout_secs(5, received.recv()).await.unwrap();
+        assert!(matches!(&request.action, ControlAction::Restart(name) if name == "web"));
+        let _ = request.reply.send(supervisor.restart("web").await);
+        join_all(&mut supervisor).await;
+        assert_eq!(recorded(tmp.path()), ["web", "web"]);
+
+        touch();
+        let request = timeout_secs(5, received.recv()).await.unwrap();
+        let _ = request.reply.send(Err(eyre::eyre!("Failed to restart")));
+        touch();
+        let request = timeout_secs(5, received.recv()).await.unwrap();
+        let _ = request.reply.send(Ok(()));
+
+        supervisor.reload(HashMap::new()).await.unwrap();
+        touch();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(1500), received.recv())
+                .await
+                .is_err()
+        );
+    }
+}
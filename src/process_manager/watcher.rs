@@ -0,0 +1,168 @@
+//! Watcher Module
+//!
+//! Notices changes to the `watchPaths` of services, so they can be restarted
+//!
+//! The paths are polled through `tokio::fs` instead of watched with inotify: an
+//! inotify watch sticks to the file a symlink pointed to when it was added, so a link
+//! switched to a new target, which is how NixOS swaps the files in `/etc`, would go
+//! unnoticed. Polling a handful of paths twice a second also needs no extra dependency
+
+use std::{
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use log::{info, warn};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+use crate::process_manager::control::{ControlAction, ControlRequest};
+
+/// What a path looked like when it was last polled
+///
+/// Follows symlinks, so pointing a link at another file counts as a change
+#[derive(Debug, PartialEq, Eq)]
+struct Stamp {
+    dev: u64,
+    ino: u64,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Stamp {
+    /// Stamp of `path`, `None` while it doesn't exist
+    async fn of(path: &Path) -> Option<Self> {
+        let meta = tokio::fs::metadata(path).await.ok()?;
+
+        Some(Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        })
+    }
+}
+
+/// Polls the watched paths of a single service
+pub struct PathWatcher {
+    paths: Vec<PathBuf>,
+    stamps: Vec<Option<Stamp>>,
+}
+
+impl PathWatcher {
+    /// How often the paths are checked
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    /// How long the paths have to stay unchanged before a change is reported
+    const DEBOUNCE: Duration = Duration::from_millis(500);
+
+    /// Watch `paths`, changes count from when `restart_on_change` first polls them
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            paths,
+            stamps: Vec::new(),
+        }
+    }
+
+    async fn poll(paths: &[PathBuf]) -> Vec<Option<Stamp>> {
+        let mut stamps = Vec::with_capacity(paths.len());
+        for path in paths {
+            stamps.push(Stamp::of(path).await);
+        }
+
+        stamps
+    }
+
+    /// Resolve once a path changed and then stayed unchanged for `DEBOUNCE`
+    ///
+    /// Returns the first path which changed
+    async fn changed(&mut self) -> PathBuf {
+        let changed = loop {
+            tokio::time::sleep(Self::POLL_INTERVAL).await;
+
+            let stamps = Self::poll(&self.paths).await;
+            let changed = self
+                .stamps
+                .iter()
+                .zip(&stamps)
+                .position(|(old, new)| old != new);
+            self.stamps = stamps;
+
+            if let Some(changed) = changed {
+                break self.paths[changed].clone();
+            }
+        };
+
+        // Editors and deployments often write a file in several steps
+        loop {
+            tokio::time::sleep(Self::DEBOUNCE).await;
+
+            let stamps = Self::poll(&self.paths).await;
+            if stamps == self.stamps {
+                return changed;
+            }
+            self.stamps = stamps;
+        }
+    }
+
+    /// Ask the process manager to restart `service` every time a watched path changes
+    ///
+    /// Watches until `cancel_tok` is cancelled or the process manager stops taking
+    /// requests, also after a restart failed
+    pub async fn restart_on_change(
+        mut self,
+        service: String,
+        requests: mpsc::Sender<ControlRequest>,
+        cancel_tok: CancellationToken,
+    ) {
+        self.stamps = Self::poll(&self.paths).await;
+        loop {
+            let changed = tokio::select! {
+                changed = self.changed() => changed,
+                _ = cancel_tok.cancelled() => return,
+            };
+            info!("Watched path {changed:?} of {service} changed, restarting it");
+
+            let (reply, outcome) = oneshot::channel();
+            let request = ControlRequest {
+                action: ControlAction::Restart(service.clone()),
+                reply,
+            };
+            if requests.send(request).await.is_err() {
+                return;
+            }
+            if let Ok(Err(e)) = outcome.await {
+                warn!("Failed to restart {service} after a watched path changed: {e:#}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn switching_a_symlink_counts_as_a_change() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (old, new, link) = (
+            tmp.path().join("old"),
+            tmp.path().join("new"),
+            tmp.path().join("link"),
+        );
+        std::fs::write(&old, "same").unwrap();
+        std::fs::write(&new, "same").unwrap();
+        std::os::unix::fs::symlink(&old, &link).unwrap();
+        let mut watcher = PathWatcher::new(vec![tmp.path().join("missing"), link.clone()]);
+        watcher.stamps = PathWatcher::poll(&watcher.paths).await;
+
+        std::fs::remove_file(&link).unwrap();
+        std::os::unix::fs::symlink(&new, &link).unwrap();
+
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed())
+            .await
+            .unwrap();
+        assert_eq!(changed, link);
+    }
+}
@@ -154,6 +154,7 @@ impl Subreaper {
 
     fn collect_children_from_proc() -> Result<HashSet<i32>> {
         let mut pids = HashSet::new();
+        let mut found_children_file = false;
         let task_dir =
             std::fs::read_dir("/proc/self/task").wrap_err("Failed to read /proc/self/task")?;
 
@@ -163,6 +164,7 @@ impl Subreaper {
             let Ok(contents) = std::fs::read_to_string(&children_path) else {
                 continue;
             };
+            found_children_file = true;
             for pid_str in contents.split_whitespace() {
                 if let Ok(pid) = pid_str.parse::<i32>() {
                     pids.insert(pid);
@@ -170,9 +172,51 @@ impl Subreaper {
             }
         }
 
+        if !found_children_file {
+            return Self::collect_children_from_proc_stat();
+        }
+
         Ok(pids)
     }
 
+    /// Fallback for kernels built without `CONFIG_PROC_CHILDREN`
+    ///
+    /// Scans every `/proc/<pid>/stat` entry for processes whose parent is nimi
+    fn collect_children_from_proc_stat() -> Result<HashSet<i32>> {
+        let own_pid = std::process::id() as i32;
+        let mut pids = HashSet::new();
+        let proc_dir = std::fs::read_dir("/proc").wrap_err("Failed to read /proc")?;
+
+        for entry in proc_dir {
+            let entry = entry.wrap_err("Failed to read /proc entry")?;
+            let Some(pid) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<i32>().ok())
+            else {
+                continue;
+            };
+            let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+                continue;
+            };
+
+            if Self::parent_pid(&stat) == Some(own_pid) {
+                pids.insert(pid);
+            }
+        }
+
+        Ok(pids)
+    }
+
+    /// Parent PID in the contents of a `/proc/<pid>/stat` file
+    fn parent_pid(stat: &str) -> Option<i32> {
+        // The command name is wrapped in parentheses and may itself contain
+        // spaces, so only parse the fields after the last `)`
+        stat.rsplit_once(')')
+            .and_then(|(_, rest)| rest.split_whitespace().nth(1))
+            .and_then(|ppid| ppid.parse::<i32>().ok())
+    }
+
     fn reap_orphaned_children() {
         let _guard = Self::reaper_guard()
             .lock()
@@ -227,3 +271,36 @@ impl Subreaper {
         Ok(())
     }
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_parent_pid_is_read_after_the_command_name() {
+        assert_eq!(
+            Subreaper::parent_pid("1234 (sleep) S 42 1234 1234 0 -1"),
+            Some(42)
+        );
+        assert_eq!(
+            Subreaper::parent_pid("1234 (a) b (c) d) S 42 1234 1234 0 -1"),
+            Some(42)
+        );
+        assert_eq!(Subreaper::parent_pid("1234 (sleep"), None);
+        assert_eq!(Subreaper::parent_pid("1234 (sleep) S"), None);
+    }
+
+    #[test]
+    fn children_are_found_through_their_stat_files() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+
+        let children = Subreaper::collect_children_from_proc_stat().unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert!(children.contains(&(child.id() as i32)));
+    }
+}